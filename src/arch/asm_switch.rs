@@ -1,5 +1,10 @@
+use crate::arch::fpu;
 use crate::arch::gdt::set_tss_rsp0;
-use crate::proc::scheduler::SCHEDULER;
+use crate::arch::pcid;
+use crate::arch::interrupts::INTERRUPT_COUNTS;
+use core::sync::atomic::Ordering;
+use crate::proc::process::ProcessState;
+use crate::proc::scheduler::{ProcessManager, IDLE_PID, SCHEDULER};
 
 #[repr(C)]
 #[derive(Debug, Clone, Copy)]
@@ -42,22 +47,27 @@ impl CpuState {
     }
 }
 
-pub unsafe fn switch_to_next(fallback: *mut CpuState) -> *mut CpuState {
-    let scheduler = SCHEDULER.get();
+/// Picks the next process and loads its kernel stack, FPU owner and
+/// address space. Returns the frame to resume, `fallback` if there is none.
+pub unsafe fn switch_to_next(scheduler: &mut ProcessManager, fallback: *mut CpuState) -> *mut CpuState {
+    let previous_pid = scheduler.current_pid;
 
     if let Some(next_pid) = scheduler.schedule() {
-        if let Some(next) = scheduler.processes.get(&next_pid) {
-            let cr3_addr = next.memory.page_table_addr;
-            let (current_cr3, _) = x86_64::registers::control::Cr3::read();
-
-            set_tss_rsp0(next.kernel_stack);
-
-            if current_cr3.start_address() != cr3_addr {
-                core::arch::asm!(
-                    "mov cr3, {}",
-                    in(reg) cr3_addr.as_u64(),
-                );
+        if let Some(previous_pid) = previous_pid.filter(|&pid| pid != next_pid) {
+            if let Some(previous) = scheduler.processes.get_mut(&previous_pid) {
+                // Still runnable means it was preempted or yielded
+                if previous.get_state() == ProcessState::Ready {
+                    previous.stats.involuntary_switches += 1;
+                } else {
+                    previous.stats.voluntary_switches += 1;
+                }
             }
+        }
+
+        if let Some(next) = scheduler.processes.get(&next_pid) {
+            set_tss_rsp0(next.kernel_stack_top());
+            fpu::switch_to(next_pid);
+            pcid::switch_to(next.memory.page_table_addr, next.memory.pcid);
             return next.saved_state;
         }
     }
@@ -67,23 +77,55 @@ pub unsafe fn switch_to_next(fallback: *mut CpuState) -> *mut CpuState {
 
 #[no_mangle]
 pub extern "C" fn switch_context(current_state: *mut CpuState) -> *mut CpuState {
+    // Before the scheduler is locked, so sockets can wake their sleepers
+    crate::net::poll();
+
     unsafe {
-        let scheduler = SCHEDULER.get();
+        let mut scheduler = SCHEDULER.lock();
+        let scheduler = &mut *scheduler;
+        scheduler.ticks += 1;
+        INTERRUPT_COUNTS.timer.fetch_add(1, Ordering::Relaxed);
 
         if let Some(current_pid) = scheduler.current_pid {
             if let Some(process) = scheduler.processes.get_mut(&current_pid) {
                 process.saved_state = current_state;
-                process.time += 1;
 
-                // Only switch if time slice expired
-                if process.time < 10 {
+                // Charge the tick to whichever ring it interrupted
+                if (*current_state).cs & 3 == 3 {
+                    process.stats.user_ticks += 1;
+                } else {
+                    process.stats.system_ticks += 1;
+                }
+
+                // Enforce RLIMIT_CPU; a fatal signal leaves nothing to resume
+                if let Some(signal) = process.limits.cpu_signal(&process.stats) {
+                    let _ = scheduler.send_signal(current_pid, signal);
+                }
+            }
+        }
+        scheduler.account_rt_tick();
+        scheduler.wake_expired();
+
+        if let Some(current_pid) = scheduler.current_pid {
+            if let Some(process) = scheduler.processes.get_mut(&current_pid) {
+                if process.get_state() != ProcessState::Running {
+                    // Stopped or killed from an interrupt handler, leave now
+                    process.time = 0;
+                } else if current_pid == IDLE_PID {
+                    scheduler.idle_ticks += 1;
+
+                    // Leave idle as soon as something became runnable
+                    if !scheduler.has_ready() {
+                        return current_state;
+                    }
+                } else if !scheduler.tick_preempts(current_pid) {
+                    // Slice left and nothing more important is ready
                     return current_state;
                 }
-                process.time = 0;
             }
         }
 
-        switch_to_next(current_state)
+        switch_to_next(scheduler, current_state)
     }
 }
 
@@ -0,0 +1,179 @@
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use core::arch::x86_64::__cpuid_count;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::xcontrol::{XCr0, XCr0Flags};
+
+use crate::proc::scheduler::SCHEDULER;
+
+/// Size of the legacy FXSAVE region.
+const FXSAVE_AREA_SIZE: usize = 512;
+/// XSAVE requires a 64-byte aligned area (FXSAVE only needs 16).
+const AREA_ALIGN: usize = 64;
+
+const NO_OWNER: u32 = u32::MAX;
+
+static XSAVE_ENABLED: AtomicBool = AtomicBool::new(false);
+static XSAVE_MASK: AtomicU64 = AtomicU64::new(0);
+static AREA_SIZE: AtomicUsize = AtomicUsize::new(FXSAVE_AREA_SIZE);
+
+/// PID whose x87/SSE/AVX state currently lives in the CPU registers.
+static FPU_OWNER: AtomicU32 = AtomicU32::new(NO_OWNER);
+
+/// Saved x87/SSE/AVX register state of a process.
+///
+/// Allocated the first time a process touches the FPU, so processes that
+/// never do floating point math don't pay for the save area.
+pub struct FpuState {
+    area: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for FpuState {}
+
+impl FpuState {
+    /// Creates a save area holding the power-on register state.
+    pub fn new() -> Self {
+        let layout = Layout::from_size_align(AREA_SIZE.load(Ordering::Relaxed), AREA_ALIGN)
+            .expect("invalid FPU save area layout");
+        let area = NonNull::new(unsafe { alloc_zeroed(layout) })
+            .expect("out of memory allocating FPU save area");
+
+        unsafe {
+            // FCW: all x87 exceptions masked, 64-bit precision.
+            area.as_ptr().cast::<u16>().write(0x037F);
+            // MXCSR: all SSE exceptions masked, round to nearest.
+            area.as_ptr().add(24).cast::<u32>().write(0x1F80);
+        }
+
+        FpuState { area, layout }
+    }
+
+    /// Stores the current register contents into this area.
+    pub unsafe fn save(&mut self) {
+        let area = self.area.as_ptr();
+        if XSAVE_ENABLED.load(Ordering::Relaxed) {
+            let mask = XSAVE_MASK.load(Ordering::Relaxed);
+            core::arch::asm!(
+                "xsave64 [{}]",
+                in(reg) area,
+                in("eax") mask as u32,
+                in("edx") (mask >> 32) as u32,
+                options(nostack),
+            );
+        } else {
+            core::arch::asm!("fxsave64 [{}]", in(reg) area, options(nostack));
+        }
+    }
+
+    /// Loads the register contents from this area.
+    pub unsafe fn restore(&self) {
+        let area = self.area.as_ptr();
+        if XSAVE_ENABLED.load(Ordering::Relaxed) {
+            let mask = XSAVE_MASK.load(Ordering::Relaxed);
+            core::arch::asm!(
+                "xrstor64 [{}]",
+                in(reg) area,
+                in("eax") mask as u32,
+                in("edx") (mask >> 32) as u32,
+                options(nostack, readonly),
+            );
+        } else {
+            core::arch::asm!("fxrstor64 [{}]", in(reg) area, options(nostack, readonly));
+        }
+    }
+}
+
+impl Drop for FpuState {
+    fn drop(&mut self) {
+        unsafe { dealloc(self.area.as_ptr(), self.layout) };
+    }
+}
+
+/// Enables x87/SSE (and AVX through XSAVE when the CPU has it) for all rings
+/// and arms lazy switching by setting CR0.TS.
+pub fn init() {
+    let features = __cpuid_count(1, 0);
+    let has_xsave = features.ecx & (1 << 26) != 0;
+    let has_avx = features.ecx & (1 << 28) != 0;
+
+    unsafe {
+        Cr0::update(|flags| {
+            flags.remove(Cr0Flags::EMULATE_COPROCESSOR);
+            flags.insert(Cr0Flags::MONITOR_COPROCESSOR | Cr0Flags::NUMERIC_ERROR);
+        });
+        Cr4::update(|flags| {
+            flags.insert(Cr4Flags::OSFXSR | Cr4Flags::OSXMMEXCPT_ENABLE);
+            if has_xsave {
+                flags.insert(Cr4Flags::OSXSAVE);
+            }
+        });
+
+        if has_xsave {
+            let mut xcr0 = XCr0Flags::X87 | XCr0Flags::SSE;
+            if has_avx {
+                xcr0 |= XCr0Flags::AVX;
+            }
+            XCr0::write(xcr0);
+
+            // EBX reports the save area size for the components now in XCR0.
+            let size = __cpuid_count(0xD, 0).ebx as usize;
+            XSAVE_MASK.store(xcr0.bits(), Ordering::Relaxed);
+            AREA_SIZE.store(size.max(FXSAVE_AREA_SIZE), Ordering::Relaxed);
+            XSAVE_ENABLED.store(true, Ordering::Relaxed);
+        }
+
+        core::arch::asm!("fninit", options(nomem, nostack));
+        Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED));
+    }
+}
+
+/// Called on every process switch. The registers are left alone and CR0.TS
+/// is set unless `next_pid` already owns them, so the first FPU instruction
+/// of the new process traps into `handle_device_not_available`.
+pub fn switch_to(next_pid: u32) {
+    unsafe {
+        if FPU_OWNER.load(Ordering::Relaxed) == next_pid {
+            core::arch::asm!("clts", options(nomem, nostack));
+        } else {
+            Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED));
+        }
+    }
+}
+
+/// Forgets `pid` as the register owner, e.g. when it terminates, so its
+/// stale state is never written back.
+pub fn release(pid: u32) {
+    let _ = FPU_OWNER.compare_exchange(pid, NO_OWNER, Ordering::Relaxed, Ordering::Relaxed);
+}
+
+/// #NM handler body: saves the previous owner's registers and loads the
+/// current process's, allocating its save area on first use.
+pub fn handle_device_not_available() {
+    unsafe {
+        core::arch::asm!("clts", options(nomem, nostack));
+
+        let mut scheduler = SCHEDULER.lock();
+        let current = match scheduler.current_pid {
+            Some(pid) => pid,
+            None => return,
+        };
+
+        let owner = FPU_OWNER.load(Ordering::Relaxed);
+        if owner == current {
+            return;
+        }
+
+        if let Some(previous) = scheduler.processes.get_mut(&owner) {
+            previous.fpu.get_or_insert_with(FpuState::new).save();
+        }
+
+        if let Some(process) = scheduler.processes.get_mut(&current) {
+            process.fpu.get_or_insert_with(FpuState::new).restore();
+        }
+
+        FPU_OWNER.store(current, Ordering::Relaxed);
+    }
+}
@@ -11,6 +11,27 @@ pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 // Static mutable TSS storage
 static mut TSS_STORAGE: TaskStateSegment = TaskStateSegment::new();
 
+/// Per-CPU data reached through `gs` after `swapgs` on the `syscall` path.
+///
+/// The field offsets are hard-coded in `syscall_fast_entry`, so the layout
+/// must not change without updating the assembly.
+#[repr(C)]
+pub struct CpuLocal {
+    /// Top of the current process's kernel stack, kept equal to TSS `rsp0`.
+    pub kernel_stack: u64,
+    /// Scratch slot for the user `rsp` while the entry stub switches stacks.
+    pub user_stack: u64,
+    pub user_cs: u64,
+    pub user_ss: u64,
+}
+
+pub static mut CPU_LOCAL: CpuLocal = CpuLocal {
+    kernel_stack: 0,
+    user_stack: 0,
+    user_cs: 0,
+    user_ss: 0,
+};
+
 lazy_static! {
     // Wrap the TSS in a Mutex for safe access
     pub static ref TSS: Mutex<&'static mut TaskStateSegment> = unsafe {
@@ -78,6 +99,14 @@ pub fn init() {
     }
 }
 
+pub fn kernel_code_selector() -> SegmentSelector {
+    GDT.1.code_selector
+}
+
+pub fn kernel_data_selector() -> SegmentSelector {
+    GDT.1.data_selector
+}
+
 pub fn user_code_selector() -> SegmentSelector {
     GDT.1.user_code_selector
 }
@@ -89,5 +118,6 @@ pub fn user_data_selector() -> SegmentSelector {
 pub fn set_tss_rsp0(stack_addr: VirtAddr) {
     unsafe {
         TSS_STORAGE.privilege_stack_table[0] = stack_addr;
+        CPU_LOCAL.kernel_stack = stack_addr.as_u64();
     }
 }
\ No newline at end of file
@@ -5,9 +5,12 @@ use x86_64::VirtAddr;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 use pc_keyboard::{DecodedKey, HandleControl, Keyboard, ScancodeSet1, layouts};
 use spin::Mutex;
+use core::sync::atomic::{AtomicU64, Ordering};
 use x86_64::registers::control::Cr2;
 use x86_64::instructions::port::Port;
 use crate::arch::gdt;
+use crate::proc::signal;
+use crate::proc::usercopy::{self, USER_SPACE_END};
 use crate::{println, hlt_loop};
 
 pub const PIC_1_OFFSET: u8 = 32;
@@ -38,6 +41,23 @@ impl InterruptIndex {
     }
 }
 
+/// Per-source interrupt counts, reported through `/proc/interrupts`.
+pub struct InterruptCounters {
+    pub timer: AtomicU64,
+    pub keyboard: AtomicU64,
+    pub syscall: AtomicU64,
+    pub page_fault: AtomicU64,
+    pub device_not_available: AtomicU64,
+}
+
+pub static INTERRUPT_COUNTS: InterruptCounters = InterruptCounters {
+    timer: AtomicU64::new(0),
+    keyboard: AtomicU64::new(0),
+    syscall: AtomicU64::new(0),
+    page_fault: AtomicU64::new(0),
+    device_not_available: AtomicU64::new(0),
+};
+
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
@@ -45,6 +65,7 @@ lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
         idt.breakpoint.set_handler_fn(breakpoint_handler);
+        idt.device_not_available.set_handler_fn(device_not_available_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
@@ -70,24 +91,100 @@ pub fn init_idt() {
 }
 
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    INTERRUPT_COUNTS.page_fault.fetch_add(1, Ordering::Relaxed);
+    // A fault while the scheduler is locked must still reach the report below
+    if !crate::proc::scheduler::SCHEDULER.is_locked() {
+        let mut scheduler = crate::proc::scheduler::SCHEDULER.lock();
+        let scheduler = &mut *scheduler;
+        if let Some(process) = scheduler.current_pid.and_then(|pid| scheduler.processes.get_mut(&pid)) {
+            process.stats.page_faults += 1;
+        }
+    }
+
+    if Cr2::read().as_u64() < USER_SPACE_END
+        && !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION)
+        && map_file_page(Cr2::read(), error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE))
+    {
+        return;
+    }
+
+    if error_code.contains(PageFaultErrorCode::USER_MODE) {
+        kill_faulting_process(Cr2::read(), error_code);
+    }
+
+    // A system call passed a bad pointer: its user copy fails instead
+    if Cr2::read().as_u64() < USER_SPACE_END {
+        if let Some(fixup) = usercopy::fixup(stack_frame.instruction_pointer.as_u64()) {
+            unsafe { stack_frame.as_mut().update(|frame| frame.instruction_pointer = VirtAddr::new(fixup)) };
+            return;
+        }
+    }
+
     println!("EXCEPTION: PAGE FAULT");
+    if Cr2::read().as_u64() < USER_SPACE_END && error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        if error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) {
+            println!("Kernel executed a user page (SMEP)");
+        } else {
+            println!("Kernel accessed a user page outside a user copy (SMAP)");
+        }
+    }
     println!("Accessed Address: {:?}", Cr2::read());
     println!("Error Code: {:?}", error_code);
     println!("{:#?}", stack_frame);
     hlt_loop();
 }
 
+/// Reads in the page of a mapped file the current process touched at
+/// `addr`, from its own code or a user copy in the kernel, returning
+/// whether the access can be retried.
+fn map_file_page(addr: VirtAddr, write: bool) -> bool {
+    if crate::proc::scheduler::SCHEDULER.is_locked() {
+        return false;
+    }
+    let mut scheduler = crate::proc::scheduler::SCHEDULER.lock();
+    let Some(pid) = scheduler.current_pid else {
+        return false;
+    };
+    scheduler.handle_file_fault(pid, addr, write).is_ok()
+}
+
+/// Terminates the process whose user code faulted at `addr`, e.g. writing
+/// its code or executing its stack. A fault can't be ignored like a signal:
+/// the instruction would only fault again. No lock is held in user mode, so
+/// the handler waits on the dead process's kernel stack for the timer, which
+/// switches away for good as the process is no longer running.
+fn kill_faulting_process(addr: VirtAddr, error_code: PageFaultErrorCode) -> ! {
+    {
+        let mut scheduler = crate::proc::scheduler::SCHEDULER.lock();
+        if let Some(pid) = scheduler.current_pid {
+            println!("process {} killed: segmentation fault at {:?} ({:?})", pid, addr, error_code);
+            scheduler.terminate_process(pid);
+        }
+    }
+    loop {
+        x86_64::instructions::interrupts::enable_and_hlt();
+    }
+}
+
 extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
     println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
 }
 
+extern "x86-interrupt" fn device_not_available_handler(_stack_frame: InterruptStackFrame) {
+    INTERRUPT_COUNTS.device_not_available.fetch_add(1, Ordering::Relaxed);
+    crate::arch::fpu::handle_device_not_available();
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    if let Some(pid) = crate::mem::kstack::overflowed(Cr2::read()) {
+        panic!("EXCEPTION: KERNEL STACK OVERFLOW in process {}\n{:#?}", pid, stack_frame);
+    }
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
@@ -97,10 +194,11 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
             Mutex::new(Keyboard::new(
                 ScancodeSet1::new(),
                 layouts::Us104Key,
-                HandleControl::Ignore
+                HandleControl::MapLettersToUnicode
             ));
     }
 
+    INTERRUPT_COUNTS.keyboard.fetch_add(1, Ordering::Relaxed);
     let mut keyboard = KEYBOARD.lock();
     let mut port = Port::new(0x60);
 
@@ -108,10 +206,14 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
         if let Some(key) = keyboard.process_keyevent(key_event) {
             match key {
+                // Ctrl-C and Ctrl-Z signal the foreground job instead of
+                // reaching its input
+                DecodedKey::Unicode('\u{3}') => signal::signal_foreground(signal::SIGINT),
+                DecodedKey::Unicode('\u{1a}') => signal::signal_foreground(signal::SIGTSTP),
                 DecodedKey::Unicode(character) => {
                     let mut bytes = [0u8; 4];
                     let s = character.encode_utf8(&mut bytes);
-                    let input = unsafe { crate::drivers::input::INPUT.get() };
+                    let mut input = crate::drivers::input::INPUT.lock();
                     for byte in s.bytes() {
                         input.push(byte);
                     }
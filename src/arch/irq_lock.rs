@@ -0,0 +1,82 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::instructions::interrupts;
+
+/// A spinlock that keeps interrupts disabled while it is held, so an
+/// interrupt handler can never observe, or wait on, a half-finished update.
+///
+/// There is a single CPU: with interrupts off nothing else can run, so
+/// finding the lock taken means its holder is further up our own call stack
+/// and spinning would hang forever. Debug builds panic with the lock's name
+/// instead.
+pub struct IrqLock<T> {
+    name: &'static str,
+    locked: AtomicBool,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: Send> Sync for IrqLock<T> {}
+unsafe impl<T: Send> Send for IrqLock<T> {}
+
+impl<T> IrqLock<T> {
+    pub const fn new(name: &'static str, data: T) -> Self {
+        IrqLock {
+            name,
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn lock(&self) -> IrqLockGuard<'_, T> {
+        let interrupts_were_enabled = interrupts::are_enabled();
+        interrupts::disable();
+
+        while self.locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            if cfg!(debug_assertions) {
+                panic!("re-entrant locking of {}", self.name);
+            }
+            core::hint::spin_loop();
+        }
+
+        IrqLockGuard { lock: self, interrupts_were_enabled }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.locked.load(Ordering::Relaxed)
+    }
+}
+
+/// Access to the data of a held `IrqLock`. Dropping it releases the lock and
+/// re-enables interrupts if they were enabled before.
+pub struct IrqLockGuard<'a, T> {
+    lock: &'a IrqLock<T>,
+    interrupts_were_enabled: bool,
+}
+
+impl<T> Deref for IrqLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<T> DerefMut for IrqLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+impl<T> Drop for IrqLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+        if self.interrupts_were_enabled {
+            interrupts::enable();
+        }
+    }
+}
@@ -1,3 +1,8 @@
 pub mod gdt;
 pub mod interrupts;
-pub mod asm_switch;
\ No newline at end of file
+pub mod asm_switch;
+pub mod fpu;
+pub mod irq_lock;
+pub mod pcid;
+pub mod protection;
+pub mod random;
\ No newline at end of file
@@ -0,0 +1,167 @@
+use alloc::vec::Vec;
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::{PhysAddr, VirtAddr};
+
+use crate::arch::irq_lock::IrqLock;
+
+/// Process-context identifiers the CPU tags TLB entries with, so loading
+/// CR3 need not flush them. 0 belongs to the kernel's own address space,
+/// and to every process when the CPU has no PCIDs.
+pub const PCID_COUNT: usize = 4096;
+
+/// CR3 bit asking the CPU to keep the new PCID's TLB entries.
+const CR3_NOFLUSH: u64 = 1 << 63;
+
+/// INVPCID types.
+const INVPCID_ADDRESS: u64 = 0;
+const INVPCID_ALL: u64 = 2;
+
+static PCID_ENABLED: AtomicBool = AtomicBool::new(false);
+static INVPCID_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Bit `n` set means PCID `n` may have stale TLB entries, so its next CR3
+/// load must flush them. Set where INVPCID can't reach an inactive one.
+static STALE: [AtomicU64; PCID_COUNT / 64] = [const { AtomicU64::new(0) }; PCID_COUNT / 64];
+
+struct PcidAllocator {
+    next: usize,
+    free: Vec<u16>,
+}
+
+static PCIDS: IrqLock<PcidAllocator> = IrqLock::new("PCID", PcidAllocator { next: 1, free: Vec::new() });
+
+/// Turns on CR4.PCIDE when the CPU has PCIDs. The active CR3 must carry
+/// PCID 0, as it does at boot.
+pub fn init() {
+    let has_pcid = __cpuid_count(1, 0).ecx & (1 << 17) != 0;
+    let has_invpcid = __cpuid_count(7, 0).ebx & (1 << 10) != 0;
+    if !has_pcid {
+        return;
+    }
+
+    unsafe { Cr4::update(|flags| flags.insert(Cr4Flags::PCID)) };
+    INVPCID_SUPPORTED.store(has_invpcid, Ordering::Relaxed);
+    PCID_ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    PCID_ENABLED.load(Ordering::Relaxed)
+}
+
+/// A PCID for a new address space, or 0 when there is none left, which
+/// shares the kernel's and is flushed on every switch like before PCIDs.
+pub fn allocate() -> u16 {
+    if !enabled() {
+        return 0;
+    }
+    let mut pcids = PCIDS.lock();
+    let pcid = match pcids.free.pop() {
+        Some(pcid) => pcid,
+        None if pcids.next < PCID_COUNT => {
+            pcids.next += 1;
+            (pcids.next - 1) as u16
+        }
+        None => return 0,
+    };
+    // Entries of a previous owner may be left
+    mark_stale(pcid);
+    pcid
+}
+
+/// Gives back the PCID of an address space no longer loaded.
+pub fn release(pcid: u16) {
+    if pcid != 0 {
+        PCIDS.lock().free.push(pcid);
+    }
+}
+
+fn mark_stale(pcid: u16) {
+    STALE[pcid as usize / 64].fetch_or(1 << (pcid % 64), Ordering::Relaxed);
+}
+
+fn take_stale(pcid: u16) -> bool {
+    let bit = 1 << (pcid % 64);
+    STALE[pcid as usize / 64].fetch_and(!bit, Ordering::Relaxed) & bit != 0
+}
+
+fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) value, options(nomem, nostack, preserves_flags)) };
+    value
+}
+
+/// Loads the address space `table` tagged `pcid`, keeping its TLB entries
+/// if `preserve` and the CPU has PCIDs.
+///
+/// # Safety
+/// `table` must map the running kernel.
+pub unsafe fn load(table: PhysAddr, pcid: u16, preserve: bool) {
+    let mut value = table.as_u64();
+    if enabled() {
+        value |= pcid as u64;
+        if preserve {
+            value |= CR3_NOFLUSH;
+        }
+    }
+    core::arch::asm!("mov cr3, {}", in(reg) value, options(nostack, preserves_flags));
+}
+
+/// Switches to the address space `table` tagged `pcid`. Its TLB entries
+/// survive unless a mapping changed while it was inactive.
+///
+/// # Safety
+/// `table` must map the running kernel.
+pub unsafe fn switch_to(table: PhysAddr, pcid: u16) {
+    let stale = enabled() && take_stale(pcid);
+    let current = read_cr3();
+    if !stale && current & !0xFFF == table.as_u64() && (current & 0xFFF) as u16 == pcid_of(pcid) {
+        return;
+    }
+    load(table, pcid, !stale);
+}
+
+/// The PCID CR3 actually carries for `pcid`: none without PCID support.
+fn pcid_of(pcid: u16) -> u16 {
+    if enabled() { pcid } else { 0 }
+}
+
+unsafe fn invpcid(kind: u64, pcid: u16, addr: VirtAddr) {
+    let descriptor: [u64; 2] = [pcid as u64, addr.as_u64()];
+    core::arch::asm!("invpcid {}, [{}]", in(reg) kind, in(reg) &descriptor, options(nostack, preserves_flags));
+}
+
+/// Invalidates the entry for `addr` in the address space tagged `pcid`,
+/// after a mapping there was removed or narrowed.
+pub fn flush_page(pcid: u16, addr: VirtAddr, active: bool) {
+    if active {
+        tlb::flush(addr);
+    } else if !enabled() {
+        // Loading its CR3 will flush everything anyway
+    } else if INVPCID_SUPPORTED.load(Ordering::Relaxed) {
+        unsafe { invpcid(INVPCID_ADDRESS, pcid, addr) };
+    } else {
+        mark_stale(pcid);
+    }
+}
+
+/// Invalidates every address space's entries, after a mapping shared by
+/// all of them was removed.
+pub fn flush_all() {
+    if !enabled() {
+        tlb::flush_all();
+    } else if INVPCID_SUPPORTED.load(Ordering::Relaxed) {
+        unsafe { invpcid(INVPCID_ALL, 0, VirtAddr::zero()) };
+    } else {
+        for stale in &STALE {
+            stale.store(u64::MAX, Ordering::Relaxed);
+        }
+        // Reloading CR3 without the no-flush bit clears the active PCID
+        let current = read_cr3();
+        unsafe { core::arch::asm!("mov cr3, {}", in(reg) current & !CR3_NOFLUSH, options(nostack, preserves_flags)) };
+        take_stale((current & 0xFFF) as u16);
+    }
+}
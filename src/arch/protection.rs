@@ -0,0 +1,85 @@
+use core::arch::x86_64::__cpuid_count;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::PageTableFlags;
+
+static NX_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMEP_ENABLED: AtomicBool = AtomicBool::new(false);
+static SMAP_ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns on whichever of these the CPU has: no-execute pages (EFER.NXE),
+/// and with SMEP and SMAP, faults when the kernel executes or touches
+/// user pages outside a `UserAccess`.
+pub fn init() {
+    let has_nx = __cpuid_count(0x8000_0001, 0).edx & (1 << 20) != 0;
+    let extended = __cpuid_count(7, 0).ebx;
+    let has_smep = extended & (1 << 7) != 0;
+    let has_smap = extended & (1 << 20) != 0;
+
+    unsafe {
+        if has_nx {
+            Efer::update(|flags| *flags |= EferFlags::NO_EXECUTE_ENABLE);
+        }
+        Cr4::update(|flags| {
+            if has_smep {
+                flags.insert(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION);
+            }
+            if has_smap {
+                flags.insert(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION);
+            }
+        });
+    }
+    NX_ENABLED.store(has_nx, Ordering::Relaxed);
+    SMEP_ENABLED.store(has_smep, Ordering::Relaxed);
+    SMAP_ENABLED.store(has_smap, Ordering::Relaxed);
+}
+
+pub fn nx_enabled() -> bool {
+    NX_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn smep_enabled() -> bool {
+    SMEP_ENABLED.load(Ordering::Relaxed)
+}
+
+pub fn smap_enabled() -> bool {
+    SMAP_ENABLED.load(Ordering::Relaxed)
+}
+
+/// `NO_EXECUTE`, or nothing when the CPU can't honour it: the bit is
+/// reserved then, and setting it faults.
+pub fn no_execute() -> PageTableFlags {
+    if nx_enabled() {
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
+
+/// Lets the kernel touch user pages under SMAP while it lives, by setting
+/// RFLAGS.AC. Only the user copy routines take one.
+pub struct UserAccess(());
+
+impl UserAccess {
+    pub fn begin() -> Self {
+        if smap_enabled() {
+            unsafe { core::arch::asm!("stac", options(nostack)) };
+        }
+        UserAccess(())
+    }
+}
+
+impl Drop for UserAccess {
+    fn drop(&mut self) {
+        end_user_access();
+    }
+}
+
+/// Clears RFLAGS.AC, e.g. one user space entered the kernel with.
+pub fn end_user_access() {
+    if smap_enabled() {
+        unsafe { core::arch::asm!("clac", options(nostack)) };
+    }
+}
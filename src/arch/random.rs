@@ -0,0 +1,55 @@
+use core::arch::x86_64::{__cpuid_count, _rdtsc};
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Weyl sequence increment of the fallback generator.
+const GOLDEN_GAMMA: u64 = 0x9E37_79B9_7F4A_7C15;
+
+static RDRAND_SUPPORTED: AtomicBool = AtomicBool::new(false);
+
+/// Fallback generator state, seeded from the time stamp counter.
+static STATE: AtomicU64 = AtomicU64::new(GOLDEN_GAMMA);
+
+/// Picks the kernel's entropy source: RDRAND when the CPU has it, else a
+/// generator seeded from the time stamp counter at boot.
+pub fn init() {
+    let has_rdrand = __cpuid_count(1, 0).ecx & (1 << 30) != 0;
+    RDRAND_SUPPORTED.store(has_rdrand, Ordering::Relaxed);
+    STATE.fetch_xor(unsafe { _rdtsc() }, Ordering::Relaxed);
+}
+
+/// RDRAND, which may come up empty for a while when drained.
+fn rdrand() -> Option<u64> {
+    for _ in 0..10 {
+        let value: u64;
+        let ok: u8;
+        unsafe {
+            core::arch::asm!("rdrand {}", "setc {}", out(reg) value, out(reg_byte) ok, options(nomem, nostack));
+        }
+        if ok != 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Unpredictable 64 bits. Without RDRAND the fallback also mixes in the
+/// time stamp counter on every draw, so the sequence depends on timing and
+/// not only on the boot seed.
+pub fn random_u64() -> u64 {
+    if RDRAND_SUPPORTED.load(Ordering::Relaxed) {
+        if let Some(value) = rdrand() {
+            return value;
+        }
+    }
+    // SplitMix64
+    let mut z = STATE.fetch_add(GOLDEN_GAMMA, Ordering::Relaxed).wrapping_add(GOLDEN_GAMMA) ^ unsafe { _rdtsc() };
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// Uniform enough below `bound`, which must not be zero; the bounds used
+/// are powers of two.
+pub fn random_below(bound: u64) -> u64 {
+    random_u64() % bound
+}
@@ -1,48 +1,39 @@
-use core::cell::UnsafeCell;
+use crate::arch::irq_lock::IrqLock;
+use crate::proc::wait::WaitQueue;
 
-pub struct InputCell(UnsafeCell<Input>);
-
-unsafe impl Sync for InputCell {}
-unsafe impl Send for InputCell {}
-
-impl InputCell {
-    const fn new() -> Self {
-        InputCell(UnsafeCell::new(Input {
-            buffer: [0; 256],
-            head: 0,
-            tail: 0,
-            waiting_pid: None,
-        }))
-    }
-
-    pub unsafe fn get(&self) -> &mut Input {
-        &mut *self.0.get()
-    }
-}
-
-pub static INPUT: InputCell = InputCell::new();
+pub static INPUT: IrqLock<Input> = IrqLock::new("INPUT", Input::new());
 
 pub struct Input {
     buffer: [u8; 256],
     head: usize,
     tail: usize,
-    pub waiting_pid: Option<u32>,
+    /// Processes polling for keyboard input.
+    pub waiters: WaitQueue,
+    /// Session the console belongs to, claimed by the first `setsid` caller.
+    pub session: Option<u32>,
+    /// Process group allowed to read the console and receive Ctrl-C/Ctrl-Z.
+    pub foreground_pgid: u32,
 }
 
 impl Input {
+    pub const fn new() -> Self {
+        Input {
+            buffer: [0; 256],
+            head: 0,
+            tail: 0,
+            waiters: WaitQueue::new(),
+            session: None,
+            foreground_pgid: 0,
+        }
+    }
+
     pub fn push(&mut self, byte: u8) {
         let next_head = (self.head + 1) % self.buffer.len();
         if next_head != self.tail {
             self.buffer[self.head] = byte;
             self.head = next_head;
 
-            if let Some(pid) = self.waiting_pid.take() {
-                let scheduler = unsafe { crate::proc::scheduler::SCHEDULER.get() };
-                if let Some(process) = scheduler.processes.get_mut(&pid) {
-                    process.set_state(crate::proc::process::ProcessState::Ready);
-                    scheduler.ready_queue.push_back(pid);
-                }
-            }
+            self.waiters.wake_all();
         }
     }
 
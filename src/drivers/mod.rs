@@ -1,3 +1,6 @@
 pub mod input;
+pub mod pci;
+pub mod pit;
 pub mod serial;
-pub mod vga_buffer;
\ No newline at end of file
+pub mod vga_buffer;
+pub mod virtio_net;
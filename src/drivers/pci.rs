@@ -0,0 +1,95 @@
+use x86_64::instructions::port::Port;
+
+/// Configuration mechanism #1 ports.
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+const COMMAND: u8 = 0x04;
+const COMMAND_IO_SPACE: u32 = 1 << 0;
+const COMMAND_BUS_MASTER: u32 = 1 << 2;
+
+/// One function of a device on the PCI bus.
+#[derive(Debug, Clone, Copy)]
+pub struct PciDevice {
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciDevice {
+    fn address(&self, offset: u8) -> u32 {
+        0x8000_0000
+            | (self.bus as u32) << 16
+            | (self.device as u32) << 11
+            | (self.function as u32) << 8
+            | (offset as u32 & 0xFC)
+    }
+
+    /// Reads the configuration dword at `offset`.
+    pub fn read(&self, offset: u8) -> u32 {
+        let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+        let mut data: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe {
+            address.write(self.address(offset));
+            data.read()
+        }
+    }
+
+    pub fn write(&self, offset: u8, value: u32) {
+        let mut address: Port<u32> = Port::new(CONFIG_ADDRESS);
+        let mut data: Port<u32> = Port::new(CONFIG_DATA);
+        unsafe {
+            address.write(self.address(offset));
+            data.write(value);
+        }
+    }
+
+    pub fn vendor_id(&self) -> u16 {
+        self.read(0x00) as u16
+    }
+
+    pub fn device_id(&self) -> u16 {
+        (self.read(0x00) >> 16) as u16
+    }
+
+    fn is_multifunction(&self) -> bool {
+        (self.read(0x0C) >> 16) & 0x80 != 0
+    }
+
+    /// The port base of BAR `index`, if it is an I/O BAR.
+    pub fn io_bar(&self, index: u8) -> Option<u16> {
+        let bar = self.read(0x10 + index * 4);
+        (bar & 1 == 1).then_some((bar & !0x3) as u16)
+    }
+
+    /// Lets the device answer I/O accesses and master the bus for DMA.
+    pub fn enable(&self) {
+        // The upper half is the status register, whose bits clear on write
+        let command = self.read(COMMAND) & 0xFFFF;
+        self.write(COMMAND, command | COMMAND_IO_SPACE | COMMAND_BUS_MASTER);
+    }
+}
+
+/// Finds the first function with the given vendor and device ids.
+pub fn find(vendor_id: u16, device_id: u16) -> Option<PciDevice> {
+    for bus in 0..=255u8 {
+        for device in 0..32u8 {
+            for function in 0..8u8 {
+                let candidate = PciDevice { bus, device, function };
+                if candidate.vendor_id() == 0xFFFF {
+                    if function == 0 {
+                        break;
+                    }
+                    continue;
+                }
+                if candidate.vendor_id() == vendor_id && candidate.device_id() == device_id {
+                    return Some(candidate);
+                }
+                if function == 0 && !candidate.is_multifunction() {
+                    break;
+                }
+            }
+        }
+    }
+    None
+}
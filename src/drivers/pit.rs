@@ -0,0 +1,22 @@
+use x86_64::instructions::port::Port;
+
+/// Frequency the timer interrupt is programmed to, i.e. clock ticks per second.
+pub const TIMER_HZ: u64 = 100;
+
+/// Input clock of the 8253/8254 programmable interval timer.
+const PIT_FREQUENCY: u64 = 1_193_182;
+
+/// Programs channel 0 as a rate generator firing `TIMER_HZ` times a second.
+pub fn init() {
+    let divisor = (PIT_FREQUENCY / TIMER_HZ) as u16;
+
+    let mut command: Port<u8> = Port::new(0x43);
+    let mut channel0: Port<u8> = Port::new(0x40);
+
+    unsafe {
+        // Channel 0, lobyte/hibyte access, mode 3 (square wave)
+        command.write(0x36);
+        channel0.write((divisor & 0xFF) as u8);
+        channel0.write((divisor >> 8) as u8);
+    }
+}
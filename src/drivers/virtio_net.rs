@@ -0,0 +1,263 @@
+use alloc::vec::Vec;
+use core::ptr;
+use core::sync::atomic::{fence, Ordering};
+
+use x86_64::instructions::port::Port;
+use x86_64::structures::paging::{FrameAllocator, PhysFrame};
+
+use crate::drivers::pci;
+use crate::mem::memory::{self, FRAME_ALLOCATOR};
+use crate::net::NetDevice;
+use crate::proc::errno::Errno;
+
+const VIRTIO_VENDOR_ID: u16 = 0x1AF4;
+/// The transitional virtio-net device, which QEMU offers with the legacy
+/// I/O port interface this driver uses.
+const VIRTIO_NET_DEVICE_ID: u16 = 0x1000;
+
+// Legacy registers, at offsets from the port base in BAR 0
+const REG_DEVICE_FEATURES: u16 = 0x00;
+const REG_GUEST_FEATURES: u16 = 0x04;
+const REG_QUEUE_ADDRESS: u16 = 0x08;
+const REG_QUEUE_SIZE: u16 = 0x0C;
+const REG_QUEUE_SELECT: u16 = 0x0E;
+const REG_QUEUE_NOTIFY: u16 = 0x10;
+const REG_STATUS: u16 = 0x12;
+const REG_MAC: u16 = 0x14;
+
+const STATUS_ACKNOWLEDGE: u8 = 1;
+const STATUS_DRIVER: u8 = 2;
+const STATUS_DRIVER_OK: u8 = 4;
+const STATUS_FAILED: u8 = 128;
+
+const FEATURE_MAC: u32 = 1 << 5;
+
+/// The buffer is written by the device.
+const DESC_F_WRITE: u16 = 2;
+
+const RX_QUEUE: u16 = 0;
+const TX_QUEUE: u16 = 1;
+/// Buffers posted for receiving, and kept for sending, one frame each.
+const BUFFERS: u16 = 16;
+/// `struct virtio_net_hdr`, without mergeable receive buffers.
+const NET_HDR_SIZE: usize = 10;
+
+/// Used when the device doesn't report one, QEMU's default.
+const DEFAULT_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+#[repr(C)]
+struct Descriptor {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+fn frame_ptr(frame: PhysFrame) -> *mut u8 {
+    let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+    (phys_mem_offset + frame.start_address().as_u64()) as *mut u8
+}
+
+/// A split virtqueue in the legacy layout: the descriptor table and the
+/// available ring, then the used ring on the next page boundary.
+struct Virtqueue {
+    size: u16,
+    base: *mut u8,
+    used_offset: usize,
+    /// Used ring entries handled so far.
+    last_used: u16,
+    /// The buffer behind each of the first `BUFFERS` descriptors.
+    buffers: Vec<PhysFrame>,
+}
+
+impl Virtqueue {
+    fn new(io_base: u16, index: u16) -> Result<Self, Errno> {
+        write_u16(io_base, REG_QUEUE_SELECT, index);
+        let size = read_u16(io_base, REG_QUEUE_SIZE);
+        if size < BUFFERS {
+            return Err(Errno::ENODEV);
+        }
+
+        let avail_end = 16 * size as usize + 6 + 2 * size as usize;
+        let used_offset = (avail_end + 4095) & !4095;
+        let pages = (used_offset + 6 + 8 * size as usize).div_ceil(4096);
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let first = allocator.allocate_contiguous(pages).ok_or(Errno::ENOMEM)?;
+        let mut buffers = Vec::with_capacity(BUFFERS as usize);
+        for _ in 0..BUFFERS {
+            buffers.push(allocator.allocate_frame().ok_or(Errno::ENOMEM)?);
+        }
+        drop(allocator);
+
+        let base = frame_ptr(first);
+        unsafe { ptr::write_bytes(base, 0, pages * 4096) };
+        write_u32(io_base, REG_QUEUE_ADDRESS, (first.start_address().as_u64() >> 12) as u32);
+
+        Ok(Virtqueue { size, base, used_offset, last_used: 0, buffers })
+    }
+
+    fn descriptor(&self, index: u16) -> *mut Descriptor {
+        unsafe { (self.base as *mut Descriptor).add(index as usize) }
+    }
+
+    fn avail_idx(&self) -> *mut u16 {
+        unsafe { self.base.add(16 * self.size as usize + 2) as *mut u16 }
+    }
+
+    fn used_idx(&self) -> *mut u16 {
+        unsafe { self.base.add(self.used_offset + 2) as *mut u16 }
+    }
+
+    /// Points descriptor `index` at its buffer, `len` bytes long.
+    fn set_descriptor(&mut self, index: u16, len: usize, flags: u16) {
+        let descriptor = Descriptor {
+            addr: self.buffers[index as usize].start_address().as_u64(),
+            len: len as u32,
+            flags,
+            next: 0,
+        };
+        unsafe { ptr::write_volatile(self.descriptor(index), descriptor) };
+    }
+
+    /// Hands descriptor `index` to the device.
+    fn push(&mut self, index: u16) {
+        unsafe {
+            let idx = ptr::read_volatile(self.avail_idx());
+            let ring = self.avail_idx().add(1);
+            ptr::write_volatile(ring.add((idx % self.size) as usize), index);
+            fence(Ordering::SeqCst);
+            ptr::write_volatile(self.avail_idx(), idx.wrapping_add(1));
+            fence(Ordering::SeqCst);
+        }
+    }
+
+    /// The next descriptor the device is done with, and the bytes it wrote.
+    fn pop_used(&mut self) -> Option<(u16, usize)> {
+        fence(Ordering::SeqCst);
+        if unsafe { ptr::read_volatile(self.used_idx()) } == self.last_used {
+            return None;
+        }
+        let elem = unsafe {
+            let ring = self.base.add(self.used_offset + 4) as *const UsedElem;
+            ptr::read_volatile(ring.add((self.last_used % self.size) as usize))
+        };
+        self.last_used = self.last_used.wrapping_add(1);
+        Some((elem.id as u16, elem.len as usize))
+    }
+}
+
+/// A virtio network card. It raises no interrupts the kernel listens to:
+/// the network stack polls it on every timer tick.
+pub struct VirtioNet {
+    io_base: u16,
+    mac: [u8; 6],
+    rx: Virtqueue,
+    tx: Virtqueue,
+    /// Transmit descriptors not in use by the device.
+    tx_free: Vec<u16>,
+}
+
+// The queues point into memory only this driver touches
+unsafe impl Send for VirtioNet {}
+
+fn read_u16(io_base: u16, reg: u16) -> u16 {
+    unsafe { Port::<u16>::new(io_base + reg).read() }
+}
+
+fn write_u16(io_base: u16, reg: u16, value: u16) {
+    unsafe { Port::<u16>::new(io_base + reg).write(value) }
+}
+
+fn write_u32(io_base: u16, reg: u16, value: u32) {
+    unsafe { Port::<u32>::new(io_base + reg).write(value) }
+}
+
+fn set_status(io_base: u16, status: u8) {
+    unsafe { Port::<u8>::new(io_base + REG_STATUS).write(status) }
+}
+
+/// Finds the card on the PCI bus and brings it up.
+pub fn init() -> Result<VirtioNet, Errno> {
+    let device = pci::find(VIRTIO_VENDOR_ID, VIRTIO_NET_DEVICE_ID).ok_or(Errno::ENODEV)?;
+    let io_base = device.io_bar(0).ok_or(Errno::ENODEV)?;
+    device.enable();
+
+    set_status(io_base, 0);
+    set_status(io_base, STATUS_ACKNOWLEDGE);
+    set_status(io_base, STATUS_ACKNOWLEDGE | STATUS_DRIVER);
+
+    let features = unsafe { Port::<u32>::new(io_base + REG_DEVICE_FEATURES).read() };
+    write_u32(io_base, REG_GUEST_FEATURES, features & FEATURE_MAC);
+
+    let mut mac = DEFAULT_MAC;
+    if features & FEATURE_MAC != 0 {
+        for (index, byte) in mac.iter_mut().enumerate() {
+            *byte = unsafe { Port::<u8>::new(io_base + REG_MAC + index as u16).read() };
+        }
+    }
+
+    let queues = Virtqueue::new(io_base, RX_QUEUE)
+        .and_then(|rx| Ok((rx, Virtqueue::new(io_base, TX_QUEUE)?)));
+    let (mut rx, tx) = match queues {
+        Ok(queues) => queues,
+        Err(err) => {
+            set_status(io_base, STATUS_FAILED);
+            return Err(err);
+        }
+    };
+
+    for index in 0..BUFFERS {
+        rx.set_descriptor(index, 4096, DESC_F_WRITE);
+        rx.push(index);
+    }
+    write_u16(io_base, REG_QUEUE_NOTIFY, RX_QUEUE);
+    set_status(io_base, STATUS_ACKNOWLEDGE | STATUS_DRIVER | STATUS_DRIVER_OK);
+
+    Ok(VirtioNet { io_base, mac, rx, tx, tx_free: (0..BUFFERS).collect() })
+}
+
+impl NetDevice for VirtioNet {
+    fn mac(&self) -> [u8; 6] {
+        self.mac
+    }
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Errno> {
+        if NET_HDR_SIZE + frame.len() > 4096 {
+            return Err(Errno::EMSGSIZE);
+        }
+        while let Some((index, _)) = self.tx.pop_used() {
+            self.tx_free.push(index);
+        }
+        let index = self.tx_free.pop().ok_or(Errno::EAGAIN)?;
+
+        let buffer = frame_ptr(self.tx.buffers[index as usize]);
+        unsafe {
+            ptr::write_bytes(buffer, 0, NET_HDR_SIZE);
+            ptr::copy_nonoverlapping(frame.as_ptr(), buffer.add(NET_HDR_SIZE), frame.len());
+        }
+        self.tx.set_descriptor(index, NET_HDR_SIZE + frame.len(), 0);
+        self.tx.push(index);
+        write_u16(self.io_base, REG_QUEUE_NOTIFY, TX_QUEUE);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Option<Vec<u8>> {
+        let (index, len) = self.rx.pop_used()?;
+        let len = len.clamp(NET_HDR_SIZE, 4096);
+        let buffer = frame_ptr(self.rx.buffers[index as usize]);
+        let frame = unsafe { core::slice::from_raw_parts(buffer.add(NET_HDR_SIZE), len - NET_HDR_SIZE) }.to_vec();
+
+        // Post the buffer again
+        self.rx.set_descriptor(index, 4096, DESC_F_WRITE);
+        self.rx.push(index);
+        write_u16(self.io_base, REG_QUEUE_NOTIFY, RX_QUEUE);
+        Some(frame)
+    }
+}
@@ -0,0 +1,60 @@
+use crate::drivers::input::INPUT;
+use crate::fs::poll::{POLLIN, POLLOUT};
+use crate::fs::vfs::File;
+use crate::proc::errno::Errno;
+use crate::proc::scheduler::SCHEDULER;
+use crate::proc::signal::SIGTTIN;
+
+/// The keyboard and VGA screen, installed as descriptors 0, 1 and 2.
+pub struct Console;
+
+impl File for Console {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut input = INPUT.lock();
+        let mut scheduler = SCHEDULER.lock();
+
+        // Background jobs are stopped when they try to read the terminal
+        if let Some(process) = scheduler.current_pid.and_then(|pid| scheduler.processes.get(&pid)) {
+            if input.session == Some(process.sid) && process.pgid != input.foreground_pgid {
+                if process.ignored_signals & (1 << SIGTTIN) != 0 {
+                    return Err(Errno::EIO);
+                }
+                let pgid = process.pgid;
+                scheduler.signal_group(pgid, SIGTTIN)?;
+                return Err(Errno::ERESTART);
+            }
+        }
+
+        let mut count = 0;
+
+        while count < buf.len() {
+            if let Some(byte) = input.pop() {
+                buf[count] = byte;
+                count += 1;
+            } else {
+                break;
+            }
+        }
+
+        Ok(count)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        if let Ok(s) = core::str::from_utf8(buf) {
+            crate::print!("{}", s);
+        }
+        Ok(buf.len())
+    }
+
+    fn poll(&self) -> u16 {
+        if INPUT.lock().is_empty() {
+            POLLOUT
+        } else {
+            POLLIN | POLLOUT
+        }
+    }
+
+    fn poll_wait(&self, pid: u32) {
+        INPUT.lock().waiters.add(pid);
+    }
+}
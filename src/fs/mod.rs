@@ -1 +1,6 @@
-pub mod ramfs;
\ No newline at end of file
+pub mod console;
+pub mod pipe;
+pub mod poll;
+pub mod procfs;
+pub mod ramfs;
+pub mod vfs;
\ No newline at end of file
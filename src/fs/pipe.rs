@@ -0,0 +1,181 @@
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::fs::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT};
+use crate::fs::vfs::File;
+use crate::proc::errno::Errno;
+use crate::proc::wait::WaitQueue;
+
+/// Bytes a pipe buffers before its writer blocks, one page like early Linux.
+pub const PIPE_CAPACITY: usize = 4096;
+
+struct PipeState {
+    buffer: VecDeque<u8>,
+    reader_open: bool,
+    writer_open: bool,
+    readers: WaitQueue,
+    writers: WaitQueue,
+}
+
+/// A bounded byte channel with blocking ends. Also carries each direction
+/// of a connected stream socket.
+pub struct Pipe {
+    state: IrqLock<PipeState>,
+}
+
+impl Pipe {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Pipe {
+            state: IrqLock::new("PIPE", PipeState {
+                buffer: VecDeque::with_capacity(PIPE_CAPACITY),
+                reader_open: true,
+                writer_open: true,
+                readers: WaitQueue::new(),
+                writers: WaitQueue::new(),
+            }),
+        })
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut state = self.state.lock();
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if state.buffer.is_empty() {
+            if !state.writer_open {
+                return Ok(0);
+            }
+            return Err(state.readers.sleep());
+        }
+
+        let count = buf.len().min(state.buffer.len());
+        for (dst, byte) in buf.iter_mut().zip(state.buffer.drain(..count)) {
+            *dst = byte;
+        }
+        state.writers.wake_all();
+        Ok(count)
+    }
+
+    /// Writes as much of `buf` as fits and returns the count. A restarted
+    /// write would repeat bytes already queued, so only a full pipe blocks.
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let mut state = self.state.lock();
+        if !state.reader_open {
+            return Err(Errno::EPIPE);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let count = buf.len().min(PIPE_CAPACITY - state.buffer.len());
+        if count == 0 {
+            return Err(state.writers.sleep());
+        }
+        state.buffer.extend(&buf[..count]);
+        state.readers.wake_all();
+        Ok(count)
+    }
+
+    /// Readable with data queued, and at end of file once the writer is gone.
+    pub fn read_events(&self) -> u16 {
+        let state = self.state.lock();
+        match (state.buffer.is_empty(), state.writer_open) {
+            (false, true) => POLLIN,
+            (false, false) => POLLIN | POLLHUP,
+            (true, true) => 0,
+            (true, false) => POLLHUP,
+        }
+    }
+
+    pub fn write_events(&self) -> u16 {
+        let state = self.state.lock();
+        if !state.reader_open {
+            POLLERR
+        } else if state.buffer.len() < PIPE_CAPACITY {
+            POLLOUT
+        } else {
+            0
+        }
+    }
+
+    pub fn wait_readable(&self, pid: u32) {
+        self.state.lock().readers.add(pid);
+    }
+
+    pub fn wait_writable(&self, pid: u32) {
+        self.state.lock().writers.add(pid);
+    }
+
+    /// Makes further writes fail with `EPIPE`.
+    pub fn close_reader(&self) {
+        let mut state = self.state.lock();
+        state.reader_open = false;
+        state.writers.wake_all();
+    }
+
+    /// Makes reads of the drained pipe return end of file.
+    pub fn close_writer(&self) {
+        let mut state = self.state.lock();
+        state.writer_open = false;
+        state.readers.wake_all();
+    }
+}
+
+/// The read end of a pipe. Dropping it, once the last descriptor sharing
+/// it is closed, makes further writes fail with `EPIPE`.
+pub struct PipeReader(Arc<Pipe>);
+
+/// The write end of a pipe. Dropping it makes reads of a drained pipe
+/// return end of file.
+pub struct PipeWriter(Arc<Pipe>);
+
+/// Creates a pipe and returns its read and write ends.
+pub fn pipe() -> (Arc<dyn File>, Arc<dyn File>) {
+    let pipe = Pipe::new();
+    (Arc::new(PipeReader(pipe.clone())), Arc::new(PipeWriter(pipe)))
+}
+
+impl File for PipeReader {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.0.read(buf)
+    }
+
+    fn poll(&self) -> u16 {
+        self.0.read_events()
+    }
+
+    fn poll_wait(&self, pid: u32) {
+        self.0.wait_readable(pid);
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        self.0.close_reader();
+    }
+}
+
+impl File for PipeWriter {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.0.write(buf)
+    }
+
+    fn poll(&self) -> u16 {
+        self.0.write_events()
+    }
+
+    fn poll_wait(&self, pid: u32) {
+        self.0.wait_writable(pid);
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.0.close_writer();
+    }
+}
@@ -0,0 +1,154 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::drivers::pit::TIMER_HZ;
+use crate::fs::vfs::{File, OpenFile};
+use crate::proc::errno::Errno;
+use crate::proc::process::ProcessState;
+use crate::proc::scheduler::SCHEDULER;
+
+/// Readiness bits, shared by `poll` and `epoll`.
+pub const POLLIN: u16 = 0x01;
+pub const POLLOUT: u16 = 0x04;
+pub const POLLERR: u16 = 0x08;
+pub const POLLHUP: u16 = 0x10;
+pub const POLLNVAL: u16 = 0x20;
+
+/// Reported whether asked for or not.
+const ALWAYS_REPORTED: u16 = POLLERR | POLLHUP | POLLNVAL;
+
+pub const EPOLL_CTL_ADD: u64 = 1;
+pub const EPOLL_CTL_DEL: u64 = 2;
+pub const EPOLL_CTL_MOD: u64 = 3;
+
+/// `struct pollfd`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct PollFd {
+    pub fd: i32,
+    pub events: i16,
+    pub revents: i16,
+}
+
+/// `struct epoll_event`, packed like on Linux x86_64.
+#[repr(C, packed)]
+#[derive(Debug, Clone, Copy)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// The events of `file` among `requested`.
+pub fn events(file: &OpenFile, requested: u16) -> u16 {
+    file.poll() & (requested | ALWAYS_REPORTED)
+}
+
+/// Puts the caller to sleep until one of `files` may have become ready or
+/// `timeout_ms` runs out, negative meaning never. The timeout counts from
+/// the first attempt: a restarted call keeps its deadline.
+///
+/// Returns `ERESTART` for the caller to pass up once asleep, `Ok` when the
+/// time is up.
+pub fn sleep(files: &[Arc<OpenFile>], timeout_ms: i64) -> Result<(), Errno> {
+    let mut scheduler = SCHEDULER.lock();
+    let now = scheduler.ticks;
+    let process = scheduler.current_process()?;
+
+    if timeout_ms >= 0 {
+        let ticks = (timeout_ms as u64 * TIMER_HZ).div_ceil(1000);
+        let deadline = *process.wake_at.get_or_insert(now + ticks);
+        if now >= deadline {
+            process.wake_at = None;
+            return Ok(());
+        }
+    }
+
+    let pid = process.get_pid();
+    process.set_state(ProcessState::Waiting);
+    drop(scheduler);
+
+    for file in files {
+        file.poll_wait(pid);
+    }
+    Err(Errno::ERESTART)
+}
+
+/// Clears the deadline `sleep` keeps across restarts, once the call returns.
+pub fn finish() {
+    if let Ok(process) = SCHEDULER.lock().current_process() {
+        process.wake_at = None;
+    }
+}
+
+struct Interest {
+    /// Entries of closed files go away on their own, as on Linux.
+    file: Weak<OpenFile>,
+    events: u32,
+    data: u64,
+}
+
+/// An `epoll` instance: an interest list kept in the kernel, so waiting on
+/// many descriptors doesn't mean passing them all in on every call.
+pub struct Epoll {
+    interest: IrqLock<BTreeMap<i32, Interest>>,
+}
+
+impl Epoll {
+    pub fn new() -> Arc<dyn File> {
+        Arc::new(Epoll { interest: IrqLock::new("EPOLL", BTreeMap::new()) })
+    }
+
+    /// `epoll_ctl`: adds, changes or removes descriptor `fd`.
+    pub fn control(&self, op: u64, fd: i32, file: &Arc<OpenFile>, event: EpollEvent) -> Result<(), Errno> {
+        let mut interest = self.interest.lock();
+        let present = interest.get(&fd)
+            .map_or(false, |entry| entry.file.upgrade().map_or(false, |known| Arc::ptr_eq(&known, file)));
+
+        match (op, present) {
+            (EPOLL_CTL_ADD, true) => Err(Errno::EEXIST),
+            (EPOLL_CTL_ADD, false) | (EPOLL_CTL_MOD, true) => {
+                interest.insert(fd, Interest { file: Arc::downgrade(file), events: event.events, data: event.data });
+                Ok(())
+            }
+            (EPOLL_CTL_DEL, true) => {
+                interest.remove(&fd);
+                Ok(())
+            }
+            (EPOLL_CTL_MOD | EPOLL_CTL_DEL, false) => Err(Errno::ENOENT),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    /// Up to `max` ready events, along with every file of the interest
+    /// list to sleep on when there are none.
+    pub fn ready(&self, max: usize) -> (Vec<EpollEvent>, Vec<Arc<OpenFile>>) {
+        let mut interest = self.interest.lock();
+        interest.retain(|_, entry| entry.file.strong_count() > 0);
+
+        let mut ready = Vec::new();
+        let mut files = Vec::new();
+        for entry in interest.values() {
+            let Some(file) = entry.file.upgrade() else {
+                continue;
+            };
+            let events = events(&file, entry.events as u16);
+            if events != 0 && ready.len() < max {
+                ready.push(EpollEvent { events: events as u32, data: entry.data });
+            }
+            files.push(file);
+        }
+        (ready, files)
+    }
+}
+
+impl File for Epoll {
+    fn read(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    fn epoll(&self) -> Result<&Epoll, Errno> {
+        Ok(self)
+    }
+}
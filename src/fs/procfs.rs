@@ -0,0 +1,338 @@
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::fmt::Write;
+use core::sync::atomic::Ordering;
+use spin::Mutex;
+
+use crate::arch::interrupts::INTERRUPT_COUNTS;
+use crate::drivers::pit::TIMER_HZ;
+use crate::fs::vfs::File;
+use crate::mem::{allocator, anon, memory, vmalloc};
+use crate::net::NET;
+use crate::proc::errno::Errno;
+use crate::proc::rlimit::{RLIMIT_AS, RLIMIT_CPU, RLIMIT_NOFILE, RLIMIT_NPROC, RLIM_INFINITY};
+use crate::proc::scheduler::{IDLE_PID, SCHEDULER};
+
+const GLOBAL_FILES: [&str; 6] = ["meminfo", "slabinfo", "uptime", "interrupts", "schedstat", "net"];
+const PROCESS_FILES: [&str; 7] = ["status", "state", "priority", "parent", "maps", "cmdline", "limits"];
+
+type Generator = Box<dyn Fn(&mut String) + Send + Sync>;
+
+/// A read-only `/proc` file. Nothing is stored: the text comes from the live
+/// kernel structures through `generate`.
+///
+/// It is rebuilt on every read at offset 0 and kept for the reads that
+/// follow, so a reader walking through the file sees one consistent snapshot.
+struct ProcFile {
+    generate: Generator,
+    content: Mutex<String>,
+}
+
+impl ProcFile {
+    fn new(generate: impl Fn(&mut String) + Send + Sync + 'static) -> Arc<dyn File> {
+        Arc::new(ProcFile {
+            generate: Box::new(generate),
+            content: Mutex::new(String::new()),
+        })
+    }
+}
+
+impl File for ProcFile {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        let mut content = self.content.lock();
+        if offset == 0 {
+            content.clear();
+            (self.generate)(&mut content);
+        }
+
+        let bytes = content.as_bytes();
+        if offset >= bytes.len() {
+            return Ok(0);
+        }
+        let count = buf.len().min(bytes.len() - offset);
+        buf[..count].copy_from_slice(&bytes[offset..offset + count]);
+        Ok(count)
+    }
+}
+
+/// Opens `path`, given relative to `/proc`.
+pub fn open(path: &str) -> Result<Arc<dyn File>, Errno> {
+    let path = path.trim_end_matches('/');
+    let (first, rest) = match path.split_once('/') {
+        Some((first, rest)) => (first, Some(rest)),
+        None => (path, None),
+    };
+
+    match (first, rest) {
+        ("", None) => Ok(ProcFile::new(root_dir)),
+        ("meminfo", None) => Ok(ProcFile::new(meminfo)),
+        ("slabinfo", None) => Ok(ProcFile::new(slabinfo)),
+        ("uptime", None) => Ok(ProcFile::new(uptime)),
+        ("interrupts", None) => Ok(ProcFile::new(interrupts)),
+        ("schedstat", None) => Ok(ProcFile::new(schedstat)),
+        ("net", None) => Ok(ProcFile::new(net)),
+        (pid, entry) => {
+            let pid = resolve_pid(pid)?;
+            match entry {
+                None => Ok(ProcFile::new(|out| {
+                    for name in PROCESS_FILES {
+                        let _ = writeln!(out, "{}", name);
+                    }
+                })),
+                Some("status") => Ok(ProcFile::new(move |out| status(pid, out))),
+                Some("state") => Ok(ProcFile::new(move |out| state(pid, out))),
+                Some("priority") => Ok(ProcFile::new(move |out| priority(pid, out))),
+                Some("parent") => Ok(ProcFile::new(move |out| parent(pid, out))),
+                Some("maps") => Ok(ProcFile::new(move |out| maps(pid, out))),
+                Some("cmdline") => Ok(ProcFile::new(move |out| cmdline(pid, out))),
+                Some("limits") => Ok(ProcFile::new(move |out| limits(pid, out))),
+                Some(_) => Err(Errno::ENOENT),
+            }
+        }
+    }
+}
+
+/// Maps a directory name to a live PID, `self` being the caller.
+fn resolve_pid(name: &str) -> Result<u32, Errno> {
+    let scheduler = SCHEDULER.lock();
+    let pid = match name {
+        "self" => scheduler.current_pid.ok_or(Errno::ENOENT)?,
+        "idle" => IDLE_PID,
+        _ => name.parse::<u32>().map_err(|_| Errno::ENOENT)?,
+    };
+
+    if scheduler.processes.contains_key(&pid) {
+        Ok(pid)
+    } else {
+        Err(Errno::ENOENT)
+    }
+}
+
+fn root_dir(out: &mut String) {
+    for name in GLOBAL_FILES {
+        let _ = writeln!(out, "{}", name);
+    }
+    let scheduler = SCHEDULER.lock();
+    for &pid in scheduler.processes.keys() {
+        if pid == IDLE_PID {
+            let _ = writeln!(out, "idle");
+        } else {
+            let _ = writeln!(out, "{}", pid);
+        }
+    }
+}
+
+fn meminfo(out: &mut String) {
+    let (total_frames, used_frames) = {
+        let frames = memory::FRAME_ALLOCATOR.lock();
+        (frames.total_frames(), frames.allocated_frames())
+    };
+    let heap = allocator::stats();
+
+    let _ = writeln!(out, "PhysTotal:  {:>8} kB", total_frames * 4);
+    let _ = writeln!(out, "PhysUsed:   {:>8} kB", used_frames * 4);
+    let _ = writeln!(out, "PhysFree:   {:>8} kB", total_frames.saturating_sub(used_frames) * 4);
+    let _ = writeln!(out, "AnonHugePages: {:>5} kB", anon::huge_pages() * 2048);
+    let _ = writeln!(out, "HeapTotal:  {:>8} kB", heap.size / 1024);
+    let _ = writeln!(out, "HeapUsed:   {:>8} kB", heap.used / 1024);
+    let _ = writeln!(out, "HeapFree:   {:>8} kB", heap.free / 1024);
+    let _ = writeln!(out, "HeapSlabCached: {:>5} kB", heap.cached / 1024);
+    let _ = writeln!(out, "HeapPeak:   {:>8} kB", heap.peak / 1024);
+    let _ = writeln!(out, "HeapLargestFree: {:>8} kB", heap.largest_free / 1024);
+    let _ = writeln!(out, "HeapFragmentation: {:>6} %", heap.fragmentation());
+    let _ = writeln!(out, "HeapAllocations: {:>9}", heap.allocations);
+    let _ = writeln!(out, "HeapFailures: {:>12}", heap.failures);
+    let vmalloc = vmalloc::stats();
+    let _ = writeln!(out, "VmallocTotal: {:>7} kB", vmalloc::VMALLOC_SIZE / 1024);
+    let _ = writeln!(out, "VmallocUsed: {:>8} kB", vmalloc.used / 1024);
+    let _ = writeln!(out, "VmallocAllocations: {:>6}", vmalloc.allocations);
+}
+
+/// One line per slab cache: block size, slabs, blocks in use and free.
+fn slabinfo(out: &mut String) {
+    let _ = writeln!(out, "size slabs in_use free");
+    for cache in allocator::slab_stats() {
+        let _ = writeln!(out, "{} {} {} {}", cache.block_size, cache.slabs, cache.in_use, cache.free);
+    }
+}
+
+fn write_seconds(out: &mut String, ticks: u64) {
+    let _ = write!(out, "{}.{:02}", ticks / TIMER_HZ, (ticks % TIMER_HZ) * 100 / TIMER_HZ);
+}
+
+/// Seconds since boot and seconds spent idle, like Linux.
+fn uptime(out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    write_seconds(out, scheduler.ticks);
+    out.push(' ');
+    write_seconds(out, scheduler.idle_ticks);
+    out.push('\n');
+}
+
+fn interrupts(out: &mut String) {
+    let counts = &INTERRUPT_COUNTS;
+    let rows = [
+        (7, &counts.device_not_available, "device not available"),
+        (14, &counts.page_fault, "page fault"),
+        (32, &counts.timer, "timer"),
+        (33, &counts.keyboard, "keyboard"),
+        (128, &counts.syscall, "syscall"),
+    ];
+    for (vector, count, name) in rows {
+        let _ = writeln!(out, "{:>4}: {:>10}  {}", vector, count.load(Ordering::Relaxed), name);
+    }
+}
+
+fn schedstat(out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    let switches: u64 = scheduler.processes.values()
+        .map(|process| process.stats.voluntary_switches + process.stats.involuntary_switches)
+        .sum();
+
+    let _ = writeln!(out, "ticks {}", scheduler.ticks);
+    let _ = writeln!(out, "idle_ticks {}", scheduler.idle_ticks);
+    let _ = writeln!(out, "cpu_usage {}%", scheduler.cpu_usage());
+    let _ = writeln!(out, "processes {}", scheduler.processes.len());
+    let rt_ready: usize = scheduler.rt_queues.values().map(|queue| queue.len()).sum();
+    let _ = writeln!(out, "ready {}", scheduler.ready_queue.len());
+    let _ = writeln!(out, "rt_ready {}", rt_ready);
+    let _ = writeln!(out, "rt_ticks {}", scheduler.rt_ticks);
+    let _ = writeln!(out, "rt_throttled {}", scheduler.rt_throttle_count);
+    let _ = writeln!(out, "context_switches {}", switches);
+}
+
+/// The interface's addresses and counters, then the ARP cache.
+fn net(out: &mut String) {
+    let net = NET.lock();
+    let iface = &net.iface;
+    let mac = iface.mac;
+    let _ = writeln!(
+        out,
+        "mac {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+        mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+    );
+    let _ = writeln!(out, "addr {}", iface.addr);
+    let _ = writeln!(out, "netmask {}", iface.netmask);
+    let _ = writeln!(out, "gateway {}", iface.gateway);
+    let _ = writeln!(out, "rx_frames {}", iface.stats.rx_frames);
+    let _ = writeln!(out, "tx_frames {}", iface.stats.tx_frames);
+    let _ = writeln!(out, "rx_dropped {}", iface.stats.rx_dropped);
+    let _ = writeln!(out, "tx_errors {}", iface.stats.tx_errors);
+    for (addr, mac) in iface.arp.entries() {
+        let _ = writeln!(
+            out,
+            "arp {} {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}",
+            addr, mac[0], mac[1], mac[2], mac[3], mac[4], mac[5]
+        );
+    }
+}
+
+fn status(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let stats = &process.stats;
+        let _ = writeln!(out, "Name: {}", process.cmdline);
+        let _ = writeln!(out, "State: {}", process.get_state_name());
+        let _ = writeln!(out, "Pid: {}", process.get_pid());
+        let _ = writeln!(out, "PPid: {}", process.parent_pid);
+        let _ = writeln!(out, "Pgid: {}", process.pgid);
+        let _ = writeln!(out, "Sid: {}", process.sid);
+        let _ = writeln!(out, "Priority: {}", process.priority);
+        let _ = writeln!(out, "Policy: {}", process.policy.name());
+        let _ = writeln!(out, "RtPriority: {}", process.rt_priority);
+        let _ = writeln!(out, "Pages: {}", process.memory.pages_allocated());
+        let _ = writeln!(out, "Messages: {}", process.mailbox.len());
+        let _ = writeln!(out, "UserTicks: {}", stats.user_ticks);
+        let _ = writeln!(out, "SystemTicks: {}", stats.system_ticks);
+        let _ = writeln!(out, "VoluntarySwitches: {}", stats.voluntary_switches);
+        let _ = writeln!(out, "InvoluntarySwitches: {}", stats.involuntary_switches);
+        let _ = writeln!(out, "PageFaults: {}", stats.page_faults);
+    }
+}
+
+fn state(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let _ = writeln!(out, "{}", process.get_state_name());
+    }
+}
+
+fn priority(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let _ = writeln!(out, "{}", process.priority);
+    }
+}
+
+fn parent(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let _ = writeln!(out, "{}", process.parent_pid);
+    }
+}
+
+fn maps(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        for region in process.memory.regions() {
+            let _ = writeln!(
+                out,
+                "{:016x}-{:016x} {} {:?}",
+                region.start.as_u64(),
+                region.end.as_u64(),
+                region_permissions(region.flags),
+                region.kind,
+            );
+        }
+    }
+}
+
+fn region_permissions(flags: x86_64::structures::paging::PageTableFlags) -> &'static str {
+    use x86_64::structures::paging::PageTableFlags as Flags;
+
+    let writable = flags.contains(Flags::WRITABLE);
+    let executable = !flags.contains(Flags::NO_EXECUTE);
+    match (writable, executable) {
+        (true, true) => "rwx",
+        (true, false) => "rw-",
+        (false, true) => "r-x",
+        (false, false) => "r--",
+    }
+}
+
+fn cmdline(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let _ = writeln!(out, "{}", process.cmdline);
+    }
+}
+
+fn write_limit(out: &mut String, value: u64) {
+    if value == RLIM_INFINITY {
+        let _ = write!(out, " {:>10}", "unlimited");
+    } else {
+        let _ = write!(out, " {:>10}", value);
+    }
+}
+
+fn limits(pid: u32, out: &mut String) {
+    let scheduler = SCHEDULER.lock();
+    if let Some(process) = scheduler.processes.get(&pid) {
+        let rows = [
+            (RLIMIT_CPU, "Max cpu time", "seconds"),
+            (RLIMIT_NPROC, "Max processes", "processes"),
+            (RLIMIT_NOFILE, "Max open files", "files"),
+            (RLIMIT_AS, "Max address space", "bytes"),
+        ];
+        let _ = writeln!(out, "{:<18} {:>10} {:>10} Units", "Limit", "Soft", "Hard");
+        for (resource, name, units) in rows {
+            if let Ok(limit) = process.limits.get(resource) {
+                let _ = write!(out, "{:<18}", name);
+                write_limit(out, limit.cur);
+                write_limit(out, limit.max);
+                let _ = writeln!(out, " {}", units);
+            }
+        }
+    }
+}
@@ -1,21 +1,8 @@
-use core::cell::UnsafeCell;
+use crate::arch::irq_lock::IrqLock;
+use crate::fs::vfs::File;
+use crate::proc::errno::Errno;
 
-pub struct RamFsCell(UnsafeCell<RamFs>);
-
-unsafe impl Sync for RamFsCell {}
-unsafe impl Send for RamFsCell {}
-
-impl RamFsCell {
-    const fn new() -> Self {
-        RamFsCell(UnsafeCell::new(RamFs::new()))
-    }
-
-    pub unsafe fn get(&self) -> &mut RamFs {
-        &mut *self.0.get()
-    }
-}
-
-pub static RAMFS: RamFsCell = RamFsCell::new();
+pub static RAMFS: IrqLock<RamFs> = IrqLock::new("RAMFS", RamFs::new());
 
 pub struct RamFs {
     entries: [Option<FileEntry>; 16],
@@ -36,6 +23,10 @@ impl FileEntry {
             data,
         }
     }
+
+    pub fn name_str(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.name_len]).unwrap_or("")
+    }
 }
 
 impl RamFs {
@@ -65,4 +56,35 @@ impl RamFs {
         }
         None
     }
-}
\ No newline at end of file
+
+    /// Iterates over every stored file.
+    pub fn entries(&self) -> impl Iterator<Item = &FileEntry> {
+        self.entries.iter().flatten()
+    }
+}
+
+/// An open ramfs file. The contents are read-only.
+pub struct RamFile {
+    data: &'static [u8],
+}
+
+impl RamFile {
+    pub fn new(data: &'static [u8]) -> Self {
+        RamFile { data }
+    }
+}
+
+impl File for RamFile {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        if offset >= self.data.len() {
+            return Ok(0);
+        }
+        let count = buf.len().min(self.data.len() - offset);
+        buf[..count].copy_from_slice(&self.data[offset..offset + count]);
+        Ok(count)
+    }
+
+    fn size(&self) -> Result<usize, Errno> {
+        Ok(self.data.len())
+    }
+}
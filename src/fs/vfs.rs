@@ -0,0 +1,205 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::fs::console::Console;
+use crate::fs::poll::{Epoll, POLLIN, POLLOUT};
+use crate::fs::procfs;
+use crate::fs::ramfs::{RamFile, RAMFS};
+use crate::mem::shm::SharedMemory;
+use crate::net::unix;
+use crate::net::Socket;
+use crate::proc::errno::Errno;
+
+/// Most descriptors a single process may have open.
+pub const MAX_FILES: usize = 32;
+
+/// Anything that can sit behind a file descriptor.
+///
+/// Stream-like files (the console, pipes, sockets) simply ignore
+/// `offset`.
+pub trait File: Send + Sync {
+    /// Reads into `buf` starting at `offset`. Returns the number of bytes
+    /// read, with 0 meaning end of file.
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno>;
+
+    fn write(&self, _offset: usize, _buf: &[u8]) -> Result<usize, Errno> {
+        Err(Errno::EBADF)
+    }
+
+    /// Sets the size of the file, as `ftruncate` does.
+    fn truncate(&self, _size: usize) -> Result<(), Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// The memory a shared `mmap` of the file maps.
+    fn mmap(&self) -> Result<Arc<SharedMemory>, Errno> {
+        Err(Errno::ENODEV)
+    }
+
+    /// Size in bytes of a file `mmap` can map page by page.
+    fn size(&self) -> Result<usize, Errno> {
+        Err(Errno::ENODEV)
+    }
+
+    /// Identifies the contents behind this open of the file, so shared
+    /// `mmap`s made through different opens use the same pages. Without
+    /// one, only mappings of the same open file do.
+    fn cache_key(&self) -> Option<usize> {
+        None
+    }
+
+    /// Current `POLL*` readiness. Files that never block are always ready.
+    fn poll(&self) -> u16 {
+        POLLIN | POLLOUT
+    }
+
+    /// Arranges for `pid` to be woken when the readiness may change.
+    fn poll_wait(&self, _pid: u32) {}
+
+    /// The instance behind an `epoll` descriptor.
+    fn epoll(&self) -> Result<&Epoll, Errno> {
+        Err(Errno::EINVAL)
+    }
+
+    /// The socket behind a socket descriptor.
+    fn socket(&self) -> Result<&dyn Socket, Errno> {
+        Err(Errno::ENOTSOCK)
+    }
+}
+
+/// An open file description. Descriptors created by `dup` or inherited by
+/// children share one of these, and with it the file offset.
+pub struct OpenFile {
+    file: Arc<dyn File>,
+    offset: AtomicUsize,
+}
+
+impl OpenFile {
+    pub fn new(file: Arc<dyn File>) -> Arc<Self> {
+        Arc::new(OpenFile {
+            file,
+            offset: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn read(&self, buf: &mut [u8]) -> Result<usize, Errno> {
+        let count = self.file.read(self.offset.load(Ordering::Relaxed), buf)?;
+        self.offset.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    pub fn write(&self, buf: &[u8]) -> Result<usize, Errno> {
+        let count = self.file.write(self.offset.load(Ordering::Relaxed), buf)?;
+        self.offset.fetch_add(count, Ordering::Relaxed);
+        Ok(count)
+    }
+
+    pub fn truncate(&self, size: usize) -> Result<(), Errno> {
+        self.file.truncate(size)
+    }
+
+    pub fn mmap(&self) -> Result<Arc<SharedMemory>, Errno> {
+        self.file.mmap()
+    }
+
+    /// The file behind the description, for mappings that outlive it.
+    pub fn file(&self) -> Arc<dyn File> {
+        self.file.clone()
+    }
+
+    pub fn poll(&self) -> u16 {
+        self.file.poll()
+    }
+
+    pub fn poll_wait(&self, pid: u32) {
+        self.file.poll_wait(pid)
+    }
+
+    pub fn epoll(&self) -> Result<&Epoll, Errno> {
+        self.file.epoll()
+    }
+
+    pub fn socket(&self) -> Result<&dyn Socket, Errno> {
+        self.file.socket()
+    }
+}
+
+/// Per-process descriptor table.
+#[derive(Clone)]
+pub struct FileTable {
+    files: Vec<Option<Arc<OpenFile>>>,
+}
+
+impl FileTable {
+    pub const fn new() -> Self {
+        FileTable { files: Vec::new() }
+    }
+
+    /// A table with the console on stdin, stdout and stderr.
+    pub fn with_console() -> Self {
+        let console = OpenFile::new(Arc::new(Console));
+        let mut table = FileTable::new();
+        for _ in 0..3 {
+            table.files.push(Some(console.clone()));
+        }
+        table
+    }
+
+    pub fn get(&self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.files.get(fd).cloned().flatten().ok_or(Errno::EBADF)
+    }
+
+    /// Installs `file` at the lowest free descriptor below `max_files`.
+    pub fn insert(&mut self, file: Arc<OpenFile>, max_files: usize) -> Result<usize, Errno> {
+        let max_files = max_files.min(MAX_FILES);
+        if let Some(fd) = self.files.iter().take(max_files).position(|slot| slot.is_none()) {
+            self.files[fd] = Some(file);
+            return Ok(fd);
+        }
+        if self.files.len() >= max_files {
+            return Err(Errno::EMFILE);
+        }
+        self.files.push(Some(file));
+        Ok(self.files.len() - 1)
+    }
+
+    /// Installs `file` at descriptor `fd`, returning whatever was there.
+    pub fn install(&mut self, fd: usize, file: Arc<OpenFile>) -> Result<Option<Arc<OpenFile>>, Errno> {
+        if fd >= MAX_FILES {
+            return Err(Errno::EBADF);
+        }
+        if self.files.len() <= fd {
+            self.files.resize(fd + 1, None);
+        }
+        Ok(self.files[fd].replace(file))
+    }
+
+    pub fn remove(&mut self, fd: usize) -> Result<Arc<OpenFile>, Errno> {
+        self.files.get_mut(fd).and_then(|slot| slot.take()).ok_or(Errno::EBADF)
+    }
+
+    /// Closes every descriptor, e.g. when the process exits.
+    pub fn clear(&mut self) {
+        self.files.clear();
+    }
+}
+
+/// Resolves `path` to a file. `/proc` is served by procfs, every other
+/// path names a ramfs file, with or without a leading slash.
+pub fn open(path: &str) -> Result<Arc<dyn File>, Errno> {
+    if path == "/proc" {
+        return procfs::open("");
+    }
+    if let Some(rest) = path.strip_prefix("/proc/") {
+        return procfs::open(rest);
+    }
+
+    let name = path.strip_prefix('/').unwrap_or(path);
+    // Sockets are reached with connect(), not open()
+    if unix::is_bound(name) {
+        return Err(Errno::ENXIO);
+    }
+    let data = RAMFS.lock().find(name).map(|entry| entry.data).ok_or(Errno::ENOENT)?;
+    Ok(Arc::new(RamFile::new(data)))
+}
@@ -2,6 +2,7 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
@@ -18,11 +19,18 @@ pub mod drivers;
 pub mod proc;
 pub mod mem;
 pub mod fs;
+pub mod net;
 
 pub fn init() {
     arch::gdt::init();
+    proc::syscall::init_fast_syscalls();
     arch::interrupts::init_idt();
+    arch::fpu::init();
+    arch::pcid::init();
+    arch::protection::init();
+    arch::random::init();
     unsafe { arch::interrupts::PICS.lock().initialize() };
+    drivers::pit::init();
     x86_64::instructions::interrupts::enable();
 }
 
@@ -19,17 +19,19 @@ extern crate alloc;
 entry_point!(kernel_main);
 
 const SHELL_PROGRAM: &[u8] = include_bytes!("../user/shell.bin");
+const BENCH_PROGRAM: &[u8] = include_bytes!("../user/bench.bin");
+const WC_PROGRAM: &[u8] = include_bytes!("../user/wc.bin");
 
 fn init_processes() {
     println!("Initializing process management...");
 
     x86_64::instructions::interrupts::without_interrupts(|| {
-        let scheduler = unsafe { SCHEDULER.get() };
+        let mut scheduler = SCHEDULER.lock();
         scheduler.init_kernel_process();
+        scheduler.init_idle_task();
 
-        let fs = unsafe { ramfs::RAMFS.get() };
-        if let Some(shell) = fs.find("shell") {
-            scheduler.create_process(shell.data);
+        if scheduler.spawn("shell").is_err() {
+            println!("No shell found in ramfs");
         }
     });
 }
@@ -41,13 +43,10 @@ fn heap_init(boot_info: &'static BootInfo) {
         BootInfoFrameAllocator::init(&boot_info.memory_map)
     };
 
-    unsafe {
-        memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64();
-        memory::FRAME_ALLOCATOR.init(frame_allocator);
-    }
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
 
-    let alloc = unsafe { memory::FRAME_ALLOCATOR.get() };
-    allocator::init_heap(&mut mapper, alloc)
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
         .expect("heap initialization failed");
 
     println!("Heap initialized successfully!");
@@ -58,9 +57,15 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     game_os::init();
 
     heap_init(boot_info);
-    unsafe {
-        let fs = ramfs::RAMFS.get();
+    {
+        let mut fs = ramfs::RAMFS.lock();
         fs.add("shell", SHELL_PROGRAM);
+        fs.add("bench", BENCH_PROGRAM);
+        fs.add("wc", WC_PROGRAM);
+    }
+    match game_os::net::init() {
+        Ok(()) => println!("Network card found, address {}", game_os::net::NET.lock().iface.addr),
+        Err(_) => println!("No network card found"),
     }
     init_processes();
 
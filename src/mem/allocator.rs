@@ -1,13 +1,213 @@
-use x86_64::{structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB}, VirtAddr};
+use x86_64::{structures::paging::{mapper::MapToError, FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size2MiB, Size4KiB}, VirtAddr};
 use alloc::alloc::{GlobalAlloc, Layout};
-use linked_list_allocator::LockedHeap;
-use core::ptr::null_mut;
+use linked_list_allocator::Heap;
+use core::ptr::{null_mut, NonNull};
+
+use crate::arch::irq_lock::IrqLock;
+use crate::mem::memory::{self, BootInfoFrameAllocator, FRAME_ALLOCATOR, HUGE_PAGE_FRAMES};
+use crate::mem::slab::{CacheStats, SlabAllocator, SIZE_CLASSES, SLAB_SIZE};
 
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: KernelHeap = KernelHeap(IrqLock::new("HEAP", HeapState {
+    heap: Heap::empty(),
+    slabs: SlabAllocator::new(),
+    peak: 0,
+    allocations: 0,
+    failures: 0,
+}));
 
 pub const HEAP_START: usize = 0x_4444_4444_0000;
+/// What `init_heap` maps; the heap grows from there.
 pub const HEAP_SIZE: usize = 512 * 1024;
+/// The heap's virtual region, which it never grows past.
+pub const HEAP_MAX_SIZE: usize = 64 * 1024 * 1024;
+/// Smallest step the heap grows by, so growing stays rare.
+const HEAP_GROWTH: usize = 64 * 1024;
+/// Free space kept ahead of demand. Allocations made while the frame
+/// allocator is locked, like the push of a freed frame onto its list,
+/// can't grow the heap and are served from here.
+const HEAP_RESERVE: usize = 32 * 1024;
+
+/// Kernel heap usage, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct HeapStats {
+    pub size: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Free blocks held by the slab caches, which the heap counts as used.
+    pub cached: usize,
+    /// Most ever in use at once.
+    pub peak: usize,
+    /// Biggest block an allocation can still get without growing the heap.
+    pub largest_free: usize,
+    /// Allocations not yet freed.
+    pub allocations: usize,
+    /// Allocations that failed even after trying to grow.
+    pub failures: u64,
+}
+
+impl HeapStats {
+    /// External fragmentation, in percent: how much of the free space
+    /// lies outside the largest free block.
+    pub fn fragmentation(&self) -> usize {
+        if self.free == 0 {
+            return 0;
+        }
+        100 - self.largest_free * 100 / self.free
+    }
+}
+
+struct HeapState {
+    heap: Heap,
+    slabs: SlabAllocator,
+    peak: usize,
+    allocations: usize,
+    failures: u64,
+}
+
+impl HeapState {
+    fn allocate(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        self.slabs.allocate(&mut self.heap, layout)
+    }
+
+    /// Maps at least `bytes` more at the top of the heap, returning
+    /// whether anything was added. Fails while the frame allocator is
+    /// locked further up the stack, or before it is installed.
+    fn grow(&mut self, bytes: usize) -> bool {
+        let Some(mut frames) = FRAME_ALLOCATOR.try_lock() else {
+            return false;
+        };
+        let room = HEAP_START + HEAP_MAX_SIZE - self.heap.top();
+        let bytes = bytes.max(HEAP_GROWTH).next_multiple_of(4096).min(room);
+
+        // The heap's top-level entry is shared by every address space, so
+        // mapping through the active one maps it everywhere
+        let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+        let mut mapper = unsafe { memory::init(phys_mem_offset) };
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        let mut mapped = 0;
+        while mapped < bytes {
+            let addr = VirtAddr::new((self.heap.top() + mapped) as u64);
+            // Whole 2 MiB stretches take one TLB entry, if the frame
+            // allocator has the memory at hand
+            if addr.is_aligned(Size2MiB::SIZE) && bytes - mapped >= Size2MiB::SIZE as usize {
+                if let Some(frame) = frames.allocate_huge_frame_in_place() {
+                    match unsafe { mapper.map_to(Page::<Size2MiB>::containing_address(addr), frame, flags, &mut *frames) } {
+                        Ok(flush) => flush.flush(),
+                        Err(_) => break,
+                    }
+                    mapped += Size2MiB::SIZE as usize;
+                    continue;
+                }
+            }
+
+            let page = Page::<Size4KiB>::containing_address(addr);
+            let Some(frame) = frames.allocate_frame() else {
+                break;
+            };
+            // On failure the frame is lost: giving it back could allocate
+            match unsafe { mapper.map_to(page, frame, flags, &mut *frames) } {
+                Ok(flush) => flush.flush(),
+                Err(_) => break,
+            }
+            mapped += 4096;
+        }
+
+        if mapped > 0 {
+            unsafe { self.heap.extend(mapped) };
+        }
+        mapped > 0
+    }
+
+    /// The size of the largest free block. The allocator keeps no index
+    /// of its holes, so this probes with allocations.
+    fn largest_free(&mut self) -> usize {
+        const ALIGN: usize = 8;
+        let (mut low, mut high) = (0, self.heap.free() / ALIGN);
+        let mut fits = |size: usize| {
+            let layout = Layout::from_size_align(size, ALIGN).unwrap();
+            match self.heap.allocate_first_fit(layout) {
+                Ok(ptr) => {
+                    unsafe { self.heap.deallocate(ptr, layout) };
+                    true
+                }
+                Err(()) => false,
+            }
+        };
+
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if fits(mid * ALIGN) {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        low * ALIGN
+    }
+}
+
+/// The kernel heap: slab caches for small objects over a linked-list heap
+/// that maps more frames when it runs out. Its lock masks interrupts, as
+/// handlers like the network poll on the timer tick allocate too.
+pub struct KernelHeap(IrqLock<HeapState>);
+
+unsafe impl GlobalAlloc for KernelHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut state = self.0.lock();
+        let mut result = state.allocate(layout);
+        if result.is_none() && state.grow(layout.size().max(SLAB_SIZE) + layout.align()) {
+            result = state.allocate(layout);
+        }
+        let Some(ptr) = result else {
+            state.failures += 1;
+            return null_mut();
+        };
+
+        state.allocations += 1;
+        state.peak = state.peak.max(state.heap.used());
+        if state.heap.free() < HEAP_RESERVE {
+            state.grow(HEAP_GROWTH);
+        }
+        ptr.as_ptr()
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut state = self.0.lock();
+        let state = &mut *state;
+        state.slabs.deallocate(&mut state.heap, NonNull::new_unchecked(ptr), layout);
+        state.allocations -= 1;
+    }
+}
+
+pub fn stats() -> HeapStats {
+    let mut state = ALLOCATOR.0.lock();
+    HeapStats {
+        size: state.heap.size(),
+        used: state.heap.used(),
+        free: state.heap.free(),
+        cached: state.slabs.cached(),
+        peak: state.peak,
+        largest_free: state.largest_free(),
+        allocations: state.allocations,
+        failures: state.failures,
+    }
+}
+
+/// Each slab cache's counts, smallest blocks first.
+pub fn slab_stats() -> [CacheStats; SIZE_CLASSES.len()] {
+    ALLOCATOR.0.lock().slabs.stats()
+}
+
+#[alloc_error_handler]
+fn alloc_error(layout: Layout) -> ! {
+    let heap = stats();
+    panic!(
+        "kernel heap exhausted allocating {} bytes (align {}): {} of {} bytes used, largest free block {}",
+        layout.size(), layout.align(), heap.used, heap.size, heap.largest_free
+    );
+}
 
 pub struct Dummy;
 
@@ -23,7 +223,7 @@ unsafe impl GlobalAlloc for Dummy {
 
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    frame_allocator: &mut BootInfoFrameAllocator,
 ) -> Result<(), MapToError<Size4KiB>> {
     let page_range = {
         let heap_start = VirtAddr::new(HEAP_START as u64);
@@ -44,8 +244,12 @@ pub fn init_heap(
     }
 
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.0.lock().heap.init(HEAP_START, HEAP_SIZE);
     }
 
+    // Room for the frames a huge page for the heap may skip, as growing
+    // the heap can't grow the free list
+    frame_allocator.reserve_free(HUGE_PAGE_FRAMES);
+
     Ok(())
 }
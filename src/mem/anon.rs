@@ -0,0 +1,205 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PageTableFlags, PhysFrame, Size2MiB};
+use x86_64::VirtAddr;
+
+use crate::arch::pcid;
+use crate::arch::protection;
+use crate::mem::memory::{self, BootInfoFrameAllocator, FRAME_ALLOCATOR};
+use crate::mem::shm::{MMAP_BASE, MMAP_END};
+use crate::proc::errno::Errno;
+use crate::proc::process::{ProcessMemory, RegionKind};
+use crate::proc::scheduler::ProcessManager;
+use crate::proc::usercopy::USER_SPACE_END;
+
+const HUGE_PAGE_SIZE: u64 = Size2MiB::SIZE;
+
+/// 2 MiB pages currently backing anonymous mappings.
+static HUGE_PAGES: AtomicUsize = AtomicUsize::new(0);
+
+pub fn huge_pages() -> usize {
+    HUGE_PAGES.load(Ordering::Relaxed)
+}
+
+impl ProcessManager {
+    /// `mmap` of `MAP_ANONYMOUS`: `length` bytes of zeroed memory private to
+    /// `pid`. Mappings of 2 MiB or more start on a 2 MiB boundary, and each
+    /// whole 2 MiB in them is a huge page when the frame allocator has one.
+    pub fn map_anonymous(&mut self, pid: u32, length: usize, writable: bool) -> Result<VirtAddr, Errno> {
+        if length == 0 {
+            return Err(Errno::EINVAL);
+        }
+        if length as u64 > MMAP_END - MMAP_BASE {
+            return Err(Errno::ENOMEM);
+        }
+        let pages = length.div_ceil(4096);
+
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        process.limits.check_pages(process.memory.pages_allocated() + pages)?;
+        let align = if pages as u64 * 4096 >= HUGE_PAGE_SIZE { HUGE_PAGE_SIZE } else { 4096 };
+        let base = process.memory.mmap_base.as_u64();
+        let start = process.memory.find_free_aligned(base, MMAP_END, pages, align).ok_or(Errno::ENOMEM)?;
+        let end = start + pages as u64 * 4096;
+
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | protection::no_execute();
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        let page_table = PhysFrame::containing_address(process.memory.page_table_addr);
+        let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        let mut addr = start;
+        while addr < end {
+            if addr.is_aligned(HUGE_PAGE_SIZE) && end - addr >= HUGE_PAGE_SIZE {
+                if let Some(frame) = allocator.allocate_huge_frame() {
+                    unsafe { core::ptr::write_bytes(frame_ptr(frame.start_address().as_u64()), 0, HUGE_PAGE_SIZE as usize) };
+                    match memory::map_user_huge_frame(page_table, phys_mem_offset, &mut *allocator, addr, frame, flags) {
+                        Ok(()) => {
+                            HUGE_PAGES.fetch_add(1, Ordering::Relaxed);
+                            addr += HUGE_PAGE_SIZE;
+                            continue;
+                        }
+                        // A table from an earlier mapping is in the way
+                        Err(_) => allocator.deallocate_huge_frame(frame),
+                    }
+                }
+            }
+
+            let Some(frame) = allocator.allocate_frame() else {
+                release(&process.memory, &mut allocator, start, addr);
+                return Err(Errno::ENOMEM);
+            };
+            unsafe { core::ptr::write_bytes(frame_ptr(frame.start_address().as_u64()), 0, 4096) };
+            if let Err(err) = memory::map_user_frame(page_table, phys_mem_offset, &mut *allocator, addr, frame, flags) {
+                unsafe { allocator.deallocate_frame(frame) };
+                release(&process.memory, &mut allocator, start, addr);
+                return Err(err);
+            }
+            addr += 4096u64;
+        }
+        drop(allocator);
+
+        process.memory.add_region(start, end, flags, RegionKind::Anonymous);
+        Ok(start)
+    }
+
+    /// `brk`: moves the end of `pid`'s heap to `addr`, mapping zeroed pages
+    /// as it grows and freeing them as it shrinks. Returns the break, which
+    /// stays where it was when `addr` lies below the heap's start or can't
+    /// be reached, so 0 just asks for it.
+    pub fn brk(&mut self, pid: u32, addr: u64) -> Result<VirtAddr, Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let memory = &mut process.memory;
+        let (heap_start, current) = (memory.heap_start(), memory.brk());
+        let requested = match VirtAddr::try_new(addr) {
+            Ok(requested) if requested >= heap_start && addr < USER_SPACE_END => requested,
+            _ => return Ok(current),
+        };
+        let old_end = current.align_up(4096u64);
+        let new_end = requested.align_up(4096u64);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE
+            | protection::no_execute();
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        if new_end > old_end {
+            let pages = ((new_end - old_end) / 4096) as usize;
+            let collides = memory.regions().iter()
+                .any(|region| region.kind != RegionKind::Heap && region.start < new_end && old_end < region.end);
+            if collides || process.limits.check_pages(memory.pages_allocated() + pages).is_err() {
+                return Ok(current);
+            }
+
+            let page_table = PhysFrame::containing_address(memory.page_table_addr);
+            let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+            let mut addr = old_end;
+            while addr < new_end {
+                let Some(frame) = allocator.allocate_frame() else {
+                    release(memory, &mut allocator, old_end, addr);
+                    return Ok(current);
+                };
+                unsafe { core::ptr::write_bytes(frame_ptr(frame.start_address().as_u64()), 0, 4096) };
+                if memory::map_user_frame(page_table, phys_mem_offset, &mut *allocator, addr, frame, flags).is_err() {
+                    unsafe { allocator.deallocate_frame(frame) };
+                    release(memory, &mut allocator, old_end, addr);
+                    return Ok(current);
+                }
+                addr += 4096u64;
+            }
+        } else if new_end < old_end {
+            release(memory, &mut allocator, new_end, old_end);
+        }
+        drop(allocator);
+
+        memory.remove_region(heap_start);
+        if new_end > heap_start {
+            memory.add_region(heap_start, new_end, flags, RegionKind::Heap);
+        }
+        memory.set_brk(requested);
+        Ok(requested)
+    }
+
+    /// `munmap` of a whole anonymous mapping of `pid` starting at `start`.
+    pub fn unmap_anonymous(&mut self, pid: u32, start: VirtAddr, length: usize) -> Result<(), Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let region = process.memory.regions().iter()
+            .find(|region| region.start == start && region.kind == RegionKind::Anonymous)
+            .copied()
+            .ok_or(Errno::EINVAL)?;
+        if length.div_ceil(4096) as u64 != (region.end - region.start) / 4096 {
+            return Err(Errno::EINVAL);
+        }
+
+        release(&process.memory, &mut FRAME_ALLOCATOR.lock(), region.start, region.end);
+        process.memory.remove_region(start);
+        Ok(())
+    }
+
+    /// Frees every anonymous mapping of `pid` and its heap, e.g. when it
+    /// exits.
+    pub(crate) fn unmap_all_anonymous(&mut self, pid: u32) {
+        let Some(process) = self.processes.get_mut(&pid) else {
+            return;
+        };
+        let anonymous: Vec<_> = process.memory.regions().iter()
+            .filter(|region| matches!(region.kind, RegionKind::Anonymous | RegionKind::Heap))
+            .copied()
+            .collect();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for region in anonymous {
+            release(&process.memory, &mut allocator, region.start, region.end);
+            process.memory.remove_region(region.start);
+        }
+    }
+}
+
+fn frame_ptr(phys: u64) -> *mut u8 {
+    (unsafe { memory::PHYS_MEM_OFFSET } + phys) as *mut u8
+}
+
+/// Unmaps `[start, end)` and frees its frames, of either size.
+pub(crate) fn release(memory: &ProcessMemory, allocator: &mut BootInfoFrameAllocator, start: VirtAddr, end: VirtAddr) {
+    let page_table = PhysFrame::containing_address(memory.page_table_addr);
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let active = Cr3::read().0 == page_table;
+
+    let mut addr = start;
+    while addr < end {
+        if addr.is_aligned(HUGE_PAGE_SIZE) {
+            if let Some(frame) = memory::unmap_user_huge_page(page_table, phys_mem_offset, addr) {
+                pcid::flush_page(memory.pcid, addr, active);
+                allocator.deallocate_huge_frame(frame);
+                HUGE_PAGES.fetch_sub(1, Ordering::Relaxed);
+                addr += HUGE_PAGE_SIZE;
+                continue;
+            }
+        }
+        if let Some(frame) = memory::unmap_user_page(page_table, phys_mem_offset, addr) {
+            pcid::flush_page(memory.pcid, addr, active);
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+        addr += 4096u64;
+    }
+}
@@ -0,0 +1,85 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+use x86_64::VirtAddr;
+
+use crate::arch::random;
+use crate::mem::shm::{MMAP_BASE, MMAP_END};
+
+/// Where programs linked at a fixed address are loaded. Their code never
+/// moves; everything else still does.
+pub const FIXED_CODE_BASE: u64 = 0x400000;
+/// Lowest load address of position-independent programs, in a level 4
+/// entry of its own.
+pub const PIE_BASE: u64 = 0x5500_0000_0000;
+/// Highest user stack top, in the last level 4 entry below the kernel.
+/// The page above stays unmapped.
+pub const STACK_TOP: u64 = 0x7FFF_FFFF_F000;
+/// Stack top with randomization off, as it always was before.
+pub const FIXED_STACK_TOP: u64 = 0x800000;
+
+/// Pages each base may be moved by: 256 GiB for code, keeping it inside
+/// its level 4 entry, the lower half of the `mmap` window, 64 GiB for the
+/// stack and 32 MiB between the code and the heap.
+const PIE_PAGES: u64 = 1 << 26;
+const MMAP_PAGES: u64 = (MMAP_END - MMAP_BASE) / 4096 / 2;
+const STACK_PAGES: u64 = 1 << 24;
+const HEAP_PAGES: u64 = 1 << 13;
+
+/// On unless the kernel was built with the `no-aslr` feature, so a debug
+/// session sees the same addresses on every boot. The bootloader passes no
+/// command line, so this stands in for a boot option: turning it off takes
+/// a rebuild, or a `set_enabled` call from kernel code.
+static ENABLED: AtomicBool = AtomicBool::new(!cfg!(feature = "no-aslr"));
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Turns randomization on or off for processes created from now on. No
+/// system call reaches this.
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Where a new process's pieces go.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub code_base: VirtAddr,
+    /// Start of the heap, past the end of the code, which `brk` grows.
+    pub heap_base: VirtAddr,
+    pub stack_top: VirtAddr,
+    /// Where `mmap` starts looking for free space.
+    pub mmap_base: VirtAddr,
+}
+
+/// Page offset below `pages`, or 0 with randomization off.
+fn offset(pages: u64) -> u64 {
+    if enabled() {
+        random::random_below(pages) * 4096
+    } else {
+        0
+    }
+}
+
+/// Picks the layout of a process whose code takes `code_pages` pages. The
+/// code moves only when `position_independent`.
+pub fn layout(code_pages: usize, position_independent: bool) -> Layout {
+    let code_base = if position_independent {
+        PIE_BASE + offset(PIE_PAGES)
+    } else {
+        FIXED_CODE_BASE
+    };
+    let heap_base = code_base + code_pages as u64 * 4096 + offset(HEAP_PAGES);
+    let stack_top = if enabled() {
+        STACK_TOP - offset(STACK_PAGES)
+    } else {
+        FIXED_STACK_TOP
+    };
+    let mmap_base = MMAP_BASE + offset(MMAP_PAGES);
+
+    Layout {
+        code_base: VirtAddr::new(code_base),
+        heap_base: VirtAddr::new(heap_base),
+        stack_top: VirtAddr::new(stack_top),
+        mmap_base: VirtAddr::new(mmap_base),
+    }
+}
@@ -0,0 +1,263 @@
+use alloc::collections::btree_map::Entry;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::arch::pcid;
+use crate::arch::protection;
+use crate::fs::vfs::File;
+use crate::mem::anon;
+use crate::mem::memory::{self, FRAME_ALLOCATOR};
+use crate::mem::shm::{MMAP_BASE, MMAP_END};
+use crate::proc::errno::Errno;
+use crate::proc::process::{FileMapping, ProcessMemory, RegionKind};
+use crate::proc::scheduler::ProcessManager;
+
+/// Pages of shared file mappings, by file and page index in it. Every
+/// process mapping a page shared maps the one frame, so each sees what the
+/// others write, and write-back copies out all of it.
+static SHARED_PAGES: IrqLock<BTreeMap<(usize, usize), SharedPage>> =
+    IrqLock::new("SHARED_PAGES", BTreeMap::new());
+
+struct SharedPage {
+    frame: PhysFrame,
+    /// Mappings it is mapped into; the frame is freed when none are left.
+    mappings: usize,
+}
+
+impl ProcessManager {
+    /// `mmap` of a file: reserves `length` bytes of `pid`'s address space for
+    /// the file from `offset`, without reading anything yet. Writes to a
+    /// `shared` mapping go back to the file, which must take writes.
+    pub fn map_file(
+        &mut self,
+        pid: u32,
+        file: Arc<dyn File>,
+        offset: usize,
+        length: usize,
+        writable: bool,
+        shared: bool,
+    ) -> Result<VirtAddr, Errno> {
+        if length == 0 || offset % 4096 != 0 {
+            return Err(Errno::EINVAL);
+        }
+        if length as u64 > MMAP_END - MMAP_BASE {
+            return Err(Errno::ENOMEM);
+        }
+        let pages = length.div_ceil(4096);
+        // Every file position the mapping covers must be representable
+        offset.checked_add(pages * 4096).ok_or(Errno::EINVAL)?;
+        file.size()?;
+        // An empty write tells whether the file takes any
+        if shared && writable && file.write(offset, &[]).is_err() {
+            return Err(Errno::EACCES);
+        }
+
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        process.limits.check_pages(process.memory.pages_allocated() + pages)?;
+        let base = process.memory.mmap_base.as_u64();
+        let start = process.memory.find_free_range(base, MMAP_END, pages).ok_or(Errno::ENOMEM)?;
+        let end = start + pages as u64 * 4096;
+
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | protection::no_execute();
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        process.memory.add_region(start, end, flags, RegionKind::File);
+        process.memory.mapped_files.push(FileMapping { start, end, file, offset, shared });
+        Ok(start)
+    }
+
+    /// Reads in the page of a mapped file `pid` faulted on at `addr`, or for
+    /// a shared mapping, maps the page other mappings of it already use.
+    /// Fails unless `addr` lies in a mapped file with nothing there yet, and
+    /// for writes to a read-only mapping.
+    pub fn handle_file_fault(&mut self, pid: u32, addr: VirtAddr, write: bool) -> Result<(), Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let memory = &process.memory;
+        let mapping = memory.mapped_files.iter()
+            .find(|mapping| mapping.start <= addr && addr < mapping.end)
+            .ok_or(Errno::EFAULT)?;
+        let flags = memory.regions().iter()
+            .find(|region| region.start == mapping.start)
+            .map(|region| region.flags)
+            .ok_or(Errno::EFAULT)?;
+        if write && !flags.contains(PageTableFlags::WRITABLE) {
+            return Err(Errno::EACCES);
+        }
+
+        let page = addr.align_down(4096u64);
+        let position = mapping.offset + (page - mapping.start) as usize;
+        let page_table = PhysFrame::containing_address(memory.page_table_addr);
+        let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+
+        let key = (file_key(&mapping.file), position / 4096);
+        let mut shared_pages = SHARED_PAGES.lock();
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        if mapping.shared {
+            if let Some(shared) = shared_pages.get_mut(&key) {
+                memory::map_user_frame(page_table, phys_mem_offset, &mut *allocator, page, shared.frame, flags)?;
+                shared.mappings += 1;
+                return Ok(());
+            }
+        }
+
+        let frame = allocator.allocate_frame().ok_or(Errno::ENOMEM)?;
+        let contents = unsafe { &mut *page_ptr(frame) };
+        // A private copy starts from what shared mappings have written, which
+        // may not be in the file yet
+        let filled = match shared_pages.get(&key) {
+            Some(shared) => {
+                contents.copy_from_slice(unsafe { &*page_ptr(shared.frame) });
+                Ok(())
+            }
+            None => read_page(&*mapping.file, position, contents),
+        };
+        if let Err(err) = filled
+            .and_then(|()| memory::map_user_frame(page_table, phys_mem_offset, &mut *allocator, page, frame, flags))
+        {
+            unsafe { allocator.deallocate_frame(frame) };
+            return Err(err);
+        }
+        if mapping.shared {
+            shared_pages.insert(key, SharedPage { frame, mappings: 1 });
+        }
+        Ok(())
+    }
+
+    /// `msync`: writes the dirty pages of `pid`'s shared file mappings in
+    /// `[start, start + length)` back to their files.
+    pub fn sync_files(&mut self, pid: u32, start: VirtAddr, length: usize) -> Result<(), Errno> {
+        if !start.is_aligned(4096u64) {
+            return Err(Errno::EINVAL);
+        }
+        let end = start.as_u64().checked_add(length as u64)
+            .and_then(|end| VirtAddr::try_new(end).ok())
+            .ok_or(Errno::ENOMEM)?;
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let memory = &process.memory;
+        for mapping in memory.mapped_files.iter().filter(|mapping| mapping.start < end && start < mapping.end) {
+            write_back(memory, mapping, start.max(mapping.start), end.min(mapping.end))?;
+        }
+        Ok(())
+    }
+
+    /// `munmap` of a whole file mapping of `pid` starting at `start`,
+    /// writing it back first if shared.
+    pub fn unmap_file(&mut self, pid: u32, start: VirtAddr, length: usize) -> Result<(), Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let index = process.memory.mapped_files.iter()
+            .position(|mapping| mapping.start == start)
+            .ok_or(Errno::EINVAL)?;
+        let mapping = &process.memory.mapped_files[index];
+        if length.div_ceil(4096) as u64 != (mapping.end - mapping.start) / 4096 {
+            return Err(Errno::EINVAL);
+        }
+
+        let result = write_back(&process.memory, mapping, mapping.start, mapping.end);
+        let mapping = process.memory.mapped_files.remove(index);
+        release(&process.memory, &mapping);
+        process.memory.remove_region(start);
+        result
+    }
+
+    /// Writes back and drops every file mapping of `pid`, e.g. when it exits.
+    pub(crate) fn unmap_all_files(&mut self, pid: u32) {
+        let Some(process) = self.processes.get_mut(&pid) else {
+            return;
+        };
+        for mapping in core::mem::take(&mut process.memory.mapped_files) {
+            // Nobody is left to report a failure to
+            let _ = write_back(&process.memory, &mapping, mapping.start, mapping.end);
+            release(&process.memory, &mapping);
+            process.memory.remove_region(mapping.start);
+        }
+    }
+}
+
+/// What `SHARED_PAGES` knows `file`'s contents by. Mappings keep the file
+/// alive, so the address of the open file can't be reused while it has
+/// pages there.
+fn file_key(file: &Arc<dyn File>) -> usize {
+    file.cache_key().unwrap_or(Arc::as_ptr(file) as *const u8 as usize)
+}
+
+/// Unmaps `mapping` and frees its frames, those of shared pages once no
+/// other mapping uses them.
+fn release(memory: &ProcessMemory, mapping: &FileMapping) {
+    if !mapping.shared {
+        anon::release(memory, &mut FRAME_ALLOCATOR.lock(), mapping.start, mapping.end);
+        return;
+    }
+    let page_table = PhysFrame::containing_address(memory.page_table_addr);
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let active = Cr3::read().0 == page_table;
+    let key = file_key(&mapping.file);
+
+    let mut shared_pages = SHARED_PAGES.lock();
+    let mut allocator = FRAME_ALLOCATOR.lock();
+    let mut page = mapping.start;
+    while page < mapping.end {
+        if memory::unmap_user_page(page_table, phys_mem_offset, page).is_some() {
+            pcid::flush_page(memory.pcid, page, active);
+            let index = (mapping.offset + (page - mapping.start) as usize) / 4096;
+            if let Entry::Occupied(mut shared) = shared_pages.entry((key, index)) {
+                shared.get_mut().mappings -= 1;
+                if shared.get().mappings == 0 {
+                    unsafe { allocator.deallocate_frame(shared.remove().frame) };
+                }
+            }
+        }
+        page += 4096u64;
+    }
+}
+
+fn page_ptr(frame: PhysFrame) -> *mut [u8; 4096] {
+    (unsafe { memory::PHYS_MEM_OFFSET } + frame.start_address().as_u64()) as *mut [u8; 4096]
+}
+
+/// Fills `page` from `file` at `position`, zeroing what lies past its end.
+fn read_page(file: &dyn File, position: usize, page: &mut [u8; 4096]) -> Result<(), Errno> {
+    let mut filled = 0;
+    while filled < page.len() {
+        let count = file.read(position + filled, &mut page[filled..])?;
+        if count == 0 {
+            break;
+        }
+        filled += count;
+    }
+    page[filled..].fill(0);
+    Ok(())
+}
+
+/// Writes the dirty pages of `mapping` in `[start, end)` to its file, if it
+/// is shared. Only what lies within the file is written: a mapping doesn't
+/// grow the file.
+fn write_back(memory: &ProcessMemory, mapping: &FileMapping, start: VirtAddr, end: VirtAddr) -> Result<(), Errno> {
+    if !mapping.shared {
+        return Ok(());
+    }
+    let page_table = PhysFrame::containing_address(memory.page_table_addr);
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let active = Cr3::read().0 == page_table;
+    let size = mapping.file.size()?;
+
+    let mut page = start.align_down(4096u64);
+    while page < end {
+        if let Some(frame) = memory::take_dirty_user_page(page_table, phys_mem_offset, page) {
+            pcid::flush_page(memory.pcid, page, active);
+            let position = mapping.offset + (page - mapping.start) as usize;
+            if position < size {
+                let count = (size - position).min(4096);
+                let contents = unsafe { &*page_ptr(frame) };
+                mapping.file.write(position, &contents[..count])?;
+            }
+        }
+        page += 4096u64;
+    }
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::mem::memory::{self, FRAME_ALLOCATOR};
+use crate::proc::errno::Errno;
+
+/// Start of the kernel stack region, in the kernel's upper half so every
+/// address space shares it.
+pub const KSTACK_START: u64 = 0xFFFF_FF00_0000_0000;
+/// Virtual space each stack gets. Only the top `stack_size()` bytes are
+/// mapped; what lies below, at least one page, is the guard.
+pub const KSTACK_SLOT_SIZE: usize = 64 * 1024;
+/// Stacks the region holds.
+pub const KSTACK_MAX: usize = 4096;
+/// Stack size until `set_stack_size` changes it.
+pub const KSTACK_DEFAULT_SIZE: usize = 8 * 1024;
+
+const NO_OWNER: u32 = u32::MAX;
+
+static STACK_SIZE: AtomicUsize = AtomicUsize::new(KSTACK_DEFAULT_SIZE);
+
+/// The process each slot belongs to, read without locks by the double
+/// fault handler.
+static OWNERS: [AtomicU32; KSTACK_MAX] = [const { AtomicU32::new(NO_OWNER) }; KSTACK_MAX];
+/// Mapped bytes at the top of each slot, so the handler can tell the guard
+/// from the stack.
+static SIZES: [AtomicUsize; KSTACK_MAX] = [const { AtomicUsize::new(0) }; KSTACK_MAX];
+
+struct StackSlots {
+    /// First slot never used.
+    next: usize,
+    /// Released slots, still mapped, with their stack size.
+    free: Vec<(usize, usize)>,
+}
+
+static SLOTS: IrqLock<StackSlots> = IrqLock::new("KSTACK", StackSlots { next: 0, free: Vec::new() });
+
+/// A kernel stack in the stack region. Dropping it gives the slot back for
+/// the next stack of the same size; its pages stay mapped, so a process
+/// can finish exiting on the stack it just released.
+pub struct KernelStack {
+    slot: usize,
+    size: usize,
+}
+
+impl KernelStack {
+    /// Where the stack starts, growing down.
+    pub fn top(&self) -> VirtAddr {
+        slot_base(self.slot) + KSTACK_SLOT_SIZE as u64
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for KernelStack {
+    fn drop(&mut self) {
+        OWNERS[self.slot].store(NO_OWNER, Ordering::Relaxed);
+        SLOTS.lock().free.push((self.slot, self.size));
+    }
+}
+
+fn slot_base(slot: usize) -> VirtAddr {
+    VirtAddr::new(KSTACK_START + (slot * KSTACK_SLOT_SIZE) as u64)
+}
+
+/// Size of stacks allocated from now on.
+pub fn stack_size() -> usize {
+    STACK_SIZE.load(Ordering::Relaxed)
+}
+
+/// Sets the size of stacks allocated from now on: whole pages, leaving at
+/// least a guard page in the slot.
+pub fn set_stack_size(bytes: usize) -> Result<(), Errno> {
+    if bytes == 0 || bytes % 4096 != 0 || bytes > KSTACK_SLOT_SIZE - 4096 {
+        return Err(Errno::EINVAL);
+    }
+    STACK_SIZE.store(bytes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Allocates a stack for `pid`, reusing a released one of the right size
+/// when there is one.
+pub fn allocate(pid: u32) -> Result<KernelStack, Errno> {
+    let size = stack_size();
+    let mut slots = SLOTS.lock();
+    let slot = match slots.free.iter().position(|&(_, free_size)| free_size == size) {
+        Some(index) => slots.free.swap_remove(index).0,
+        None => {
+            let slot = slots.next;
+            if slot == KSTACK_MAX {
+                return Err(Errno::ENOMEM);
+            }
+            // A slot left half-mapped by a failure is not used again
+            slots.next += 1;
+            map(slot, size)?;
+            slot
+        }
+    };
+    SIZES[slot].store(size, Ordering::Relaxed);
+    OWNERS[slot].store(pid, Ordering::Relaxed);
+    Ok(KernelStack { slot, size })
+}
+
+/// Maps the top `size` bytes of `slot`. The mapping is made in the
+/// kernel's tables, under a top-level entry every address space copies:
+/// the first stack is allocated before the first process exists.
+fn map(slot: usize, size: usize) -> Result<(), Errno> {
+    let mut mapper = unsafe { memory::kernel_mapper() };
+    let mut frames = FRAME_ALLOCATOR.lock();
+    let top = slot_base(slot) + KSTACK_SLOT_SIZE as u64;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+    let pages = Page::<Size4KiB>::range(Page::containing_address(top - size as u64), Page::containing_address(top));
+    for page in pages {
+        let frame = frames.allocate_frame().ok_or(Errno::ENOMEM)?;
+        unsafe { mapper.map_to(page, frame, flags, &mut *frames) }
+            .map_err(|_| Errno::ENOMEM)?
+            .flush();
+    }
+    Ok(())
+}
+
+/// The process whose stack guard `addr` lies in. A kernel stack overflow
+/// faults there, and again pushing the page fault's frame, which ends in a
+/// double fault on its own stack with `addr` in CR2.
+pub fn overflowed(addr: VirtAddr) -> Option<u32> {
+    let offset = addr.as_u64().checked_sub(KSTACK_START)? as usize;
+    let slot = offset / KSTACK_SLOT_SIZE;
+    if slot >= KSTACK_MAX {
+        return None;
+    }
+    // An address on the stack itself is no overflow
+    if offset % KSTACK_SLOT_SIZE >= KSTACK_SLOT_SIZE - SIZES[slot].load(Ordering::Relaxed) {
+        return None;
+    }
+    match OWNERS[slot].load(Ordering::Relaxed) {
+        NO_OWNER => None,
+        pid => Some(pid),
+    }
+}
@@ -3,30 +3,42 @@ use x86_64::{structures::paging::PageTable, VirtAddr};
 use x86_64::structures::paging::OffsetPageTable;
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::registers::control::Cr3;
-use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+use core::sync::atomic::{AtomicU64, Ordering};
 use bootloader::bootinfo::MemoryMap;
-use x86_64::{PhysAddr, structures::paging::{PhysFrame, Size4KiB, FrameAllocator}};
+use x86_64::{PhysAddr, structures::paging::{PhysFrame, Size2MiB, Size4KiB, FrameAllocator, FrameDeallocator}};
 use bootloader::bootinfo::MemoryRegionType;
-use alloc::vec;
+use alloc::vec::Vec;
 
-use alloc::boxed::Box;
+use crate::arch::irq_lock::{IrqLock, IrqLockGuard};
+use crate::proc::errno::Errno;
 
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
     next: usize,
+    /// Frames given back, handed out again before fresh ones.
+    free: Vec<PhysFrame>,
 }
 
-pub struct FrameAllocatorCell(UnsafeCell<Option<BootInfoFrameAllocator>>);
 
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        if let Some(frame) = self.free.pop() {
+            return Some(frame);
+        }
         let frame = self.usable_frames().nth(self.next);
         self.next += 1;
         frame
     }
 }
 
+impl FrameDeallocator<Size4KiB> for BootInfoFrameAllocator {
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        self.free.push(frame);
+    }
+}
+
 impl BootInfoFrameAllocator {
     /// Create a FrameAllocator from the passed memory map.
     ///
@@ -37,6 +49,7 @@ impl BootInfoFrameAllocator {
         BootInfoFrameAllocator {
             memory_map,
             next: 0,
+            free: Vec::new(),
         }
     }
 }
@@ -56,29 +69,146 @@ impl BootInfoFrameAllocator {
         // create `PhysFrame` types from the start addresses
         frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
     }
+
+    /// Number of usable frames in the memory map.
+    pub fn total_frames(&self) -> usize {
+        self.usable_frames().count()
+    }
+
+    /// Number of frames currently handed out.
+    pub fn allocated_frames(&self) -> usize {
+        self.next - self.free.len()
+    }
+
+    /// Allocates `count` physically contiguous frames, as devices reading
+    /// rings through DMA need, and returns the first.
+    pub fn allocate_contiguous(&mut self, count: usize) -> Option<PhysFrame> {
+        self.allocate_aligned(count, 1, usize::MAX)
+    }
+
+    /// Allocates the frames of a 2 MiB page. Only memory never handed out
+    /// is used: frames come back one by one, and the free list is not
+    /// searched for runs.
+    pub fn allocate_huge_frame(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let first = self.allocate_aligned(HUGE_PAGE_FRAMES, HUGE_PAGE_FRAMES, usize::MAX)?;
+        Some(PhysFrame::containing_address(first.start_address()))
+    }
+
+    /// Like `allocate_huge_frame`, but gives up rather than grow the free
+    /// list, for the kernel heap, which can't allocate while it grows.
+    pub fn allocate_huge_frame_in_place(&mut self) -> Option<PhysFrame<Size2MiB>> {
+        let room = self.free.capacity() - self.free.len();
+        let first = self.allocate_aligned(HUGE_PAGE_FRAMES, HUGE_PAGE_FRAMES, room)?;
+        Some(PhysFrame::containing_address(first.start_address()))
+    }
+
+    /// Gives back the frames of a 2 MiB page, as single frames.
+    pub fn deallocate_huge_frame(&mut self, frame: PhysFrame<Size2MiB>) {
+        let first = PhysFrame::<Size4KiB>::containing_address(frame.start_address());
+        self.free.extend((0..HUGE_PAGE_FRAMES as u64).map(|index| first + index));
+    }
+
+    /// Makes room on the free list for `frames` more without allocating.
+    pub fn reserve_free(&mut self, frames: usize) {
+        self.free.reserve(frames);
+    }
+
+    /// Allocates `count` contiguous fresh frames, the first at a multiple
+    /// of `align` frames. Fresh frames passed over go on the free list; if
+    /// more than `spill` would, nothing is allocated.
+    fn allocate_aligned(&mut self, count: usize, align: usize, spill: usize) -> Option<PhysFrame> {
+        let mut run: Option<(PhysFrame, usize)> = None;
+        let mut taken = 0;
+        for frame in self.usable_frames().skip(self.next) {
+            taken += 1;
+            run = match run {
+                Some((first, len)) if first + len as u64 == frame => Some((first, len + 1)),
+                _ if frame.start_address().as_u64() / 4096 % align as u64 == 0 => Some((frame, 1)),
+                _ => None,
+            };
+            let len = run.map_or(0, |(_, len)| len);
+            if len == count {
+                break;
+            }
+            // Everything before the run would be spilled
+            if taken - len > spill {
+                return None;
+            }
+        }
+
+        let (first, len) = run.filter(|&(_, len)| len == count)?;
+        self.free.extend(self.usable_frames().skip(self.next).take(taken - len));
+        self.next += taken;
+        Some(first)
+    }
 }
 
-unsafe impl Sync for FrameAllocatorCell {}
-unsafe impl Send for FrameAllocatorCell {}
+/// The frame allocator, installed by `init` once the memory map is known.
+pub struct GlobalFrameAllocator(IrqLock<Option<BootInfoFrameAllocator>>);
 
-impl FrameAllocatorCell {
+impl GlobalFrameAllocator {
     const fn new() -> Self {
-        FrameAllocatorCell(UnsafeCell::new(None))
+        GlobalFrameAllocator(IrqLock::new("FRAME_ALLOCATOR", None))
+    }
+
+    pub fn lock(&self) -> FrameAllocatorGuard<'_> {
+        FrameAllocatorGuard(self.0.lock())
+    }
+
+    pub fn init(&self, alloc: BootInfoFrameAllocator) {
+        *self.0.lock() = Some(alloc);
     }
 
-    pub unsafe fn get(&self) -> &mut BootInfoFrameAllocator {
-        (*self.0.get()).as_mut().expect("Frame allocator not initialized")
+    /// Locks the allocator unless it is already locked further up the
+    /// stack or not installed yet, for the heap, which may be asked to
+    /// grow from anywhere.
+    pub fn try_lock(&self) -> Option<FrameAllocatorGuard<'_>> {
+        if self.0.is_locked() {
+            return None;
+        }
+        let guard = self.0.lock();
+        guard.is_some().then(|| FrameAllocatorGuard(guard))
     }
+}
+
+pub struct FrameAllocatorGuard<'a>(IrqLockGuard<'a, Option<BootInfoFrameAllocator>>);
+
+impl Deref for FrameAllocatorGuard<'_> {
+    type Target = BootInfoFrameAllocator;
 
-    pub unsafe fn init(&self, alloc: BootInfoFrameAllocator) {
-        *self.0.get() = Some(alloc);
+    fn deref(&self) -> &BootInfoFrameAllocator {
+        self.0.as_ref().expect("Frame allocator not initialized")
     }
 }
 
-pub static FRAME_ALLOCATOR: FrameAllocatorCell = FrameAllocatorCell::new();
+impl DerefMut for FrameAllocatorGuard<'_> {
+    fn deref_mut(&mut self) -> &mut BootInfoFrameAllocator {
+        self.0.as_mut().expect("Frame allocator not initialized")
+    }
+}
+
+pub static FRAME_ALLOCATOR: GlobalFrameAllocator = GlobalFrameAllocator::new();
 pub static mut PHYS_MEM_OFFSET: u64 = 0;
 
+/// The kernel's own level 4 table, which address spaces are built from.
+/// The first `init`, at boot, records it.
+static KERNEL_PAGE_TABLE: AtomicU64 = AtomicU64::new(0);
+
+/// Marks a table entry pointing to a table private to its address space.
+/// Other entries point to the kernel's tables, which must not be changed
+/// for one process.
+const PRIVATE_TABLE: PageTableFlags = PageTableFlags::BIT_9;
+
+/// Where the lower half of the address space ends, and the kernel's upper
+/// half starts.
+const UPPER_HALF_INDEX: usize = 256;
+
+/// 4 KiB frames in a 2 MiB page.
+pub const HUGE_PAGE_FRAMES: usize = 512;
+
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    let active = Cr3::read().0.start_address().as_u64();
+    let _ = KERNEL_PAGE_TABLE.compare_exchange(0, active, Ordering::Relaxed, Ordering::Relaxed);
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
         OffsetPageTable::new(level_4_table, physical_memory_offset)
@@ -98,32 +228,35 @@ pub unsafe fn active_level_4_table(physical_memory_offset: VirtAddr)
     unsafe { &mut *page_table_ptr }
 }
 
-pub fn allocate_kernel_stack() -> VirtAddr {
-    // Allocate stack on the heap
-    const STACK_SIZE: usize = 8192; // 8KB stack
-    
-    // Create a boxed array for the stack
-    let stack_vec = vec![0u8; STACK_SIZE];
-    let stack_box = stack_vec.into_boxed_slice();
-    
-    // Leak the box to get a static lifetime (we don't want it freed)
-    let stack_bottom = Box::leak(stack_box).as_ptr();
-    
-    // Return the TOP of the stack
-    let stack_top = unsafe {
-        VirtAddr::from_ptr(stack_bottom.add(STACK_SIZE))
-    };
-    
-    stack_top
+/// The kernel's level 4 table, as set up at boot.
+pub fn kernel_page_table() -> PhysFrame {
+    PhysFrame::containing_address(PhysAddr::new(KERNEL_PAGE_TABLE.load(Ordering::Relaxed)))
 }
 
+/// A mapper over the kernel's own tables, for mappings every address
+/// space shares: those under a top-level entry that existed when the
+/// address space was created.
+///
+/// # Safety
+/// `PHYS_MEM_OFFSET` must be set, and no other reference to the kernel's
+/// tables be in use.
+pub unsafe fn kernel_mapper() -> OffsetPageTable<'static> {
+    let phys_mem_offset = VirtAddr::new(unsafe { PHYS_MEM_OFFSET });
+    let table = phys_mem_offset + kernel_page_table().start_address().as_u64();
+    unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), phys_mem_offset) }
+}
+
+/// Creates an address space holding the kernel's mappings and nothing
+/// else. Its tables are the kernel's until a user page is mapped under
+/// them, which gives the process its own copy first, so processes never
+/// see each other's pages. None of it is user-accessible.
 pub fn create_process_page_table(
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
     phys_mem_offset: VirtAddr,
-) -> PhysFrame {
+) -> Result<PhysFrame, Errno> {
 
     // Allocate a new frame for the process's page table
-    let phy_frame = frame_allocator.allocate_frame().expect("No more frames available");
+    let phy_frame = frame_allocator.allocate_frame().ok_or(Errno::ENOMEM)?;
 
     let phy_addr = phy_frame.start_address();
     let virt_addr = phys_mem_offset + phy_addr.as_u64();
@@ -134,64 +267,168 @@ pub fn create_process_page_table(
         page_table_ptr.write(PageTable::new());
     };
 
-    // Copy the kernel to the new page table
-    let kernel_table = unsafe { active_level_4_table(phys_mem_offset) };
+    // Copy the kernel to the new page table. The kernel's table, not the
+    // active one, which may be another process's
+    let kernel_addr = phys_mem_offset + kernel_page_table().start_address().as_u64();
+    let kernel_table = unsafe { &*kernel_addr.as_ptr::<PageTable>() };
     let new_table = unsafe { &mut *page_table_ptr };
 
-    for i in 0..512 {
-        new_table[i] = kernel_table[i].clone();
+    for (entry, kernel_entry) in new_table.iter_mut().zip(kernel_table.iter()) {
+        if !kernel_entry.is_unused() {
+            entry.set_addr(kernel_entry.addr(), kernel_entry.flags() - PageTableFlags::USER_ACCESSIBLE);
+        }
     }
 
-    phy_frame
+    Ok(phy_frame)
 }
 
+/// Returns the table `entry` points to, creating it if missing and copying
+/// it first if it is the kernel's.
 fn get_or_create_table(entry: &mut PageTableEntry, phys_mem_offset: VirtAddr,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> PhysFrame {
-    if entry.is_unused() {
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
-        let frame = frame_allocator.allocate_frame().expect("No more frames");
+) -> Result<PhysFrame, Errno> {
+    if entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return Err(Errno::EEXIST);
+    }
+    if entry.flags().contains(PRIVATE_TABLE) {
+        return Ok(PhysFrame::containing_address(entry.addr()));
+    }
 
-        let table_ptr = (phys_mem_offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>();
+    let frame = frame_allocator.allocate_frame().ok_or(Errno::ENOMEM)?;
+    let table_ptr = (phys_mem_offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>();
+    if entry.is_unused() {
         unsafe { table_ptr.write(PageTable::new()) };
-
-        entry.set_addr(frame.start_address(), flags);
-        frame
     } else {
-        let flags = entry.flags() | PageTableFlags::USER_ACCESSIBLE;
-        entry.set_flags(flags);
-        PhysFrame::containing_address(entry.addr())
+        // The copy still points to the kernel's tables one level down, which
+        // are copied in turn if a user page lands under them
+        let kernel_ptr = (phys_mem_offset + entry.addr().as_u64()).as_ptr::<PageTable>();
+        unsafe { table_ptr.write((*kernel_ptr).clone()) };
+        let table = unsafe { &mut *table_ptr };
+        for entry in table.iter_mut().filter(|entry| !entry.is_unused()) {
+            entry.set_flags(entry.flags() - PRIVATE_TABLE);
+        }
     }
+
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE | PRIVATE_TABLE;
+    entry.set_addr(frame.start_address(), flags);
+    Ok(frame)
 }
 
 
-pub fn map_user_page(page_table_frame: PhysFrame,phys_mem_offset: VirtAddr,
-    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-    virt_addr: VirtAddr, flags: PageTableFlags,
-) -> PhysFrame {
+/// Returns the entry mapping `virt_addr` at `level`, 1 for a 4 KiB page
+/// or 2 for a 2 MiB one, creating the tables on the way. A 2 MiB page
+/// already covering the address is `EEXIST`.
+fn user_page_entry<'a>(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>, virt_addr: VirtAddr, level: usize,
+) -> Result<&'a mut PageTableEntry, Errno> {
+    if usize::from(virt_addr.p4_index()) >= UPPER_HALF_INDEX {
+        return Err(Errno::EFAULT);
+    }
 
     // Get table reference from a physical frame
     let table = |frame: PhysFrame| -> &mut PageTable {
         unsafe { &mut *(phys_mem_offset + frame.start_address().as_u64()).as_mut_ptr::<PageTable>() }
     };
 
-    // PML4 -> PDPT
-    let pml4 = table(page_table_frame);
-    let pdpt_frame = get_or_create_table(&mut pml4[virt_addr.p4_index()], phys_mem_offset, frame_allocator);
+    // PML4 -> PDPT -> PD -> PT, stopping at the table holding `level`
+    let indexes = [virt_addr.p4_index(), virt_addr.p3_index(), virt_addr.p2_index(), virt_addr.p1_index()];
+    let mut frame = page_table_frame;
+    for &index in &indexes[..4 - level] {
+        frame = get_or_create_table(&mut table(frame)[index], phys_mem_offset, frame_allocator)?;
+    }
+    Ok(&mut table(frame)[indexes[4 - level]])
+}
+
+pub fn map_user_page(page_table_frame: PhysFrame,phys_mem_offset: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    virt_addr: VirtAddr, flags: PageTableFlags,
+) -> Result<PhysFrame, Errno> {
+    let data_frame = frame_allocator.allocate_frame().ok_or(Errno::ENOMEM)?;
+    map_user_frame(page_table_frame, phys_mem_offset, frame_allocator, virt_addr, data_frame, flags)?;
+    Ok(data_frame)
+}
+
+/// Maps the existing `frame` at `virt_addr`, e.g. a page of shared memory.
+pub fn map_user_frame(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    virt_addr: VirtAddr, frame: PhysFrame, flags: PageTableFlags,
+) -> Result<(), Errno> {
+    let entry = user_page_entry(page_table_frame, phys_mem_offset, frame_allocator, virt_addr, 1)?;
+    entry.set_addr(frame.start_address(), flags);
+    Ok(())
+}
 
-    // PDPT -> PD
-    let pdpt = table(pdpt_frame);
-    let pd_frame = get_or_create_table(&mut pdpt[virt_addr.p3_index()], phys_mem_offset, frame_allocator);
+/// Maps `frame` as one 2 MiB page at `virt_addr`, which must be aligned to
+/// it. Fails with `EEXIST` if anything was ever mapped under it, as the
+/// page table left behind is in the way.
+pub fn map_user_huge_frame(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+    virt_addr: VirtAddr, frame: PhysFrame<Size2MiB>, flags: PageTableFlags,
+) -> Result<(), Errno> {
+    let entry = user_page_entry(page_table_frame, phys_mem_offset, frame_allocator, virt_addr, 2)?;
+    if !entry.is_unused() {
+        return Err(Errno::EEXIST);
+    }
+    entry.set_addr(frame.start_address(), flags | PageTableFlags::HUGE_PAGE);
+    Ok(())
+}
 
-    // PD -> PT
-    let pd = table(pd_frame);
-    let pt_frame = get_or_create_table(&mut pd[virt_addr.p2_index()], phys_mem_offset, frame_allocator);
+/// The existing entry mapping `virt_addr` at `level`, 1 or 2 as for
+/// `user_page_entry`, unless a missing table or a 2 MiB page is in the way.
+fn find_user_entry<'a>(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    virt_addr: VirtAddr, level: usize,
+) -> Option<&'a mut PageTableEntry> {
+    let table = |addr: PhysAddr| -> &mut PageTable {
+        unsafe { &mut *(phys_mem_offset + addr.as_u64()).as_mut_ptr::<PageTable>() }
+    };
 
-    // PT
-    let pt = table(pt_frame);
-    let data_frame = frame_allocator.allocate_frame().expect("No more frames");
-    pt[virt_addr.p1_index()].set_addr(data_frame.start_address(), flags);
+    let pml4 = table(page_table_frame.start_address());
+    let mut entry = &mut pml4[virt_addr.p4_index()];
+    for &index in &[virt_addr.p3_index(), virt_addr.p2_index(), virt_addr.p1_index()][..4 - level] {
+        if entry.is_unused() || entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+            return None;
+        }
+        entry = &mut table(entry.addr())[index];
+    }
+    (!entry.is_unused()).then_some(entry)
+}
 
-    data_frame
-    
-}
\ No newline at end of file
+/// Removes the mapping at `virt_addr`, returning the frame it pointed to.
+/// Page tables are left in place. The caller flushes the TLB if the address
+/// space is active.
+pub fn unmap_user_page(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    virt_addr: VirtAddr,
+) -> Option<PhysFrame> {
+    let entry = find_user_entry(page_table_frame, phys_mem_offset, virt_addr, 1)?;
+    let frame = PhysFrame::containing_address(entry.addr());
+    entry.set_unused();
+    Some(frame)
+}
+
+/// Removes the 2 MiB page at `virt_addr`, if one is mapped there, like
+/// `unmap_user_page`.
+pub fn unmap_user_huge_page(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    virt_addr: VirtAddr,
+) -> Option<PhysFrame<Size2MiB>> {
+    let entry = find_user_entry(page_table_frame, phys_mem_offset, virt_addr, 2)?;
+    if !entry.flags().contains(PageTableFlags::HUGE_PAGE) {
+        return None;
+    }
+    let frame = PhysFrame::containing_address(entry.addr());
+    entry.set_unused();
+    Some(frame)
+}
+
+/// Clears the dirty bit of the page at `virt_addr`, returning its frame if
+/// it was set. The caller flushes the TLB, or writes through a cached entry
+/// would not set it again.
+pub fn take_dirty_user_page(page_table_frame: PhysFrame, phys_mem_offset: VirtAddr,
+    virt_addr: VirtAddr,
+) -> Option<PhysFrame> {
+    let entry = find_user_entry(page_table_frame, phys_mem_offset, virt_addr, 1)?;
+    if !entry.flags().contains(PageTableFlags::DIRTY) {
+        return None;
+    }
+    entry.set_flags(entry.flags() - PageTableFlags::DIRTY);
+    Some(PhysFrame::containing_address(entry.addr()))
+}
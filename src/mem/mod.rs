@@ -1,2 +1,9 @@
 pub mod allocator;
-pub mod memory;
\ No newline at end of file
+pub mod anon;
+pub mod filemap;
+pub mod aslr;
+pub mod kstack;
+pub mod memory;
+pub mod shm;
+pub mod slab;
+pub mod vmalloc;
\ No newline at end of file
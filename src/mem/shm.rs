@@ -0,0 +1,283 @@
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageTableFlags, PhysFrame};
+use x86_64::VirtAddr;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::arch::pcid;
+use crate::arch::protection;
+use crate::fs::vfs::File;
+use crate::mem::memory::{self, FRAME_ALLOCATOR};
+use crate::proc::errno::Errno;
+use crate::proc::process::{ProcessMemory, RegionKind, SharedMapping};
+use crate::proc::scheduler::ProcessManager;
+
+/// `shm_open` flags, as for `open`.
+pub const O_CREAT: u64 = 0o100;
+pub const O_EXCL: u64 = 0o200;
+
+/// Largest object `ftruncate` will size, 4 MiB.
+pub const MAX_SHM_SIZE: usize = 4 << 20;
+
+/// Where `mmap` places shared objects, a level 4 entry of its own so the
+/// mappings never land in tables shared with the kernel. Each process
+/// starts at its own randomized base inside it.
+pub const MMAP_BASE: u64 = 0x6000_0000_0000;
+pub const MMAP_END: u64 = 0x6080_0000_0000;
+
+/// Named objects. The name holds one reference until `shm_unlink`.
+static SHM_OBJECTS: IrqLock<BTreeMap<String, Arc<SharedMemory>>> =
+    IrqLock::new("SHM_OBJECTS", BTreeMap::new());
+
+/// Physical frames mapped into every process that `mmap`s the object. They
+/// go back to the frame allocator once the name, the last descriptor and
+/// the last mapping are gone.
+pub struct SharedMemory {
+    /// Empty until sized by `ftruncate`.
+    frames: IrqLock<Vec<PhysFrame>>,
+}
+
+impl SharedMemory {
+    fn new() -> Self {
+        SharedMemory { frames: IrqLock::new("SHM", Vec::new()) }
+    }
+
+    pub fn size(&self) -> usize {
+        self.frames.lock().len() * 4096
+    }
+
+    pub fn frames(&self) -> Vec<PhysFrame> {
+        self.frames.lock().clone()
+    }
+
+    /// Gives a new object `size` bytes of zeroed memory. The size is fixed
+    /// from then on.
+    pub fn set_size(&self, size: usize) -> Result<(), Errno> {
+        if size > MAX_SHM_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        let pages = size.div_ceil(4096);
+        let mut frames = self.frames.lock();
+        if frames.len() == pages {
+            return Ok(());
+        }
+        if !frames.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for _ in 0..pages {
+            let Some(frame) = allocator.allocate_frame() else {
+                for frame in frames.drain(..) {
+                    unsafe { allocator.deallocate_frame(frame) };
+                }
+                return Err(Errno::ENOMEM);
+            };
+            unsafe { core::ptr::write_bytes(page_ptr(frame), 0, 4096) };
+            frames.push(frame);
+        }
+        Ok(())
+    }
+
+    /// Calls `copy` on each piece of the object in `[offset, offset + len)`,
+    /// clamped to its size, with the piece's position in that range.
+    /// Returns the number of bytes covered.
+    fn for_each_chunk(&self, offset: usize, len: usize, mut copy: impl FnMut(&mut [u8], usize)) -> usize {
+        let frames = self.frames.lock();
+        let end = offset.saturating_add(len).min(frames.len() * 4096);
+
+        let mut position = offset;
+        while position < end {
+            let start = position % 4096;
+            let count = (4096 - start).min(end - position);
+            let chunk = unsafe {
+                core::slice::from_raw_parts_mut(page_ptr(frames[position / 4096]).add(start), count)
+            };
+            copy(chunk, position - offset);
+            position += count;
+        }
+        end.saturating_sub(offset)
+    }
+}
+
+impl Drop for SharedMemory {
+    fn drop(&mut self) {
+        let frames = core::mem::take(&mut *self.frames.lock());
+        if frames.is_empty() {
+            return;
+        }
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for frame in frames {
+            unsafe { allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+fn page_ptr(frame: PhysFrame) -> *mut u8 {
+    let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+    (phys_mem_offset + frame.start_address().as_u64()) as *mut u8
+}
+
+/// A descriptor returned by `shm_open`.
+struct ShmFile(Arc<SharedMemory>);
+
+impl File for ShmFile {
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        Ok(self.0.for_each_chunk(offset, buf.len(), |chunk, at| {
+            buf[at..at + chunk.len()].copy_from_slice(chunk);
+        }))
+    }
+
+    fn write(&self, offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        Ok(self.0.for_each_chunk(offset, buf.len(), |chunk, at| {
+            chunk.copy_from_slice(&buf[at..at + chunk.len()]);
+        }))
+    }
+
+    fn truncate(&self, size: usize) -> Result<(), Errno> {
+        self.0.set_size(size)
+    }
+
+    fn mmap(&self) -> Result<Arc<SharedMemory>, Errno> {
+        Ok(self.0.clone())
+    }
+
+    fn size(&self) -> Result<usize, Errno> {
+        Ok(self.0.size())
+    }
+
+    fn cache_key(&self) -> Option<usize> {
+        Some(Arc::as_ptr(&self.0) as usize)
+    }
+}
+
+/// `shm_open`: opens the object `name`, with or without a leading slash,
+/// creating it empty under `O_CREAT`.
+pub fn open(name: &str, flags: u64) -> Result<Arc<dyn File>, Errno> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    if name.is_empty() || name.contains('/') {
+        return Err(Errno::EINVAL);
+    }
+
+    let mut objects = SHM_OBJECTS.lock();
+    let object = match objects.get(name) {
+        Some(_) if flags & O_CREAT != 0 && flags & O_EXCL != 0 => return Err(Errno::EEXIST),
+        Some(object) => object.clone(),
+        None if flags & O_CREAT != 0 => {
+            let object = Arc::new(SharedMemory::new());
+            objects.insert(String::from(name), object.clone());
+            object
+        }
+        None => return Err(Errno::ENOENT),
+    };
+    Ok(Arc::new(ShmFile(object)))
+}
+
+/// `shm_unlink`: removes the name. Existing descriptors and mappings keep
+/// the object alive.
+pub fn unlink(name: &str) -> Result<(), Errno> {
+    let name = name.strip_prefix('/').unwrap_or(name);
+    let removed = SHM_OBJECTS.lock().remove(name);
+    removed.map(drop).ok_or(Errno::ENOENT)
+}
+
+impl ProcessManager {
+    /// Maps `length` bytes of `object` from `offset` into `pid`, returning
+    /// the address chosen.
+    pub fn map_shared(
+        &mut self,
+        pid: u32,
+        object: Arc<SharedMemory>,
+        offset: usize,
+        length: usize,
+        writable: bool,
+    ) -> Result<VirtAddr, Errno> {
+        if length == 0 || offset % 4096 != 0 {
+            return Err(Errno::EINVAL);
+        }
+        let pages = length.div_ceil(4096);
+        let frames = object.frames();
+        let first = offset / 4096;
+        if first.checked_add(pages).filter(|&last| last <= frames.len()).is_none() {
+            return Err(Errno::EINVAL);
+        }
+
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        process.limits.check_pages(process.memory.pages_allocated() + pages)?;
+        let base = process.memory.mmap_base.as_u64();
+        let start = process.memory.find_free_range(base, MMAP_END, pages).ok_or(Errno::ENOMEM)?;
+
+        let mut flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | protection::no_execute();
+        if writable {
+            flags |= PageTableFlags::WRITABLE;
+        }
+        let page_table = PhysFrame::containing_address(process.memory.page_table_addr);
+        let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+
+        let mut allocator = FRAME_ALLOCATOR.lock();
+        for (index, &frame) in frames[first..first + pages].iter().enumerate() {
+            let addr = start + index as u64 * 4096;
+            if let Err(err) = memory::map_user_frame(page_table, phys_mem_offset, &mut *allocator, addr, frame, flags) {
+                for undo in 0..index {
+                    memory::unmap_user_page(page_table, phys_mem_offset, start + undo as u64 * 4096);
+                }
+                return Err(err);
+            }
+        }
+        drop(allocator);
+
+        let end = start + pages as u64 * 4096;
+        process.memory.add_region(start, end, flags, RegionKind::Shared);
+        process.memory.shared.push(SharedMapping { start, object });
+        Ok(start)
+    }
+
+    /// `munmap`: removes a whole shared mapping of `pid` starting at `start`.
+    pub fn unmap_shared(&mut self, pid: u32, start: VirtAddr, length: usize) -> Result<(), Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        let index = process.memory.shared.iter()
+            .position(|mapping| mapping.start == start)
+            .ok_or(Errno::EINVAL)?;
+        let pages = process.memory.regions().iter()
+            .find(|region| region.start == start)
+            .map(|region| ((region.end - region.start) / 4096) as usize)
+            .ok_or(Errno::EINVAL)?;
+        if length.div_ceil(4096) != pages {
+            return Err(Errno::EINVAL);
+        }
+
+        unmap_pages(&process.memory, start, pages);
+        process.memory.remove_region(start);
+        process.memory.shared.remove(index);
+        Ok(())
+    }
+
+    /// Drops every shared mapping of `pid`, e.g. when it exits.
+    pub(crate) fn unmap_all_shared(&mut self, pid: u32) {
+        let Some(process) = self.processes.get_mut(&pid) else {
+            return;
+        };
+        for mapping in core::mem::take(&mut process.memory.shared) {
+            if let Some(region) = process.memory.remove_region(mapping.start) {
+                let pages = ((region.end - region.start) / 4096) as usize;
+                unmap_pages(&process.memory, region.start, pages);
+            }
+        }
+    }
+}
+
+fn unmap_pages(memory: &ProcessMemory, start: VirtAddr, pages: usize) {
+    let page_table = PhysFrame::containing_address(memory.page_table_addr);
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let active = Cr3::read().0 == page_table;
+
+    for index in 0..pages {
+        let addr = start + index as u64 * 4096;
+        memory::unmap_user_page(page_table, phys_mem_offset, addr);
+        pcid::flush_page(memory.pcid, addr, active);
+    }
+}
@@ -0,0 +1,149 @@
+use alloc::alloc::Layout;
+use core::ptr::NonNull;
+use linked_list_allocator::Heap;
+
+/// Block sizes the caches serve. Anything bigger, or aligned more
+/// strictly, goes to the heap as whole pages.
+pub const SIZE_CLASSES: [usize; 9] = [8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+/// What a cache takes from the heap at a time and carves into blocks.
+pub const SLAB_SIZE: usize = 4096;
+
+/// A free block, holding the link to the next one.
+struct FreeBlock {
+    next: Option<NonNull<FreeBlock>>,
+}
+
+/// One cache's counts.
+#[derive(Debug, Clone, Copy)]
+pub struct CacheStats {
+    pub block_size: usize,
+    /// Pages carved so far. They stay with the cache once carved.
+    pub slabs: usize,
+    pub in_use: usize,
+    pub free: usize,
+}
+
+struct SlabCache {
+    block_size: usize,
+    free_list: Option<NonNull<FreeBlock>>,
+    slabs: usize,
+    in_use: usize,
+    free: usize,
+}
+
+// The free list only points into the heap, which the lock around the
+// allocator guards
+unsafe impl Send for SlabCache {}
+
+impl SlabCache {
+    const fn new(block_size: usize) -> Self {
+        SlabCache { block_size, free_list: None, slabs: 0, in_use: 0, free: 0 }
+    }
+
+    /// Carves a fresh page from `heap` into free blocks.
+    fn refill(&mut self, heap: &mut Heap) -> bool {
+        let layout = Layout::from_size_align(SLAB_SIZE, SLAB_SIZE).unwrap();
+        let Ok(page) = heap.allocate_first_fit(layout) else {
+            return false;
+        };
+        for offset in (0..SLAB_SIZE).step_by(self.block_size).rev() {
+            let block = unsafe { page.as_ptr().add(offset) } as *mut FreeBlock;
+            unsafe { block.write(FreeBlock { next: self.free_list }) };
+            self.free_list = NonNull::new(block);
+        }
+        self.slabs += 1;
+        self.free += SLAB_SIZE / self.block_size;
+        true
+    }
+
+    fn allocate(&mut self, heap: &mut Heap) -> Option<NonNull<u8>> {
+        if self.free_list.is_none() && !self.refill(heap) {
+            return None;
+        }
+        let block = self.free_list?;
+        self.free_list = unsafe { block.as_ref().next };
+        self.free -= 1;
+        self.in_use += 1;
+        Some(block.cast())
+    }
+
+    /// # Safety
+    /// `ptr` must have come from this cache and not be freed already.
+    unsafe fn deallocate(&mut self, ptr: NonNull<u8>) {
+        let block = ptr.cast::<FreeBlock>();
+        block.as_ptr().write(FreeBlock { next: self.free_list });
+        self.free_list = Some(block);
+        self.in_use -= 1;
+        self.free += 1;
+    }
+}
+
+/// Per-size caches in front of the heap. Small objects of one size are
+/// taken from and returned to a free list in constant time, where the heap
+/// would walk its holes for each.
+pub struct SlabAllocator {
+    caches: [SlabCache; SIZE_CLASSES.len()],
+}
+
+impl SlabAllocator {
+    pub const fn new() -> Self {
+        SlabAllocator {
+            caches: [
+                SlabCache::new(SIZE_CLASSES[0]),
+                SlabCache::new(SIZE_CLASSES[1]),
+                SlabCache::new(SIZE_CLASSES[2]),
+                SlabCache::new(SIZE_CLASSES[3]),
+                SlabCache::new(SIZE_CLASSES[4]),
+                SlabCache::new(SIZE_CLASSES[5]),
+                SlabCache::new(SIZE_CLASSES[6]),
+                SlabCache::new(SIZE_CLASSES[7]),
+                SlabCache::new(SIZE_CLASSES[8]),
+            ],
+        }
+    }
+
+    /// The cache for `layout`, if one serves it. Blocks sit at multiples of
+    /// their power-of-two size within an aligned page, so a block is
+    /// aligned to its size.
+    fn class(layout: Layout) -> Option<usize> {
+        let size = layout.size().max(layout.align());
+        SIZE_CLASSES.iter().position(|&class| class >= size)
+    }
+
+    /// Large requests are rounded to whole pages, so the holes they leave
+    /// in the heap can take a slab or another large block.
+    fn page_layout(layout: Layout) -> Layout {
+        Layout::from_size_align(layout.size().next_multiple_of(SLAB_SIZE), layout.align().max(SLAB_SIZE)).unwrap()
+    }
+
+    pub fn allocate(&mut self, heap: &mut Heap, layout: Layout) -> Option<NonNull<u8>> {
+        match Self::class(layout) {
+            Some(class) => self.caches[class].allocate(heap),
+            None => heap.allocate_first_fit(Self::page_layout(layout)).ok(),
+        }
+    }
+
+    /// # Safety
+    /// `ptr` must have come from `allocate` with the same `heap` and
+    /// `layout`, and not be freed already.
+    pub unsafe fn deallocate(&mut self, heap: &mut Heap, ptr: NonNull<u8>, layout: Layout) {
+        match Self::class(layout) {
+            Some(class) => self.caches[class].deallocate(ptr),
+            None => heap.deallocate(ptr, Self::page_layout(layout)),
+        }
+    }
+
+    /// Bytes sitting in free lists, counted as used by the heap.
+    pub fn cached(&self) -> usize {
+        self.caches.iter().map(|cache| cache.free * cache.block_size).sum()
+    }
+
+    pub fn stats(&self) -> [CacheStats; SIZE_CLASSES.len()] {
+        self.caches.each_ref().map(|cache| CacheStats {
+            block_size: cache.block_size,
+            slabs: cache.slabs,
+            in_use: cache.in_use,
+            free: cache.free,
+        })
+    }
+}
@@ -0,0 +1,208 @@
+use alloc::collections::BTreeMap;
+use core::ops::{Deref, DerefMut};
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::VirtAddr;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::arch::pcid;
+use crate::arch::protection;
+use crate::mem::memory::{self, FRAME_ALLOCATOR};
+use crate::proc::errno::Errno;
+
+/// Start of the vmalloc region. It shares its top-level entry with the
+/// kernel stacks, which is in place before the first address space copies
+/// the kernel's, so every address space sees what is mapped here.
+pub const VMALLOC_START: u64 = 0xFFFF_FF40_0000_0000;
+/// Virtual space the region spans.
+pub const VMALLOC_SIZE: usize = 64 << 30;
+
+struct VmallocState {
+    /// First page never handed out, as an offset into the region.
+    next: usize,
+    /// Released ranges below `next`, first page to length in pages, merged
+    /// with their neighbours.
+    free: BTreeMap<usize, usize>,
+    /// Live allocations, first page to length in pages without the guard.
+    used: BTreeMap<usize, usize>,
+}
+
+static VMALLOC: IrqLock<VmallocState> = IrqLock::new("VMALLOC", VmallocState {
+    next: 0,
+    free: BTreeMap::new(),
+    used: BTreeMap::new(),
+});
+
+/// vmalloc region usage, in bytes.
+#[derive(Debug, Clone, Copy)]
+pub struct VmallocStats {
+    /// Mapped bytes, not counting guard pages.
+    pub used: usize,
+    pub allocations: usize,
+}
+
+impl VmallocState {
+    /// Takes `pages` pages of virtual space, first fit.
+    fn reserve(&mut self, pages: usize) -> Option<usize> {
+        let fit = self.free.iter().find(|&(_, &len)| len >= pages).map(|(&start, &len)| (start, len));
+        if let Some((start, len)) = fit {
+            self.free.remove(&start);
+            if len > pages {
+                self.free.insert(start + pages, len - pages);
+            }
+            return Some(start);
+        }
+        if self.next + pages > VMALLOC_SIZE / 4096 {
+            return None;
+        }
+        self.next += pages;
+        Some(self.next - pages)
+    }
+
+    /// Gives back `pages` pages from `start`.
+    fn release(&mut self, mut start: usize, mut pages: usize) {
+        if let Some((&before, &len)) = self.free.range(..start).next_back() {
+            if before + len == start {
+                self.free.remove(&before);
+                start = before;
+                pages += len;
+            }
+        }
+        if let Some(len) = self.free.remove(&(start + pages)) {
+            pages += len;
+        }
+        if start + pages == self.next {
+            self.next = start;
+        } else {
+            self.free.insert(start, pages);
+        }
+    }
+}
+
+fn page_addr(page: usize) -> VirtAddr {
+    VirtAddr::new(VMALLOC_START + page as u64 * 4096)
+}
+
+/// Maps `size` bytes of zeroed memory, rounded up to whole pages, at a
+/// kernel address of its own. The frames behind it need not be contiguous,
+/// so large buffers don't depend on the heap having room. An unmapped
+/// guard page follows each allocation.
+pub fn vmalloc(size: usize) -> Result<VirtAddr, Errno> {
+    if size == 0 {
+        return Err(Errno::EINVAL);
+    }
+    let pages = size.div_ceil(4096);
+    let first = VMALLOC.lock().reserve(pages + 1).ok_or(Errno::ENOMEM)?;
+
+    if let Err(err) = map(first, pages) {
+        VMALLOC.lock().release(first, pages + 1);
+        return Err(err);
+    }
+    VMALLOC.lock().used.insert(first, pages);
+    Ok(page_addr(first))
+}
+
+/// Unmaps an allocation made by `vmalloc` and frees its frames.
+pub fn vfree(addr: VirtAddr) -> Result<(), Errno> {
+    let offset = addr.as_u64().checked_sub(VMALLOC_START).ok_or(Errno::EINVAL)?;
+    if offset % 4096 != 0 {
+        return Err(Errno::EINVAL);
+    }
+    let first = (offset / 4096) as usize;
+    let pages = VMALLOC.lock().used.remove(&first).ok_or(Errno::EINVAL)?;
+
+    unmap(first, pages);
+    VMALLOC.lock().release(first, pages + 1);
+    Ok(())
+}
+
+pub fn stats() -> VmallocStats {
+    let state = VMALLOC.lock();
+    VmallocStats {
+        used: state.used.values().sum::<usize>() * 4096,
+        allocations: state.used.len(),
+    }
+}
+
+/// Backs `pages` pages from `first` with fresh zeroed frames, undoing it
+/// all on failure.
+fn map(first: usize, pages: usize) -> Result<(), Errno> {
+    let mut mapper = unsafe { memory::kernel_mapper() };
+    let mut frames = FRAME_ALLOCATOR.lock();
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | protection::no_execute();
+    let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+
+    for index in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(page_addr(first + index));
+        let result = frames.allocate_frame().ok_or(Errno::ENOMEM).and_then(|frame| {
+            unsafe { core::ptr::write_bytes((phys_mem_offset + frame.start_address().as_u64()) as *mut u8, 0, 4096) };
+            match unsafe { mapper.map_to(page, frame, flags, &mut *frames) } {
+                Ok(flush) => {
+                    flush.flush();
+                    Ok(())
+                }
+                Err(_) => {
+                    unsafe { frames.deallocate_frame(frame) };
+                    Err(Errno::ENOMEM)
+                }
+            }
+        });
+        if let Err(err) = result {
+            drop(frames);
+            unmap(first, index);
+            return Err(err);
+        }
+    }
+    Ok(())
+}
+
+/// Unmaps `pages` pages from `first` and frees their frames. The page
+/// tables stay, for the next allocation there.
+fn unmap(first: usize, pages: usize) {
+    let mut mapper = unsafe { memory::kernel_mapper() };
+    let mut frames = FRAME_ALLOCATOR.lock();
+    for index in 0..pages {
+        let page = Page::<Size4KiB>::containing_address(page_addr(first + index));
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.ignore();
+            unsafe { frames.deallocate_frame(frame) };
+        }
+    }
+    // Any address space may have cached the mappings
+    pcid::flush_all();
+}
+
+/// A `vmalloc` buffer, freed when dropped.
+pub struct VmallocBuffer {
+    addr: VirtAddr,
+    len: usize,
+}
+
+impl VmallocBuffer {
+    pub fn new(len: usize) -> Result<Self, Errno> {
+        Ok(VmallocBuffer { addr: vmalloc(len)?, len })
+    }
+
+    pub fn addr(&self) -> VirtAddr {
+        self.addr
+    }
+}
+
+impl Deref for VmallocBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.addr.as_ptr(), self.len) }
+    }
+}
+
+impl DerefMut for VmallocBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        unsafe { core::slice::from_raw_parts_mut(self.addr.as_mut_ptr(), self.len) }
+    }
+}
+
+impl Drop for VmallocBuffer {
+    fn drop(&mut self) {
+        let _ = vfree(self.addr);
+    }
+}
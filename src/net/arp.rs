@@ -0,0 +1,115 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::net::ethernet::{ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use crate::net::interface::Interface;
+
+pub const OP_REQUEST: u16 = 1;
+pub const OP_REPLY: u16 = 2;
+
+const HTYPE_ETHERNET: u16 = 1;
+const PACKET_SIZE: usize = 28;
+
+/// Packets held while their next hop is being resolved. Older ones are
+/// dropped first; TCP sends them again.
+const MAX_PENDING: usize = 8;
+
+/// Learned MAC addresses. Entries never expire: the hosts on QEMU's
+/// network don't move.
+pub struct ArpCache {
+    entries: BTreeMap<Ipv4Addr, [u8; 6]>,
+    /// IPv4 packets waiting for a reply, with their next hop.
+    pending: Vec<(Ipv4Addr, Vec<u8>)>,
+}
+
+impl ArpCache {
+    pub const fn new() -> Self {
+        ArpCache { entries: BTreeMap::new(), pending: Vec::new() }
+    }
+
+    pub fn lookup(&self, addr: Ipv4Addr) -> Option<[u8; 6]> {
+        self.entries.get(&addr).copied()
+    }
+
+    pub fn insert(&mut self, addr: Ipv4Addr, mac: [u8; 6]) {
+        self.entries.insert(addr, mac);
+    }
+
+    /// Holds `packet` until `next_hop` is resolved, returning whether it is
+    /// the first one waiting for it, so a request is due.
+    pub fn hold(&mut self, next_hop: Ipv4Addr, packet: Vec<u8>) -> bool {
+        let first = !self.pending.iter().any(|(addr, _)| *addr == next_hop);
+        if self.pending.len() >= MAX_PENDING {
+            self.pending.remove(0);
+        }
+        self.pending.push((next_hop, packet));
+        first
+    }
+
+    /// Takes the packets waiting for `next_hop`.
+    pub fn take_pending(&mut self, next_hop: Ipv4Addr) -> Vec<Vec<u8>> {
+        let (ready, waiting): (Vec<_>, Vec<_>) =
+            core::mem::take(&mut self.pending).into_iter().partition(|(addr, _)| *addr == next_hop);
+        self.pending = waiting;
+        ready.into_iter().map(|(_, packet)| packet).collect()
+    }
+
+    /// Every entry, for `/proc/net`.
+    pub fn entries(&self) -> impl Iterator<Item = (&Ipv4Addr, &[u8; 6])> {
+        self.entries.iter()
+    }
+}
+
+/// An ARP packet for IPv4 over Ethernet.
+pub fn build(op: u16, sha: [u8; 6], spa: Ipv4Addr, tha: [u8; 6], tpa: Ipv4Addr) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(PACKET_SIZE);
+    packet.extend_from_slice(&HTYPE_ETHERNET.to_be_bytes());
+    packet.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+    packet.push(6);
+    packet.push(4);
+    packet.extend_from_slice(&op.to_be_bytes());
+    packet.extend_from_slice(&sha);
+    packet.extend_from_slice(&spa.octets());
+    packet.extend_from_slice(&tha);
+    packet.extend_from_slice(&tpa.octets());
+    packet
+}
+
+fn mac_at(packet: &[u8], offset: usize) -> [u8; 6] {
+    let mut mac = [0; 6];
+    mac.copy_from_slice(&packet[offset..offset + 6]);
+    mac
+}
+
+fn addr_at(packet: &[u8], offset: usize) -> Ipv4Addr {
+    Ipv4Addr::new(packet[offset], packet[offset + 1], packet[offset + 2], packet[offset + 3])
+}
+
+/// Learns the sender of a packet meant for us, answers a request and sends
+/// whatever was waiting for the sender's address.
+pub fn handle(iface: &mut Interface, packet: &[u8]) {
+    if packet.len() < PACKET_SIZE
+        || u16::from_be_bytes([packet[0], packet[1]]) != HTYPE_ETHERNET
+        || u16::from_be_bytes([packet[2], packet[3]]) != ETHERTYPE_IPV4
+    {
+        iface.stats.rx_dropped += 1;
+        return;
+    }
+    let op = u16::from_be_bytes([packet[6], packet[7]]);
+    let sha = mac_at(packet, 8);
+    let spa = addr_at(packet, 14);
+    let tpa = addr_at(packet, 24);
+    if iface.addr.is_unspecified() || tpa != iface.addr {
+        return;
+    }
+
+    iface.arp.insert(spa, sha);
+    if op == OP_REQUEST {
+        let reply = build(OP_REPLY, iface.mac, iface.addr, sha, spa);
+        let _ = iface.transmit(sha, ETHERTYPE_ARP, &reply);
+    }
+    for packet in iface.arp.take_pending(spa) {
+        let _ = iface.transmit(sha, ETHERTYPE_IPV4, &packet);
+    }
+}
@@ -0,0 +1,43 @@
+use alloc::vec::Vec;
+
+use crate::net::{arp, ipv4, NetStack};
+
+pub const ETHERTYPE_IPV4: u16 = 0x0800;
+pub const ETHERTYPE_ARP: u16 = 0x0806;
+
+pub const BROADCAST: [u8; 6] = [0xFF; 6];
+
+pub const HEADER_SIZE: usize = 14;
+/// Shortest frame without the checksum; the card doesn't pad for us.
+const MIN_FRAME_SIZE: usize = 60;
+
+/// A frame carrying `payload`.
+pub fn build(dst: [u8; 6], src: [u8; 6], ethertype: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity((HEADER_SIZE + payload.len()).max(MIN_FRAME_SIZE));
+    frame.extend_from_slice(&dst);
+    frame.extend_from_slice(&src);
+    frame.extend_from_slice(&ethertype.to_be_bytes());
+    frame.extend_from_slice(payload);
+    if frame.len() < MIN_FRAME_SIZE {
+        frame.resize(MIN_FRAME_SIZE, 0);
+    }
+    frame
+}
+
+/// Hands a received frame to its protocol, if it is for us.
+pub fn handle(net: &mut NetStack, frame: &[u8]) {
+    if frame.len() < HEADER_SIZE {
+        net.iface.stats.rx_dropped += 1;
+        return;
+    }
+    let dst = &frame[0..6];
+    if dst != net.iface.mac && dst != BROADCAST {
+        return;
+    }
+    let payload = &frame[HEADER_SIZE..];
+    match u16::from_be_bytes([frame[12], frame[13]]) {
+        ETHERTYPE_ARP => arp::handle(&mut net.iface, payload),
+        ETHERTYPE_IPV4 => ipv4::handle(net, payload),
+        _ => net.iface.stats.rx_dropped += 1,
+    }
+}
@@ -0,0 +1,26 @@
+use crate::net::interface::Interface;
+use crate::net::ipv4::{self, Ipv4Header, PROTO_ICMP};
+
+const ECHO_REPLY: u8 = 0;
+const ECHO_REQUEST: u8 = 8;
+
+const HEADER_SIZE: usize = 8;
+
+/// Answers pings. Nothing else ICMP carries is acted on.
+pub fn handle(iface: &mut Interface, header: &Ipv4Header, message: &[u8]) {
+    if message.len() < HEADER_SIZE || ipv4::checksum(message) != 0 {
+        iface.stats.rx_dropped += 1;
+        return;
+    }
+    if message[0] != ECHO_REQUEST || header.dst.is_broadcast() {
+        return;
+    }
+
+    // Same identifier, sequence number and data, as RFC 792 asks
+    let mut reply = message.to_vec();
+    reply[0] = ECHO_REPLY;
+    reply[2..4].copy_from_slice(&[0, 0]);
+    let checksum = ipv4::checksum(&reply);
+    reply[2..4].copy_from_slice(&checksum.to_be_bytes());
+    let _ = iface.send_ipv4(header.dst, header.src, PROTO_ICMP, &reply);
+}
@@ -0,0 +1,143 @@
+use alloc::sync::Arc;
+use core::net::Ipv4Addr;
+
+use crate::fs::vfs::File;
+use crate::net::{now, Endpoint, Socket, SocketAddr, SocketType, NET};
+use crate::proc::errno::Errno;
+
+const IPPROTO_TCP: u64 = 6;
+const IPPROTO_UDP: u64 = 17;
+
+/// An `AF_INET` socket descriptor: a handle on a TCP or UDP socket in
+/// `NET`, which outlives it while a connection finishes closing.
+pub struct InetSocket {
+    kind: SocketType,
+    id: u32,
+}
+
+/// Creates an unbound `AF_INET` socket.
+pub fn socket(kind: SocketType, protocol: u64) -> Result<Arc<dyn File>, Errno> {
+    let id = match (kind, protocol) {
+        (SocketType::Stream, 0 | IPPROTO_TCP) => NET.lock().tcp.create(),
+        (SocketType::Datagram, 0 | IPPROTO_UDP) => NET.lock().udp.create(),
+        _ => return Err(Errno::EPROTONOSUPPORT),
+    };
+    Ok(Arc::new(InetSocket { kind, id }))
+}
+
+fn endpoint(addr: &SocketAddr) -> Result<Endpoint, Errno> {
+    match addr {
+        SocketAddr::Inet(addr, port) => Ok((*addr, *port)),
+        SocketAddr::Unix(_) => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+impl Socket for InetSocket {
+    fn kind(&self) -> SocketType {
+        self.kind
+    }
+
+    /// Port 0 picks an ephemeral port.
+    fn bind(&self, addr: &SocketAddr) -> Result<(), Errno> {
+        let (addr, port) = endpoint(addr)?;
+        let mut net = NET.lock();
+        let net = &mut *net;
+        match self.kind {
+            SocketType::Stream => net.tcp.bind(&net.iface, self.id, addr, port),
+            SocketType::Datagram => net.udp.bind(&net.iface, self.id, addr, port),
+        }
+    }
+
+    fn listen(&self, backlog: usize) -> Result<(), Errno> {
+        if self.kind != SocketType::Stream {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let mut net = NET.lock();
+        let net = &mut *net;
+        net.tcp.listen(&net.iface, self.id, backlog)
+    }
+
+    fn accept(&self) -> Result<(Arc<dyn File>, SocketAddr), Errno> {
+        if self.kind != SocketType::Stream {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let (id, (addr, port)) = NET.lock().tcp.accept(self.id)?;
+        Ok((Arc::new(InetSocket { kind: SocketType::Stream, id }), SocketAddr::Inet(addr, port)))
+    }
+
+    /// A stream socket sleeps until the handshake is done.
+    fn connect(&self, addr: &SocketAddr) -> Result<(), Errno> {
+        let remote = endpoint(addr)?;
+        let mut net = NET.lock();
+        let net = &mut *net;
+        match self.kind {
+            SocketType::Stream => net.tcp.connect(&mut net.iface, self.id, remote, now()),
+            SocketType::Datagram => net.udp.connect(&net.iface, self.id, remote),
+        }
+    }
+
+    /// A stream socket ignores `to`, like Linux does.
+    fn send(&self, buf: &[u8], to: Option<&SocketAddr>, nonblocking: bool) -> Result<usize, Errno> {
+        let to = to.map(endpoint).transpose()?;
+        let mut net = NET.lock();
+        let net = &mut *net;
+        match self.kind {
+            SocketType::Stream => net.tcp.send(&mut net.iface, self.id, buf, nonblocking, now()),
+            SocketType::Datagram => net.udp.send(&mut net.iface, self.id, buf, to),
+        }
+    }
+
+    fn recv(&self, buf: &mut [u8], nonblocking: bool) -> Result<(usize, SocketAddr), Errno> {
+        let mut net = NET.lock();
+        let net = &mut *net;
+        let (count, (addr, port)) = match self.kind {
+            SocketType::Stream => {
+                let count = net.tcp.recv(&mut net.iface, self.id, buf, nonblocking)?;
+                (count, net.tcp.remote(self.id).unwrap_or((Ipv4Addr::UNSPECIFIED, 0)))
+            }
+            SocketType::Datagram => net.udp.recv(self.id, buf, nonblocking)?,
+        };
+        Ok((count, SocketAddr::Inet(addr, port)))
+    }
+}
+
+impl File for InetSocket {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.recv(buf, false).map(|(count, _)| count)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.send(buf, None, false)
+    }
+
+    fn poll(&self) -> u16 {
+        let net = NET.lock();
+        match self.kind {
+            SocketType::Stream => net.tcp.poll(self.id),
+            SocketType::Datagram => net.udp.poll(self.id),
+        }
+    }
+
+    fn poll_wait(&self, pid: u32) {
+        let mut net = NET.lock();
+        match self.kind {
+            SocketType::Stream => net.tcp.poll_wait(self.id, pid),
+            SocketType::Datagram => net.udp.poll_wait(self.id, pid),
+        }
+    }
+
+    fn socket(&self) -> Result<&dyn Socket, Errno> {
+        Ok(self)
+    }
+}
+
+impl Drop for InetSocket {
+    fn drop(&mut self) {
+        let mut net = NET.lock();
+        let net = &mut *net;
+        match self.kind {
+            SocketType::Stream => net.tcp.close(&mut net.iface, self.id, now()),
+            SocketType::Datagram => net.udp.remove(self.id),
+        }
+    }
+}
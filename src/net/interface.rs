@@ -0,0 +1,158 @@
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::net::arp::{self, ArpCache};
+use crate::net::ethernet::{self, ETHERTYPE_ARP, ETHERTYPE_IPV4};
+use crate::net::ipv4;
+use crate::net::NetDevice;
+use crate::proc::errno::Errno;
+
+/// The MAC address QEMU gives its first card, which its user-mode network
+/// expects at 10.0.2.15.
+const QEMU_MAC: [u8; 6] = [0x52, 0x54, 0x00, 0x12, 0x34, 0x56];
+
+/// Packets sent to ourselves and not handled yet.
+const LOOPBACK_CAPACITY: usize = 64;
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NetStats {
+    pub rx_frames: u64,
+    pub tx_frames: u64,
+    pub rx_dropped: u64,
+    pub tx_errors: u64,
+}
+
+/// The one network interface: the card, if there is one, its addresses and
+/// the loopback queue that carries packets sent to 127.0.0.0/8 or to
+/// ourselves.
+pub struct Interface {
+    device: Option<Box<dyn NetDevice>>,
+    pub mac: [u8; 6],
+    pub addr: Ipv4Addr,
+    pub netmask: Ipv4Addr,
+    pub gateway: Ipv4Addr,
+    pub arp: ArpCache,
+    loopback: VecDeque<Vec<u8>>,
+    /// Identification of the next IPv4 packet sent.
+    next_id: u16,
+    pub stats: NetStats,
+}
+
+impl Interface {
+    pub const fn new() -> Self {
+        Interface {
+            device: None,
+            mac: [0; 6],
+            addr: Ipv4Addr::UNSPECIFIED,
+            netmask: Ipv4Addr::UNSPECIFIED,
+            gateway: Ipv4Addr::UNSPECIFIED,
+            arp: ArpCache::new(),
+            loopback: VecDeque::new(),
+            next_id: 0,
+            stats: NetStats { rx_frames: 0, tx_frames: 0, rx_dropped: 0, tx_errors: 0 },
+        }
+    }
+
+    /// Takes `device` as the card. With no DHCP client the address is
+    /// static: the one QEMU's user-mode network hands out to its first
+    /// card, or one derived from the MAC address for any other.
+    pub fn attach(&mut self, device: Box<dyn NetDevice>) {
+        self.mac = device.mac();
+        self.addr = if self.mac == QEMU_MAC {
+            Ipv4Addr::new(10, 0, 2, 15)
+        } else {
+            Ipv4Addr::new(10, 0, 2, self.mac[5].clamp(16, 254))
+        };
+        self.netmask = Ipv4Addr::new(255, 255, 255, 0);
+        self.gateway = Ipv4Addr::new(10, 0, 2, 2);
+        self.device = Some(device);
+    }
+
+    pub fn has_device(&self) -> bool {
+        self.device.is_some()
+    }
+
+    /// Whether packets to `addr` are for us.
+    pub fn is_local(&self, addr: Ipv4Addr) -> bool {
+        addr.is_loopback() || (!self.addr.is_unspecified() && addr == self.addr)
+    }
+
+    fn on_link(&self, addr: Ipv4Addr) -> bool {
+        let mask = u32::from(self.netmask);
+        u32::from(addr) & mask == u32::from(self.addr) & mask
+    }
+
+    /// The address packets to `dst` are sent from.
+    pub fn source_for(&self, dst: Ipv4Addr) -> Ipv4Addr {
+        if dst.is_loopback() || self.addr.is_unspecified() {
+            Ipv4Addr::LOCALHOST
+        } else {
+            self.addr
+        }
+    }
+
+    /// The next frame from the card.
+    pub fn receive(&mut self) -> Option<Vec<u8>> {
+        let frame = self.device.as_mut()?.receive()?;
+        self.stats.rx_frames += 1;
+        Some(frame)
+    }
+
+    pub fn take_loopback(&mut self) -> Option<Vec<u8>> {
+        self.loopback.pop_front()
+    }
+
+    /// Sends `payload` in one frame to `dst`.
+    pub fn transmit(&mut self, dst: [u8; 6], ethertype: u16, payload: &[u8]) -> Result<(), Errno> {
+        let frame = ethernet::build(dst, self.mac, ethertype, payload);
+        let device = self.device.as_mut().ok_or(Errno::ENETUNREACH)?;
+        match device.transmit(&frame) {
+            Ok(()) => {
+                self.stats.tx_frames += 1;
+                Ok(())
+            }
+            Err(err) => {
+                self.stats.tx_errors += 1;
+                Err(err)
+            }
+        }
+    }
+
+    /// Sends an IPv4 packet. A packet for ourselves goes round the
+    /// loopback queue; one for a host whose MAC address is unknown waits
+    /// for the ARP reply.
+    pub fn send_ipv4(&mut self, src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, payload: &[u8]) -> Result<(), Errno> {
+        let id = self.next_id;
+        self.next_id = id.wrapping_add(1);
+        let packet = ipv4::build(src, dst, protocol, id, payload);
+
+        if self.is_local(dst) {
+            if self.loopback.len() >= LOOPBACK_CAPACITY {
+                return Err(Errno::ENOBUFS);
+            }
+            self.loopback.push_back(packet);
+            return Ok(());
+        }
+        if self.device.is_none() || self.addr.is_unspecified() {
+            return Err(Errno::ENETUNREACH);
+        }
+        if dst.is_broadcast() {
+            return self.transmit(ethernet::BROADCAST, ETHERTYPE_IPV4, &packet);
+        }
+
+        let next_hop = if self.on_link(dst) { dst } else { self.gateway };
+        match self.arp.lookup(next_hop) {
+            Some(mac) => self.transmit(mac, ETHERTYPE_IPV4, &packet),
+            None => {
+                let first = self.arp.hold(next_hop, packet);
+                if first {
+                    let request = arp::build(arp::OP_REQUEST, self.mac, self.addr, [0; 6], next_hop);
+                    self.transmit(ethernet::BROADCAST, ETHERTYPE_ARP, &request)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::net::{icmp, now, NetStack};
+
+pub const PROTO_ICMP: u8 = 1;
+pub const PROTO_TCP: u8 = 6;
+pub const PROTO_UDP: u8 = 17;
+
+pub const HEADER_SIZE: usize = 20;
+const DEFAULT_TTL: u8 = 64;
+/// Don't fragment: we never fragment, and drop fragments we receive.
+const FLAG_DF: u16 = 0x4000;
+const FLAG_MF: u16 = 0x2000;
+const FRAGMENT_OFFSET: u16 = 0x1FFF;
+
+/// What the protocols above need from a received packet's header.
+#[derive(Debug, Clone, Copy)]
+pub struct Ipv4Header {
+    pub src: Ipv4Addr,
+    pub dst: Ipv4Addr,
+    pub protocol: u8,
+}
+
+/// Adds `data` to a running Internet checksum, as big-endian words.
+pub fn sum(data: &[u8], mut acc: u32) -> u32 {
+    let mut chunks = data.chunks_exact(2);
+    for chunk in &mut chunks {
+        acc += u16::from_be_bytes([chunk[0], chunk[1]]) as u32;
+    }
+    if let [last] = chunks.remainder() {
+        acc += (*last as u32) << 8;
+    }
+    acc
+}
+
+/// Folds a running sum into the ones' complement checksum.
+pub fn fold(mut acc: u32) -> u16 {
+    while acc >> 16 != 0 {
+        acc = (acc & 0xFFFF) + (acc >> 16);
+    }
+    !(acc as u16)
+}
+
+/// The Internet checksum of `data`. Over data that includes its own
+/// checksum, zero means it is correct.
+pub fn checksum(data: &[u8]) -> u16 {
+    fold(sum(data, 0))
+}
+
+/// The running sum of the pseudo-header UDP and TCP checksums cover.
+pub fn pseudo_sum(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, len: usize) -> u32 {
+    let acc = sum(&src.octets(), 0);
+    let acc = sum(&dst.octets(), acc);
+    acc + protocol as u32 + len as u32
+}
+
+/// A packet carrying `payload`, which must fit in one frame.
+pub fn build(src: Ipv4Addr, dst: Ipv4Addr, protocol: u8, id: u16, payload: &[u8]) -> Vec<u8> {
+    let total = (HEADER_SIZE + payload.len()) as u16;
+    let mut packet = Vec::with_capacity(total as usize);
+    packet.push(0x45);
+    packet.push(0);
+    packet.extend_from_slice(&total.to_be_bytes());
+    packet.extend_from_slice(&id.to_be_bytes());
+    packet.extend_from_slice(&FLAG_DF.to_be_bytes());
+    packet.push(DEFAULT_TTL);
+    packet.push(protocol);
+    packet.extend_from_slice(&[0, 0]);
+    packet.extend_from_slice(&src.octets());
+    packet.extend_from_slice(&dst.octets());
+    let checksum = checksum(&packet);
+    packet[10..12].copy_from_slice(&checksum.to_be_bytes());
+    packet.extend_from_slice(payload);
+    packet
+}
+
+/// Checks a packet's header, returning it with the payload. Options are
+/// skipped and fragments dropped.
+pub fn parse(packet: &[u8]) -> Option<(Ipv4Header, &[u8])> {
+    if packet.len() < HEADER_SIZE || packet[0] >> 4 != 4 {
+        return None;
+    }
+    let header_len = (packet[0] & 0xF) as usize * 4;
+    let total = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+    if header_len < HEADER_SIZE || total < header_len || total > packet.len() {
+        return None;
+    }
+    if checksum(&packet[..header_len]) != 0 {
+        return None;
+    }
+    let flags = u16::from_be_bytes([packet[6], packet[7]]);
+    if flags & FLAG_MF != 0 || flags & FRAGMENT_OFFSET != 0 {
+        return None;
+    }
+
+    let header = Ipv4Header {
+        src: Ipv4Addr::new(packet[12], packet[13], packet[14], packet[15]),
+        dst: Ipv4Addr::new(packet[16], packet[17], packet[18], packet[19]),
+        protocol: packet[9],
+    };
+    // Ethernet padding follows short packets
+    Some((header, &packet[header_len..total]))
+}
+
+/// Hands a received packet to its protocol, if it is for us.
+pub fn handle(net: &mut NetStack, packet: &[u8]) {
+    let Some((header, payload)) = parse(packet) else {
+        net.iface.stats.rx_dropped += 1;
+        return;
+    };
+    if !net.iface.is_local(header.dst) && !header.dst.is_broadcast() {
+        return;
+    }
+    match header.protocol {
+        PROTO_ICMP => icmp::handle(&mut net.iface, &header, payload),
+        PROTO_UDP => net.udp.handle(&header, payload),
+        PROTO_TCP => net.tcp.handle(&mut net.iface, &header, payload, now()),
+        _ => net.iface.stats.rx_dropped += 1,
+    }
+}
@@ -0,0 +1,230 @@
+pub mod arp;
+pub mod ethernet;
+pub mod icmp;
+pub mod inet;
+pub mod interface;
+pub mod ipv4;
+pub mod tcp;
+pub mod udp;
+pub mod unix;
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+use core::sync::atomic::Ordering;
+
+use crate::arch::interrupts::INTERRUPT_COUNTS;
+use crate::arch::irq_lock::IrqLock;
+use crate::drivers::virtio_net;
+use crate::fs::vfs::File;
+use crate::proc::errno::Errno;
+
+use interface::Interface;
+use tcp::TcpTable;
+use udp::UdpTable;
+
+pub const AF_UNIX: u64 = 1;
+pub const AF_INET: u64 = 2;
+pub const SOCK_STREAM: u64 = 1;
+pub const SOCK_DGRAM: u64 = 2;
+
+/// Size of the largest address, `struct sockaddr_un`.
+pub const SOCKADDR_MAX_SIZE: usize = 110;
+/// Size of `struct sockaddr_in`.
+const SOCKADDR_IN_SIZE: usize = 16;
+
+/// Most pending connections `listen` lets a socket queue.
+pub const SOMAXCONN: usize = 16;
+
+/// Frames and loopback packets handled per poll, so a flood can't keep the
+/// timer interrupt busy.
+const POLL_BUDGET: usize = 64;
+
+/// Start of the ephemeral port range, as IANA suggests.
+pub const EPHEMERAL_PORT_START: u16 = 49152;
+
+/// An IPv4 address and port.
+pub type Endpoint = (Ipv4Addr, u16);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+    Stream,
+    Datagram,
+}
+
+/// A socket address, as carried by `struct sockaddr_un` or
+/// `struct sockaddr_in`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SocketAddr {
+    /// A local socket's name, stored like ramfs names without the leading
+    /// slash. `None` for an unbound socket.
+    Unix(Option<String>),
+    Inet(Ipv4Addr, u16),
+}
+
+impl SocketAddr {
+    /// Parses a `struct sockaddr_*`. A local path ends at the first NUL or
+    /// the end of `bytes`.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Errno> {
+        if bytes.len() < 2 || bytes.len() > SOCKADDR_MAX_SIZE {
+            return Err(Errno::EINVAL);
+        }
+        match u16::from_ne_bytes([bytes[0], bytes[1]]) as u64 {
+            AF_UNIX => {
+                let path = &bytes[2..];
+                let end = path.iter().position(|&byte| byte == 0).unwrap_or(path.len());
+                let path = core::str::from_utf8(&path[..end]).map_err(|_| Errno::EINVAL)?;
+                let name = path.strip_prefix('/').unwrap_or(path);
+                if name.is_empty() {
+                    return Err(Errno::EINVAL);
+                }
+                Ok(SocketAddr::Unix(Some(String::from(name))))
+            }
+            AF_INET if bytes.len() >= 8 => {
+                let port = u16::from_be_bytes([bytes[2], bytes[3]]);
+                Ok(SocketAddr::Inet(Ipv4Addr::new(bytes[4], bytes[5], bytes[6], bytes[7]), port))
+            }
+            AF_INET => Err(Errno::EINVAL),
+            _ => Err(Errno::EAFNOSUPPORT),
+        }
+    }
+
+    /// The address as a `struct sockaddr_*`, just the family for an
+    /// unbound local socket.
+    pub fn encode(&self) -> Vec<u8> {
+        match self {
+            SocketAddr::Unix(name) => {
+                let mut bytes = Vec::from((AF_UNIX as u16).to_ne_bytes());
+                if let Some(name) = name {
+                    bytes.push(b'/');
+                    bytes.extend_from_slice(name.as_bytes());
+                    bytes.push(0);
+                }
+                bytes
+            }
+            SocketAddr::Inet(addr, port) => {
+                let mut bytes = Vec::with_capacity(SOCKADDR_IN_SIZE);
+                bytes.extend_from_slice(&(AF_INET as u16).to_ne_bytes());
+                bytes.extend_from_slice(&port.to_be_bytes());
+                bytes.extend_from_slice(&addr.octets());
+                bytes.resize(SOCKADDR_IN_SIZE, 0);
+                bytes
+            }
+        }
+    }
+}
+
+/// What the socket system calls do, for every address family. Calls that
+/// block put the caller to sleep and return `ERESTART`, like pipe reads.
+pub trait Socket {
+    fn kind(&self) -> SocketType;
+
+    fn bind(&self, addr: &SocketAddr) -> Result<(), Errno>;
+
+    /// Lets a bound stream socket accept connections, at most `backlog`
+    /// of them waiting at a time.
+    fn listen(&self, backlog: usize) -> Result<(), Errno>;
+
+    /// Takes the oldest pending connection, with the address of its peer.
+    fn accept(&self) -> Result<(Arc<dyn File>, SocketAddr), Errno>;
+
+    /// Connects a stream socket, or sets a datagram socket's default
+    /// destination.
+    fn connect(&self, addr: &SocketAddr) -> Result<(), Errno>;
+
+    /// Sends `buf` to the peer, or as one datagram to `to` or the default
+    /// destination. Stream sends may be partial.
+    fn send(&self, buf: &[u8], to: Option<&SocketAddr>, nonblocking: bool) -> Result<usize, Errno>;
+
+    /// Receives into `buf`, returning the count and the sender's address.
+    /// The part of a datagram that doesn't fit in `buf` is discarded.
+    fn recv(&self, buf: &mut [u8], nonblocking: bool) -> Result<(usize, SocketAddr), Errno>;
+}
+
+/// `socket(domain, type, protocol)`: creates an unbound socket.
+pub fn socket(domain: u64, kind: u64, protocol: u64) -> Result<Arc<dyn File>, Errno> {
+    if domain != AF_UNIX && domain != AF_INET {
+        return Err(Errno::EAFNOSUPPORT);
+    }
+    let kind = match kind {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Datagram,
+        _ => return Err(Errno::EINVAL),
+    };
+    match domain {
+        AF_UNIX => unix::socket(kind, protocol),
+        _ => inet::socket(kind, protocol),
+    }
+}
+
+/// A network card, as the stack sees it: whole Ethernet frames in and out.
+pub trait NetDevice: Send {
+    fn mac(&self) -> [u8; 6];
+
+    fn transmit(&mut self, frame: &[u8]) -> Result<(), Errno>;
+
+    /// The next frame received, if any.
+    fn receive(&mut self) -> Option<Vec<u8>>;
+}
+
+/// The interface and the protocol state of every inet socket, behind one
+/// lock.
+pub struct NetStack {
+    pub iface: Interface,
+    pub udp: UdpTable,
+    pub tcp: TcpTable,
+}
+
+pub static NET: IrqLock<NetStack> = IrqLock::new("NET", NetStack {
+    iface: Interface::new(),
+    udp: UdpTable::new(),
+    tcp: TcpTable::new(),
+});
+
+/// The clock protocol timers run on, in timer ticks. Read without the
+/// scheduler lock, which may be held while a socket is closed.
+pub fn now() -> u64 {
+    INTERRUPT_COUNTS.timer.load(Ordering::Relaxed)
+}
+
+/// Brings up the network card, if there is one. Loopback works either way.
+pub fn init() -> Result<(), Errno> {
+    let device = virtio_net::init()?;
+    NET.lock().iface.attach(Box::new(device));
+    Ok(())
+}
+
+/// Handles received frames and packets sent to ourselves, then runs the
+/// TCP timers. Called on every timer tick.
+pub fn poll() {
+    let mut net = NET.lock();
+    let net = &mut *net;
+
+    for _ in 0..POLL_BUDGET {
+        let Some(frame) = net.iface.receive() else {
+            break;
+        };
+        ethernet::handle(net, &frame);
+    }
+    for _ in 0..POLL_BUDGET {
+        let Some(packet) = net.iface.take_loopback() else {
+            break;
+        };
+        ipv4::handle(net, &packet);
+    }
+    net.tcp.on_tick(&mut net.iface, now());
+}
+
+/// Picks a free port from the ephemeral range, starting at `next`.
+pub(crate) fn ephemeral_port(next: &mut u16, in_use: impl Fn(u16) -> bool) -> Result<u16, Errno> {
+    for _ in EPHEMERAL_PORT_START..=u16::MAX {
+        let port = *next;
+        *next = port.checked_add(1).unwrap_or(EPHEMERAL_PORT_START);
+        if !in_use(port) {
+            return Ok(port);
+        }
+    }
+    Err(Errno::EADDRINUSE)
+}
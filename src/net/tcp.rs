@@ -0,0 +1,912 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::drivers::pit::TIMER_HZ;
+use crate::fs::poll::{POLLERR, POLLHUP, POLLIN, POLLOUT};
+use crate::net::interface::Interface;
+use crate::net::ipv4::{self, Ipv4Header, PROTO_TCP};
+use crate::net::{ephemeral_port, Endpoint, EPHEMERAL_PORT_START, SOMAXCONN};
+use crate::proc::errno::Errno;
+use crate::proc::wait::WaitQueue;
+
+const FIN: u8 = 0x01;
+const SYN: u8 = 0x02;
+const RST: u8 = 0x04;
+const PSH: u8 = 0x08;
+const ACK: u8 = 0x10;
+
+pub const HEADER_SIZE: usize = 20;
+/// Largest segment that fits in one Ethernet frame, which we announce.
+const MSS: usize = 1500 - ipv4::HEADER_SIZE - HEADER_SIZE;
+/// What a peer that doesn't announce its MSS takes, per RFC 1122.
+const DEFAULT_MSS: usize = 536;
+const MSS_OPTION: [u8; 4] = [2, 4, (MSS >> 8) as u8, MSS as u8];
+
+/// Size of each socket's send and receive buffer, and so the largest
+/// window we advertise.
+const BUFFER_SIZE: usize = 8192;
+
+const INITIAL_RTO: u64 = TIMER_HZ;
+const MAX_RTO: u64 = 16 * TIMER_HZ;
+/// Timeouts in a row before the connection is given up on.
+const MAX_RETRIES: u32 = 8;
+/// How long TIME-WAIT lasts. Far less than the 2 MSL RFC 793 asks for, as
+/// nothing here reuses a connection's ports that quickly.
+const TIME_WAIT: u64 = 2 * TIMER_HZ;
+/// How long a closed socket waits for the peer's FIN, as Linux's
+/// `tcp_fin_timeout`.
+const FIN_WAIT_TIMEOUT: u64 = 60 * TIMER_HZ;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TcpState {
+    Closed,
+    Listen,
+    SynSent,
+    SynReceived,
+    Established,
+    FinWait1,
+    FinWait2,
+    CloseWait,
+    Closing,
+    LastAck,
+    TimeWait,
+}
+
+/// `a` comes before `b` in sequence space.
+fn seq_lt(a: u32, b: u32) -> bool {
+    (a.wrapping_sub(b) as i32) < 0
+}
+
+/// The fields of a received segment that matter here.
+struct Segment<'a> {
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    flags: u8,
+    window: u16,
+    mss: Option<usize>,
+    payload: &'a [u8],
+}
+
+impl Segment<'_> {
+    /// The sequence space the segment takes: its data, SYN and FIN.
+    fn len(&self) -> u32 {
+        self.payload.len() as u32 + (self.flags & SYN != 0) as u32 + (self.flags & FIN != 0) as u32
+    }
+}
+
+fn parse<'a>(header: &Ipv4Header, data: &'a [u8]) -> Option<Segment<'a>> {
+    if data.len() < HEADER_SIZE {
+        return None;
+    }
+    let offset = (data[12] >> 4) as usize * 4;
+    if offset < HEADER_SIZE || offset > data.len() {
+        return None;
+    }
+    if ipv4::fold(ipv4::sum(data, ipv4::pseudo_sum(header.src, header.dst, PROTO_TCP, data.len()))) != 0 {
+        return None;
+    }
+
+    let mut mss = None;
+    let mut i = HEADER_SIZE;
+    while i < offset {
+        match data[i] {
+            0 => break,
+            1 => i += 1,
+            kind => {
+                let len = *data.get(i + 1)? as usize;
+                if len < 2 || i + len > offset {
+                    break;
+                }
+                // An MSS of 0 would leave us unable to send, so it counts
+                // as none announced
+                if kind == 2 && len == 4 {
+                    mss = Some(u16::from_be_bytes([data[i + 2], data[i + 3]]) as usize).filter(|&mss| mss > 0);
+                }
+                i += len;
+            }
+        }
+    }
+
+    Some(Segment {
+        src_port: u16::from_be_bytes([data[0], data[1]]),
+        dst_port: u16::from_be_bytes([data[2], data[3]]),
+        seq: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+        ack: u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+        flags: data[13],
+        window: u16::from_be_bytes([data[14], data[15]]),
+        mss,
+        payload: &data[offset..],
+    })
+}
+
+/// A segment from `src` to `dst`. A SYN carries our MSS.
+fn build(src: Endpoint, dst: Endpoint, seq: u32, ack: u32, flags: u8, window: u16, payload: &[u8]) -> Vec<u8> {
+    let options: &[u8] = if flags & SYN != 0 { &MSS_OPTION } else { &[] };
+    let header_len = HEADER_SIZE + options.len();
+    let len = header_len + payload.len();
+
+    let mut segment = Vec::with_capacity(len);
+    segment.extend_from_slice(&src.1.to_be_bytes());
+    segment.extend_from_slice(&dst.1.to_be_bytes());
+    segment.extend_from_slice(&seq.to_be_bytes());
+    segment.extend_from_slice(&ack.to_be_bytes());
+    segment.push(((header_len / 4) << 4) as u8);
+    segment.push(flags);
+    segment.extend_from_slice(&window.to_be_bytes());
+    segment.extend_from_slice(&[0, 0, 0, 0]);
+    segment.extend_from_slice(options);
+    segment.extend_from_slice(payload);
+
+    let checksum = ipv4::fold(ipv4::sum(&segment, ipv4::pseudo_sum(src.0, dst.0, PROTO_TCP, len)));
+    segment[16..18].copy_from_slice(&checksum.to_be_bytes());
+    segment
+}
+
+/// Answers `segment` with a reset, as RFC 793 does for a segment no
+/// connection wants. A reset is never answered.
+fn send_reset(iface: &mut Interface, local: Endpoint, remote: Endpoint, segment: &Segment) {
+    if segment.flags & RST != 0 {
+        return;
+    }
+    let reset = if segment.flags & ACK != 0 {
+        build(local, remote, segment.ack, 0, RST, 0, &[])
+    } else {
+        build(local, remote, 0, segment.seq.wrapping_add(segment.len()), RST | ACK, 0, &[])
+    };
+    let _ = iface.send_ipv4(local.0, remote.0, PROTO_TCP, &reset);
+}
+
+/// A connection's transmission control block.
+struct Tcb {
+    state: TcpState,
+    local: Option<Endpoint>,
+    remote: Option<Endpoint>,
+
+    iss: u32,
+    /// Oldest sequence number not acknowledged.
+    snd_una: u32,
+    /// Next sequence number to send. Goes back to `snd_una` on a timeout.
+    snd_nxt: u32,
+    snd_wnd: u32,
+    rcv_nxt: u32,
+    /// Largest segment the peer takes.
+    mss: usize,
+
+    /// Data from `snd_una` on, sent or not.
+    send_buffer: VecDeque<u8>,
+    recv_buffer: VecDeque<u8>,
+    /// The owner closed the socket: a FIN follows the data.
+    fin_queued: bool,
+    /// The FIN is out, at `snd_nxt - 1`.
+    fin_sent: bool,
+    fin_received: bool,
+    /// Why the connection failed.
+    error: Option<Errno>,
+
+    /// Connections established and not yet accepted, for a listener.
+    backlog: VecDeque<u32>,
+    backlog_capacity: usize,
+    /// The listener a connection not accepted yet belongs to.
+    parent: Option<u32>,
+    /// `connect` started the handshake and hasn't returned its outcome.
+    connecting: bool,
+    /// The owner closed the socket; it is freed once closed.
+    orphaned: bool,
+
+    retransmit_at: Option<u64>,
+    rto: u64,
+    retries: u32,
+    /// When TIME-WAIT, or FIN-WAIT-2 for an orphan, ends.
+    time_wait_until: u64,
+
+    /// Sleepers in `recv` and `accept`.
+    readers: WaitQueue,
+    /// Sleepers in `send` and `connect`.
+    writers: WaitQueue,
+}
+
+impl Tcb {
+    fn new() -> Self {
+        Tcb {
+            state: TcpState::Closed,
+            local: None,
+            remote: None,
+            iss: 0,
+            snd_una: 0,
+            snd_nxt: 0,
+            snd_wnd: 0,
+            rcv_nxt: 0,
+            mss: DEFAULT_MSS,
+            send_buffer: VecDeque::new(),
+            recv_buffer: VecDeque::new(),
+            fin_queued: false,
+            fin_sent: false,
+            fin_received: false,
+            error: None,
+            backlog: VecDeque::new(),
+            backlog_capacity: 0,
+            parent: None,
+            connecting: false,
+            orphaned: false,
+            retransmit_at: None,
+            rto: INITIAL_RTO,
+            retries: 0,
+            time_wait_until: 0,
+            readers: WaitQueue::new(),
+            writers: WaitQueue::new(),
+        }
+    }
+
+    /// Whether both ends know each other's sequence numbers, so ACKs and
+    /// resets may be sent.
+    fn synchronized(&self) -> bool {
+        !matches!(self.state, TcpState::Closed | TcpState::Listen | TcpState::SynSent)
+    }
+
+    fn window(&self) -> u16 {
+        (BUFFER_SIZE - self.recv_buffer.len()).min(u16::MAX as usize) as u16
+    }
+
+    fn segment(&self, iface: &mut Interface, seq: u32, flags: u8, payload: &[u8]) -> Result<(), Errno> {
+        let (Some(local), Some(remote)) = (self.local, self.remote) else {
+            return Err(Errno::ENOTCONN);
+        };
+        let ack = if flags & ACK != 0 { self.rcv_nxt } else { 0 };
+        let segment = build(local, remote, seq, ack, flags, self.window(), payload);
+        iface.send_ipv4(local.0, remote.0, PROTO_TCP, &segment)
+    }
+
+    fn send_ack(&self, iface: &mut Interface) {
+        let _ = self.segment(iface, self.snd_nxt, ACK, &[]);
+    }
+
+    fn arm(&mut self, now: u64) {
+        if self.retransmit_at.is_none() {
+            self.retransmit_at = Some(now + self.rto);
+        }
+    }
+
+    fn enter_time_wait(&mut self, now: u64) {
+        self.state = TcpState::TimeWait;
+        self.time_wait_until = now + TIME_WAIT;
+        self.retransmit_at = None;
+    }
+
+    /// Fails the connection with `error`, waking everyone waiting on it.
+    fn abort(&mut self, error: Errno) {
+        self.state = TcpState::Closed;
+        self.error = Some(error);
+        self.retransmit_at = None;
+        self.send_buffer.clear();
+        self.readers.wake_all();
+        self.writers.wake_all();
+    }
+
+    /// Sends whatever the window allows, go-back-N style, then the FIN
+    /// once all data is out. A zero window is probed with one byte.
+    fn output(&mut self, iface: &mut Interface, now: u64) {
+        let sending = matches!(
+            self.state,
+            TcpState::Established | TcpState::CloseWait | TcpState::FinWait1 | TcpState::Closing | TcpState::LastAck
+        );
+        if !sending || self.fin_sent {
+            return;
+        }
+
+        loop {
+            let in_flight = self.snd_nxt.wrapping_sub(self.snd_una) as usize;
+            let unsent = self.send_buffer.len() - in_flight;
+            if unsent == 0 {
+                break;
+            }
+            let window = (self.snd_wnd as usize).saturating_sub(in_flight);
+            let len = if window == 0 && in_flight == 0 { 1 } else { unsent.min(window).min(self.mss) };
+            if len == 0 {
+                break;
+            }
+
+            let payload: Vec<u8> = self.send_buffer.range(in_flight..in_flight + len).copied().collect();
+            // A segment lost here is sent again on the timeout
+            let _ = self.segment(iface, self.snd_nxt, ACK | PSH, &payload);
+            self.snd_nxt = self.snd_nxt.wrapping_add(len as u32);
+            self.arm(now);
+        }
+
+        if self.fin_queued && self.snd_nxt.wrapping_sub(self.snd_una) as usize == self.send_buffer.len() {
+            let _ = self.segment(iface, self.snd_nxt, FIN | ACK, &[]);
+            self.snd_nxt = self.snd_nxt.wrapping_add(1);
+            self.fin_sent = true;
+            self.arm(now);
+        }
+    }
+
+    /// Handles the reply to our SYN.
+    fn on_syn_sent(&mut self, iface: &mut Interface, segment: &Segment) {
+        let has_ack = segment.flags & ACK != 0;
+        let ack_ok = has_ack && segment.ack == self.snd_nxt;
+        if has_ack && !ack_ok {
+            send_reset(iface, self.local.unwrap(), self.remote.unwrap(), segment);
+            return;
+        }
+        if segment.flags & RST != 0 {
+            if ack_ok {
+                self.abort(Errno::ECONNREFUSED);
+            }
+            return;
+        }
+        if segment.flags & SYN == 0 {
+            return;
+        }
+
+        self.rcv_nxt = segment.seq.wrapping_add(1);
+        self.snd_wnd = segment.window as u32;
+        self.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(MSS);
+        if ack_ok {
+            self.snd_una = segment.ack;
+            self.state = TcpState::Established;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.rto = INITIAL_RTO;
+            self.send_ack(iface);
+            self.writers.wake_all();
+        } else {
+            // Both ends opened at once
+            self.state = TcpState::SynReceived;
+            let _ = self.segment(iface, self.iss, SYN | ACK, &[]);
+        }
+    }
+
+    /// Handles a segment for a connection past `Listen`.
+    fn on_segment(&mut self, iface: &mut Interface, segment: &Segment, now: u64) {
+        match self.state {
+            TcpState::SynSent => return self.on_syn_sent(iface, segment),
+            TcpState::Closed | TcpState::Listen => return,
+            _ => {}
+        }
+
+        // Keep only what continues the stream: retransmitted bytes are cut
+        // off, and segments further ahead dropped for the peer to resend.
+        let mut payload = segment.payload;
+        let mut fin = segment.flags & FIN != 0;
+        let mut ack_due = false;
+        if seq_lt(segment.seq, self.rcv_nxt) {
+            let old = self.rcv_nxt.wrapping_sub(segment.seq) as usize;
+            if old > payload.len() {
+                fin = false;
+            }
+            payload = &payload[old.min(payload.len())..];
+            ack_due = payload.is_empty() && !fin && segment.len() > 0;
+        } else if segment.seq != self.rcv_nxt {
+            payload = &[];
+            fin = false;
+            ack_due = segment.len() > 0;
+        }
+
+        if segment.flags & RST != 0 {
+            // Only an exact match, so a guessed reset can't kill us
+            if segment.seq == self.rcv_nxt {
+                self.abort(Errno::ECONNRESET);
+            }
+            return;
+        }
+        if segment.flags & SYN != 0 {
+            // A retransmitted SYN or SYN-ACK: our ACK was lost
+            self.send_ack(iface);
+            return;
+        }
+        if segment.flags & ACK == 0 {
+            return;
+        }
+
+        if self.state == TcpState::SynReceived {
+            if segment.ack != self.snd_nxt {
+                send_reset(iface, self.local.unwrap(), self.remote.unwrap(), segment);
+                return;
+            }
+            self.snd_una = segment.ack;
+            self.state = TcpState::Established;
+            self.retransmit_at = None;
+            self.retries = 0;
+            self.rto = INITIAL_RTO;
+        }
+
+        if seq_lt(self.snd_nxt, segment.ack) {
+            // Acknowledges something never sent
+            self.send_ack(iface);
+            return;
+        }
+        self.snd_wnd = segment.window as u32;
+        if seq_lt(self.snd_una, segment.ack) {
+            let acked = segment.ack.wrapping_sub(self.snd_una) as usize;
+            let data = acked.min(self.send_buffer.len());
+            self.send_buffer.drain(..data);
+            self.snd_una = segment.ack;
+            self.retries = 0;
+            self.rto = INITIAL_RTO;
+            self.retransmit_at = (self.snd_una != self.snd_nxt).then(|| now + self.rto);
+            self.writers.wake_all();
+
+            if self.fin_sent && self.snd_una == self.snd_nxt {
+                match self.state {
+                    TcpState::FinWait1 => {
+                        self.state = TcpState::FinWait2;
+                        self.time_wait_until = now + FIN_WAIT_TIMEOUT;
+                    }
+                    TcpState::Closing => self.enter_time_wait(now),
+                    TcpState::LastAck => {
+                        self.state = TcpState::Closed;
+                        return;
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let receiving = matches!(self.state, TcpState::Established | TcpState::FinWait1 | TcpState::FinWait2);
+        if !payload.is_empty() && receiving {
+            let count = payload.len().min(BUFFER_SIZE - self.recv_buffer.len());
+            self.recv_buffer.extend(&payload[..count]);
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(count as u32);
+            if count < payload.len() {
+                fin = false;
+            }
+            self.readers.wake_all();
+            ack_due = true;
+        }
+
+        if fin && receiving {
+            self.rcv_nxt = self.rcv_nxt.wrapping_add(1);
+            self.fin_received = true;
+            self.readers.wake_all();
+            ack_due = true;
+            match self.state {
+                TcpState::Established => self.state = TcpState::CloseWait,
+                TcpState::FinWait1 => self.state = TcpState::Closing,
+                _ => self.enter_time_wait(now),
+            }
+        } else if segment.flags & FIN != 0 && self.state == TcpState::TimeWait {
+            // Our last ACK was lost
+            self.enter_time_wait(now);
+            ack_due = true;
+        }
+
+        if ack_due {
+            self.send_ack(iface);
+        }
+        self.output(iface, now);
+    }
+
+    fn on_tick(&mut self, iface: &mut Interface, now: u64) {
+        match self.state {
+            TcpState::TimeWait if now >= self.time_wait_until => {
+                self.state = TcpState::Closed;
+                return;
+            }
+            TcpState::FinWait2 if self.orphaned && now >= self.time_wait_until => {
+                self.state = TcpState::Closed;
+                return;
+            }
+            _ => {}
+        }
+
+        match self.retransmit_at {
+            Some(at) if now >= at => {}
+            _ => return,
+        }
+        self.retries += 1;
+        if self.retries > MAX_RETRIES {
+            self.abort(Errno::ETIMEDOUT);
+            return;
+        }
+        self.rto = (self.rto * 2).min(MAX_RTO);
+        self.retransmit_at = Some(now + self.rto);
+        match self.state {
+            TcpState::SynSent => {
+                let _ = self.segment(iface, self.iss, SYN, &[]);
+            }
+            TcpState::SynReceived => {
+                let _ = self.segment(iface, self.iss, SYN | ACK, &[]);
+            }
+            _ => {
+                self.snd_nxt = self.snd_una;
+                self.fin_sent = false;
+                self.retransmit_at = None;
+                self.output(iface, now);
+            }
+        }
+    }
+}
+
+/// Every TCP socket and connection, by id. Connections a listener accepts
+/// get ids of their own.
+pub struct TcpTable {
+    sockets: BTreeMap<u32, Tcb>,
+    next_id: u32,
+    next_port: u16,
+    /// Added to the clock for each initial sequence number, so two taken
+    /// in one tick differ.
+    iss_offset: u32,
+}
+
+impl TcpTable {
+    pub const fn new() -> Self {
+        TcpTable { sockets: BTreeMap::new(), next_id: 1, next_port: EPHEMERAL_PORT_START, iss_offset: 0 }
+    }
+
+    fn insert(&mut self, tcb: Tcb) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sockets.insert(id, tcb);
+        id
+    }
+
+    pub fn create(&mut self) -> u32 {
+        self.insert(Tcb::new())
+    }
+
+    fn socket(&mut self, id: u32) -> Result<&mut Tcb, Errno> {
+        self.sockets.get_mut(&id).ok_or(Errno::EBADF)
+    }
+
+    /// An initial sequence number, from a clock running at about the
+    /// 250 kHz RFC 793 suggests.
+    fn next_iss(&mut self, now: u64) -> u32 {
+        self.iss_offset = self.iss_offset.wrapping_add(64_000);
+        (now as u32).wrapping_mul(250_000 / TIMER_HZ as u32).wrapping_add(self.iss_offset)
+    }
+
+    /// Binds to `addr`, which must be ours or unspecified, and `port`, an
+    /// ephemeral one if zero. Only listeners and other bound sockets not
+    /// connected yet hold a port against a new bind.
+    pub fn bind(&mut self, iface: &Interface, id: u32, addr: Ipv4Addr, port: u16) -> Result<(), Errno> {
+        if !addr.is_unspecified() && !iface.is_local(addr) {
+            return Err(Errno::EADDRNOTAVAIL);
+        }
+        let tcb = self.socket(id)?;
+        if tcb.local.is_some() || tcb.state != TcpState::Closed {
+            return Err(Errno::EINVAL);
+        }
+
+        let port = if port == 0 {
+            self.ephemeral_port()?
+        } else {
+            let taken = self.sockets.values().any(|tcb| {
+                let overlaps = tcb.local.map_or(false, |(bound, bound_port)| {
+                    bound_port == port && (bound == addr || bound.is_unspecified() || addr.is_unspecified())
+                });
+                overlaps && (tcb.state == TcpState::Listen || tcb.remote.is_none())
+            });
+            if taken {
+                return Err(Errno::EADDRINUSE);
+            }
+            port
+        };
+        self.socket(id)?.local = Some((addr, port));
+        Ok(())
+    }
+
+    fn ephemeral_port(&mut self) -> Result<u16, Errno> {
+        let mut next = self.next_port;
+        let sockets = &self.sockets;
+        let port = ephemeral_port(&mut next, |port| {
+            sockets.values().any(|tcb| tcb.local.map_or(false, |(_, bound)| bound == port))
+        })?;
+        self.next_port = next;
+        Ok(port)
+    }
+
+    /// Listens on the bound port, or an ephemeral one.
+    pub fn listen(&mut self, iface: &Interface, id: u32, backlog: usize) -> Result<(), Errno> {
+        match self.socket(id)?.state {
+            TcpState::Closed | TcpState::Listen => {}
+            _ => return Err(Errno::EINVAL),
+        }
+        if self.socket(id)?.local.is_none() {
+            self.bind(iface, id, Ipv4Addr::UNSPECIFIED, 0)?;
+        }
+        let tcb = self.socket(id)?;
+        tcb.state = TcpState::Listen;
+        tcb.backlog_capacity = backlog.clamp(1, SOMAXCONN);
+        Ok(())
+    }
+
+    /// Takes the oldest established connection, with its peer. With none
+    /// the caller sleeps and `ERESTART` is returned.
+    pub fn accept(&mut self, id: u32) -> Result<(u32, Endpoint), Errno> {
+        loop {
+            let listener = self.socket(id)?;
+            if listener.state != TcpState::Listen {
+                return Err(Errno::EINVAL);
+            }
+            let Some(child) = listener.backlog.pop_front() else {
+                return Err(listener.readers.sleep());
+            };
+            // Connections reset before they were accepted are gone
+            if let Some(tcb) = self.sockets.get_mut(&child) {
+                tcb.parent = None;
+                return Ok((child, tcb.remote.unwrap()));
+            }
+        }
+    }
+
+    /// Starts the handshake and sleeps until it is done; the restarted
+    /// call returns how it went.
+    pub fn connect(&mut self, iface: &mut Interface, id: u32, remote: Endpoint, now: u64) -> Result<(), Errno> {
+        let tcb = self.socket(id)?;
+        match (tcb.state, tcb.connecting) {
+            (TcpState::Closed, true) => {
+                tcb.connecting = false;
+                return Err(tcb.error.unwrap_or(Errno::ECONNREFUSED));
+            }
+            (TcpState::SynSent | TcpState::SynReceived, _) => return Err(tcb.writers.sleep()),
+            (TcpState::Listen, _) => return Err(Errno::EINVAL),
+            (TcpState::Closed, false) => {}
+            (_, true) => {
+                tcb.connecting = false;
+                return Ok(());
+            }
+            (_, false) => return Err(Errno::EISCONN),
+        }
+
+        // Like Linux, the unspecified address means this host
+        let remote = if remote.0.is_unspecified() { (Ipv4Addr::LOCALHOST, remote.1) } else { remote };
+        let bound = tcb.local;
+        let local = match bound {
+            Some((addr, port)) if addr.is_unspecified() => (iface.source_for(remote.0), port),
+            Some(local) => local,
+            None => (iface.source_for(remote.0), self.ephemeral_port()?),
+        };
+        let in_use = self.sockets.values().any(|tcb| {
+            tcb.state != TcpState::Closed && tcb.local == Some(local) && tcb.remote == Some(remote)
+        });
+        if in_use {
+            return Err(Errno::EADDRINUSE);
+        }
+
+        let iss = self.next_iss(now);
+        let tcb = self.socket(id)?;
+        tcb.local = Some(local);
+        tcb.remote = Some(remote);
+        tcb.state = TcpState::SynSent;
+        tcb.error = None;
+        tcb.iss = iss;
+        tcb.snd_una = iss;
+        tcb.snd_nxt = iss.wrapping_add(1);
+        tcb.send_buffer.clear();
+        tcb.recv_buffer.clear();
+        tcb.fin_queued = false;
+        tcb.fin_sent = false;
+        tcb.fin_received = false;
+        tcb.rto = INITIAL_RTO;
+        tcb.retries = 0;
+        if let Err(err) = tcb.segment(iface, iss, SYN, &[]) {
+            tcb.state = TcpState::Closed;
+            return Err(err);
+        }
+        tcb.retransmit_at = Some(now + tcb.rto);
+        tcb.connecting = true;
+        Err(tcb.writers.sleep())
+    }
+
+    /// Queues as much of `buf` as the send buffer takes. With no room the
+    /// caller sleeps, unless `nonblocking` asks for `EAGAIN`.
+    pub fn send(&mut self, iface: &mut Interface, id: u32, buf: &[u8], nonblocking: bool, now: u64) -> Result<usize, Errno> {
+        let tcb = self.socket(id)?;
+        match tcb.state {
+            TcpState::Established | TcpState::CloseWait => {}
+            TcpState::SynSent | TcpState::SynReceived if nonblocking => return Err(Errno::EAGAIN),
+            TcpState::SynSent | TcpState::SynReceived => return Err(tcb.writers.sleep()),
+            TcpState::Closed if tcb.error.is_some() => return Err(tcb.error.unwrap()),
+            TcpState::Closed | TcpState::Listen if tcb.remote.is_none() => return Err(Errno::ENOTCONN),
+            _ => return Err(Errno::EPIPE),
+        }
+
+        let space = BUFFER_SIZE - tcb.send_buffer.len();
+        if space == 0 {
+            if nonblocking {
+                return Err(Errno::EAGAIN);
+            }
+            return Err(tcb.writers.sleep());
+        }
+        let count = buf.len().min(space);
+        tcb.send_buffer.extend(&buf[..count]);
+        tcb.output(iface, now);
+        Ok(count)
+    }
+
+    /// Takes received data, 0 bytes meaning the peer closed. With nothing
+    /// to take the caller sleeps, unless `nonblocking` asks for `EAGAIN`.
+    pub fn recv(&mut self, iface: &mut Interface, id: u32, buf: &mut [u8], nonblocking: bool) -> Result<usize, Errno> {
+        let tcb = self.socket(id)?;
+        if !tcb.recv_buffer.is_empty() {
+            let closed_window = (tcb.window() as usize) < tcb.mss;
+            let count = buf.len().min(tcb.recv_buffer.len());
+            for (byte, received) in buf.iter_mut().zip(tcb.recv_buffer.drain(..count)) {
+                *byte = received;
+            }
+            // Tell a peer that stopped sending that there is room again
+            if closed_window && tcb.window() as usize >= tcb.mss && tcb.synchronized() {
+                tcb.send_ack(iface);
+            }
+            return Ok(count);
+        }
+
+        if tcb.fin_received {
+            return Ok(0);
+        }
+        if let Some(error) = tcb.error {
+            return Err(error);
+        }
+        match tcb.state {
+            TcpState::Listen => Err(Errno::ENOTCONN),
+            TcpState::Closed if tcb.remote.is_none() => Err(Errno::ENOTCONN),
+            TcpState::Closed => Ok(0),
+            _ if nonblocking => Err(Errno::EAGAIN),
+            _ => Err(tcb.readers.sleep()),
+        }
+    }
+
+    /// Gives the socket up. A connection sends its FIN and lingers until
+    /// the close completes; anything else goes at once, and a listener
+    /// resets the connections it hasn't handed out.
+    pub fn close(&mut self, iface: &mut Interface, id: u32, now: u64) {
+        let Some(tcb) = self.sockets.get_mut(&id) else {
+            return;
+        };
+        match tcb.state {
+            TcpState::SynReceived | TcpState::Established => {
+                tcb.state = TcpState::FinWait1;
+                tcb.fin_queued = true;
+                tcb.output(iface, now);
+            }
+            TcpState::CloseWait => {
+                tcb.state = TcpState::LastAck;
+                tcb.fin_queued = true;
+                tcb.output(iface, now);
+            }
+            TcpState::Closed | TcpState::Listen | TcpState::SynSent => {
+                self.sockets.remove(&id);
+                let children: Vec<u32> = self.sockets.iter()
+                    .filter(|(_, tcb)| tcb.parent == Some(id))
+                    .map(|(child, _)| *child)
+                    .collect();
+                for child in children {
+                    let tcb = self.sockets.remove(&child).unwrap();
+                    let _ = tcb.segment(iface, tcb.snd_nxt, RST, &[]);
+                }
+                return;
+            }
+            _ => {}
+        }
+        tcb.orphaned = true;
+    }
+
+    /// The peer of a connection.
+    pub fn remote(&self, id: u32) -> Option<Endpoint> {
+        self.sockets.get(&id).and_then(|tcb| tcb.remote)
+    }
+
+    pub fn poll(&self, id: u32) -> u16 {
+        let Some(tcb) = self.sockets.get(&id) else {
+            return POLLHUP;
+        };
+        if tcb.state == TcpState::Listen {
+            return if tcb.backlog.is_empty() { 0 } else { POLLIN };
+        }
+
+        let closed = tcb.state == TcpState::Closed && tcb.remote.is_some();
+        let mut events = 0;
+        if !tcb.recv_buffer.is_empty() || tcb.fin_received || closed {
+            events |= POLLIN;
+        }
+        let sending = matches!(tcb.state, TcpState::Established | TcpState::CloseWait);
+        if sending && tcb.send_buffer.len() < BUFFER_SIZE {
+            events |= POLLOUT;
+        }
+        if tcb.error.is_some() {
+            events |= POLLERR;
+        }
+        if closed {
+            events |= POLLHUP;
+        }
+        events
+    }
+
+    pub fn poll_wait(&mut self, id: u32, pid: u32) {
+        if let Ok(tcb) = self.socket(id) {
+            tcb.readers.add(pid);
+            tcb.writers.add(pid);
+        }
+    }
+
+    /// Hands a received segment to its connection, or to the listener on
+    /// its port. Segments nobody wants are answered with a reset.
+    pub fn handle(&mut self, iface: &mut Interface, header: &Ipv4Header, data: &[u8], now: u64) {
+        let Some(segment) = parse(header, data) else {
+            iface.stats.rx_dropped += 1;
+            return;
+        };
+        let local = (header.dst, segment.dst_port);
+        let remote = (header.src, segment.src_port);
+
+        let connection = self.sockets.iter().find(|(_, tcb)| {
+            !matches!(tcb.state, TcpState::Closed | TcpState::Listen)
+                && tcb.local == Some(local)
+                && tcb.remote == Some(remote)
+        });
+        let listener = || {
+            self.sockets.iter().find(|(_, tcb)| {
+                tcb.state == TcpState::Listen
+                    && tcb.local.map_or(false, |(addr, port)| {
+                        port == local.1 && (addr.is_unspecified() || addr == local.0)
+                    })
+            })
+        };
+        let Some(id) = connection.or_else(listener).map(|(id, _)| *id) else {
+            send_reset(iface, local, remote, &segment);
+            return;
+        };
+
+        let tcb = self.sockets.get_mut(&id).unwrap();
+        if tcb.state == TcpState::Listen {
+            self.on_listen(iface, id, local, remote, &segment, now);
+            return;
+        }
+
+        let was_pending = tcb.state == TcpState::SynReceived;
+        tcb.on_segment(iface, &segment, now);
+        let established = was_pending && tcb.synchronized() && tcb.state != TcpState::SynReceived;
+        let parent = tcb.parent;
+        if let Some(parent) = parent.filter(|_| established).and_then(|parent| self.sockets.get_mut(&parent)) {
+            parent.backlog.push_back(id);
+            parent.readers.wake_all();
+        }
+    }
+
+    /// Answers a SYN to a listener with a new connection, as long as its
+    /// backlog has room; otherwise the client tries again later.
+    fn on_listen(&mut self, iface: &mut Interface, id: u32, local: Endpoint, remote: Endpoint, segment: &Segment, now: u64) {
+        if segment.flags & RST != 0 {
+            return;
+        }
+        if segment.flags & ACK != 0 {
+            send_reset(iface, local, remote, segment);
+            return;
+        }
+        if segment.flags & SYN == 0 {
+            return;
+        }
+        let pending = self.sockets.values().filter(|tcb| tcb.parent == Some(id)).count();
+        if pending >= self.sockets[&id].backlog_capacity {
+            return;
+        }
+
+        let iss = self.next_iss(now);
+        let mut tcb = Tcb::new();
+        tcb.state = TcpState::SynReceived;
+        tcb.local = Some(local);
+        tcb.remote = Some(remote);
+        tcb.parent = Some(id);
+        tcb.iss = iss;
+        tcb.snd_una = iss;
+        tcb.snd_nxt = iss.wrapping_add(1);
+        tcb.snd_wnd = segment.window as u32;
+        tcb.rcv_nxt = segment.seq.wrapping_add(1);
+        tcb.mss = segment.mss.unwrap_or(DEFAULT_MSS).min(MSS);
+        let _ = tcb.segment(iface, iss, SYN | ACK, &[]);
+        tcb.arm(now);
+        self.insert(tcb);
+    }
+
+    /// Runs the retransmission and TIME-WAIT timers, then frees the
+    /// connections that closed with no socket left to report it.
+    pub fn on_tick(&mut self, iface: &mut Interface, now: u64) {
+        for tcb in self.sockets.values_mut() {
+            tcb.on_tick(iface, now);
+        }
+        self.sockets.retain(|_, tcb| tcb.state != TcpState::Closed || !(tcb.orphaned || tcb.parent.is_some()));
+    }
+}
@@ -0,0 +1,201 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::vec::Vec;
+use core::net::Ipv4Addr;
+
+use crate::fs::poll::{POLLIN, POLLOUT};
+use crate::net::interface::Interface;
+use crate::net::ipv4::{self, Ipv4Header, PROTO_UDP};
+use crate::net::{ephemeral_port, Endpoint, EPHEMERAL_PORT_START};
+use crate::proc::errno::Errno;
+use crate::proc::wait::WaitQueue;
+
+pub const HEADER_SIZE: usize = 8;
+/// Largest payload that fits in one Ethernet frame.
+pub const MAX_PAYLOAD: usize = 1500 - ipv4::HEADER_SIZE - HEADER_SIZE;
+/// Datagrams a socket holds; more are dropped, as UDP allows.
+const QUEUE_CAPACITY: usize = 32;
+
+struct Datagram {
+    from: Endpoint,
+    data: Vec<u8>,
+}
+
+struct UdpSocket {
+    local: Option<Endpoint>,
+    peer: Option<Endpoint>,
+    queue: VecDeque<Datagram>,
+    /// Sleepers in `recv`.
+    waiters: WaitQueue,
+}
+
+/// Every UDP socket, by id.
+pub struct UdpTable {
+    sockets: BTreeMap<u32, UdpSocket>,
+    next_id: u32,
+    next_port: u16,
+}
+
+/// A datagram from `src` to `dst` carrying `payload`.
+pub fn build(src: Endpoint, dst: Endpoint, payload: &[u8]) -> Vec<u8> {
+    let len = HEADER_SIZE + payload.len();
+    let mut datagram = Vec::with_capacity(len);
+    datagram.extend_from_slice(&src.1.to_be_bytes());
+    datagram.extend_from_slice(&dst.1.to_be_bytes());
+    datagram.extend_from_slice(&(len as u16).to_be_bytes());
+    datagram.extend_from_slice(&[0, 0]);
+    datagram.extend_from_slice(payload);
+
+    let checksum = ipv4::fold(ipv4::sum(&datagram, ipv4::pseudo_sum(src.0, dst.0, PROTO_UDP, len)));
+    // Zero means no checksum, so a computed zero is sent as all ones
+    let checksum = if checksum == 0 { 0xFFFF } else { checksum };
+    datagram[6..8].copy_from_slice(&checksum.to_be_bytes());
+    datagram
+}
+
+impl UdpTable {
+    pub const fn new() -> Self {
+        UdpTable { sockets: BTreeMap::new(), next_id: 1, next_port: EPHEMERAL_PORT_START }
+    }
+
+    pub fn create(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sockets.insert(id, UdpSocket {
+            local: None,
+            peer: None,
+            queue: VecDeque::new(),
+            waiters: WaitQueue::new(),
+        });
+        id
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        if let Some(mut socket) = self.sockets.remove(&id) {
+            socket.waiters.wake_all();
+        }
+    }
+
+    fn socket(&mut self, id: u32) -> Result<&mut UdpSocket, Errno> {
+        self.sockets.get_mut(&id).ok_or(Errno::EBADF)
+    }
+
+    fn port_in_use(&self, addr: Ipv4Addr, port: u16) -> bool {
+        self.sockets.values().filter_map(|socket| socket.local).any(|(bound, bound_port)| {
+            bound_port == port && (bound == addr || bound.is_unspecified() || addr.is_unspecified())
+        })
+    }
+
+    /// Binds to `addr`, which must be ours or unspecified, and `port`, an
+    /// ephemeral one if zero.
+    pub fn bind(&mut self, iface: &Interface, id: u32, addr: Ipv4Addr, port: u16) -> Result<(), Errno> {
+        if !addr.is_unspecified() && !iface.is_local(addr) {
+            return Err(Errno::EADDRNOTAVAIL);
+        }
+        if self.socket(id)?.local.is_some() {
+            return Err(Errno::EINVAL);
+        }
+        let port = if port == 0 {
+            let mut next = self.next_port;
+            let port = ephemeral_port(&mut next, |port| self.port_in_use(addr, port))?;
+            self.next_port = next;
+            port
+        } else if self.port_in_use(addr, port) {
+            return Err(Errno::EADDRINUSE);
+        } else {
+            port
+        };
+        self.socket(id)?.local = Some((addr, port));
+        Ok(())
+    }
+
+    /// The local endpoint, binding to an ephemeral port first if needed.
+    fn local(&mut self, iface: &Interface, id: u32) -> Result<Endpoint, Errno> {
+        if let Some(local) = self.socket(id)?.local {
+            return Ok(local);
+        }
+        self.bind(iface, id, Ipv4Addr::UNSPECIFIED, 0)?;
+        Ok(self.socket(id)?.local.unwrap())
+    }
+
+    /// Sets the default destination, and the only source datagrams are
+    /// then received from.
+    pub fn connect(&mut self, iface: &Interface, id: u32, peer: Endpoint) -> Result<(), Errno> {
+        self.local(iface, id)?;
+        self.socket(id)?.peer = Some(peer);
+        Ok(())
+    }
+
+    pub fn send(&mut self, iface: &mut Interface, id: u32, buf: &[u8], to: Option<Endpoint>) -> Result<usize, Errno> {
+        if buf.len() > MAX_PAYLOAD {
+            return Err(Errno::EMSGSIZE);
+        }
+        let dst = to.or(self.socket(id)?.peer).ok_or(Errno::EDESTADDRREQ)?;
+        let (addr, port) = self.local(iface, id)?;
+        let src = if addr.is_unspecified() { iface.source_for(dst.0) } else { addr };
+
+        let datagram = build((src, port), dst, buf);
+        iface.send_ipv4(src, dst.0, PROTO_UDP, &datagram)?;
+        Ok(buf.len())
+    }
+
+    /// Takes the oldest datagram. With none the caller sleeps and
+    /// `ERESTART` is returned, unless `nonblocking` asks for `EAGAIN`.
+    pub fn recv(&mut self, id: u32, buf: &mut [u8], nonblocking: bool) -> Result<(usize, Endpoint), Errno> {
+        let socket = self.socket(id)?;
+        let Some(datagram) = socket.queue.pop_front() else {
+            if nonblocking {
+                return Err(Errno::EAGAIN);
+            }
+            return Err(socket.waiters.sleep());
+        };
+        let count = buf.len().min(datagram.data.len());
+        buf[..count].copy_from_slice(&datagram.data[..count]);
+        Ok((count, datagram.from))
+    }
+
+    /// Sockets are always writable: the interface never blocks a sender.
+    pub fn poll(&self, id: u32) -> u16 {
+        match self.sockets.get(&id) {
+            Some(socket) if !socket.queue.is_empty() => POLLIN | POLLOUT,
+            _ => POLLOUT,
+        }
+    }
+
+    pub fn poll_wait(&mut self, id: u32, pid: u32) {
+        if let Ok(socket) = self.socket(id) {
+            socket.waiters.add(pid);
+        }
+    }
+
+    /// Queues a received datagram on the socket bound to its destination.
+    pub fn handle(&mut self, header: &Ipv4Header, datagram: &[u8]) {
+        if datagram.len() < HEADER_SIZE {
+            return;
+        }
+        let len = u16::from_be_bytes([datagram[4], datagram[5]]) as usize;
+        if len < HEADER_SIZE || len > datagram.len() {
+            return;
+        }
+        let datagram = &datagram[..len];
+        let has_checksum = datagram[6..8] != [0, 0];
+        if has_checksum && ipv4::fold(ipv4::sum(datagram, ipv4::pseudo_sum(header.src, header.dst, PROTO_UDP, len))) != 0 {
+            return;
+        }
+
+        let from = (header.src, u16::from_be_bytes([datagram[0], datagram[1]]));
+        let port = u16::from_be_bytes([datagram[2], datagram[3]]);
+        let socket = self.sockets.values_mut().find(|socket| {
+            socket.local.map_or(false, |(addr, bound_port)| {
+                bound_port == port && (addr.is_unspecified() || addr == header.dst)
+            }) && socket.peer.map_or(true, |peer| peer == from)
+        });
+        let Some(socket) = socket else {
+            return;
+        };
+        if socket.queue.len() >= QUEUE_CAPACITY {
+            return;
+        }
+        socket.queue.push_back(Datagram { from, data: Vec::from(&datagram[HEADER_SIZE..]) });
+        socket.waiters.wake_all();
+    }
+}
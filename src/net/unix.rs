@@ -0,0 +1,354 @@
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::fs::pipe::Pipe;
+use crate::fs::poll::{POLLIN, POLLOUT};
+use crate::fs::ramfs::RAMFS;
+use crate::fs::vfs::File;
+use crate::net::{Socket as _, SocketAddr, SocketType, SOMAXCONN};
+use crate::proc::errno::Errno;
+use crate::proc::wait::WaitQueue;
+
+/// Largest datagram.
+pub const DGRAM_MAX_SIZE: usize = 4096;
+/// Datagrams a socket holds before senders get `EAGAIN`.
+pub const DGRAM_QUEUE_CAPACITY: usize = 32;
+
+/// Bound names. A name belongs to its socket until the socket is closed;
+/// entries whose socket is gone are stale and may be bound again.
+static BOUND: IrqLock<BTreeMap<String, Weak<Socket>>> = IrqLock::new("UNIX_BOUND", BTreeMap::new());
+
+struct Datagram {
+    /// The sender's name, if it is bound.
+    from: Option<String>,
+    data: Vec<u8>,
+}
+
+enum Connection {
+    Unconnected,
+    /// Connections made but not yet accepted, oldest first.
+    Listening { backlog: VecDeque<Arc<Socket>>, capacity: usize },
+    /// A stream socket's pipes, one per direction.
+    Connected { rx: Arc<Pipe>, tx: Arc<Pipe>, peer: Option<String> },
+    /// A datagram socket's default destination.
+    Peer(String),
+}
+
+struct SocketState {
+    name: Option<String>,
+    connection: Connection,
+    datagrams: VecDeque<Datagram>,
+    /// Sleepers in `accept`, and in `recv` on a datagram socket.
+    waiters: WaitQueue,
+}
+
+struct Socket {
+    kind: SocketType,
+    state: IrqLock<SocketState>,
+}
+
+/// A local socket descriptor. A connected stream socket is a pair of pipes
+/// shared with its peer, so it blocks, reports end of file and fails with
+/// `EPIPE` exactly like one.
+pub struct UnixSocket(Arc<Socket>);
+
+/// Creates an unbound `AF_UNIX` socket.
+pub fn socket(kind: SocketType, protocol: u64) -> Result<Arc<dyn File>, Errno> {
+    if protocol != 0 {
+        return Err(Errno::EPROTONOSUPPORT);
+    }
+    Ok(Arc::new(UnixSocket(Socket::new(kind, Connection::Unconnected))))
+}
+
+/// Whether `name` belongs to a live socket, which `open` then refuses.
+pub fn is_bound(name: &str) -> bool {
+    BOUND.lock().get(name).map_or(false, |socket| socket.strong_count() > 0)
+}
+
+fn lookup(name: &str) -> Result<Arc<Socket>, Errno> {
+    BOUND.lock().get(name).and_then(Weak::upgrade).ok_or(Errno::ENOENT)
+}
+
+/// The name a local address carries.
+fn name(addr: &SocketAddr) -> Result<&str, Errno> {
+    match addr {
+        SocketAddr::Unix(Some(name)) => Ok(name),
+        SocketAddr::Unix(None) => Err(Errno::EINVAL),
+        SocketAddr::Inet(..) => Err(Errno::EAFNOSUPPORT),
+    }
+}
+
+impl Socket {
+    fn new(kind: SocketType, connection: Connection) -> Arc<Self> {
+        Arc::new(Socket {
+            kind,
+            state: IrqLock::new("SOCKET", SocketState {
+                name: None,
+                connection,
+                datagrams: VecDeque::new(),
+                waiters: WaitQueue::new(),
+            }),
+        })
+    }
+
+    /// Queues a new connection for `accept`.
+    fn enqueue(&self, socket: Arc<Socket>) -> Result<(), Errno> {
+        let mut guard = self.state.lock();
+        let state = &mut *guard;
+        let Connection::Listening { backlog, capacity } = &mut state.connection else {
+            return Err(Errno::ECONNREFUSED);
+        };
+        if backlog.len() >= *capacity {
+            return Err(Errno::ECONNREFUSED);
+        }
+        backlog.push_back(socket);
+        state.waiters.wake_all();
+        Ok(())
+    }
+
+    /// Queues a datagram. Like a mailbox, a full queue fails rather than
+    /// blocking the sender.
+    fn deliver(&self, datagram: Datagram) -> Result<(), Errno> {
+        let mut state = self.state.lock();
+        if state.datagrams.len() >= DGRAM_QUEUE_CAPACITY {
+            return Err(Errno::EAGAIN);
+        }
+        state.datagrams.push_back(datagram);
+        state.waiters.wake_all();
+        Ok(())
+    }
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        let mut state = self.state.lock();
+        let connection = core::mem::replace(&mut state.connection, Connection::Unconnected);
+        let name = state.name.take();
+        drop(state);
+
+        // Connections still waiting in a listener's backlog are dropped
+        // with it, so their clients see end of file.
+        if let Connection::Connected { rx, tx, .. } = connection {
+            rx.close_reader();
+            tx.close_writer();
+        }
+        if let Some(name) = name {
+            let mut bound = BOUND.lock();
+            if bound.get(&name).map_or(false, |socket| socket.strong_count() == 0) {
+                bound.remove(&name);
+            }
+        }
+    }
+}
+
+impl crate::net::Socket for UnixSocket {
+    fn kind(&self) -> SocketType {
+        self.0.kind
+    }
+
+    /// Gives the socket a name, which must not be a ramfs file or another
+    /// live socket.
+    fn bind(&self, addr: &SocketAddr) -> Result<(), Errno> {
+        let name = name(addr)?;
+        let mut state = self.0.state.lock();
+        if state.name.is_some() {
+            return Err(Errno::EINVAL);
+        }
+        if RAMFS.lock().find(name).is_some() {
+            return Err(Errno::EADDRINUSE);
+        }
+
+        let mut bound = BOUND.lock();
+        if bound.get(name).map_or(false, |socket| socket.strong_count() > 0) {
+            return Err(Errno::EADDRINUSE);
+        }
+        bound.insert(String::from(name), Arc::downgrade(&self.0));
+        state.name = Some(String::from(name));
+        Ok(())
+    }
+
+    /// Listening again only changes the limit.
+    fn listen(&self, backlog: usize) -> Result<(), Errno> {
+        if self.0.kind != SocketType::Stream {
+            return Err(Errno::EOPNOTSUPP);
+        }
+        let mut guard = self.0.state.lock();
+        let state = &mut *guard;
+        if state.name.is_none() {
+            return Err(Errno::EINVAL);
+        }
+
+        let capacity = backlog.clamp(1, SOMAXCONN);
+        match &mut state.connection {
+            Connection::Listening { capacity: current, .. } => *current = capacity,
+            Connection::Unconnected => {
+                state.connection = Connection::Listening { backlog: VecDeque::new(), capacity };
+            }
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(())
+    }
+
+    /// The peer is named after the socket that connected, if it is bound.
+    fn accept(&self) -> Result<(Arc<dyn File>, SocketAddr), Errno> {
+        let mut guard = self.0.state.lock();
+        let state = &mut *guard;
+        let Connection::Listening { backlog, .. } = &mut state.connection else {
+            return Err(Errno::EINVAL);
+        };
+        let Some(socket) = backlog.pop_front() else {
+            return Err(state.waiters.sleep());
+        };
+        drop(guard);
+
+        let peer = match &socket.state.lock().connection {
+            Connection::Connected { peer, .. } => peer.clone(),
+            _ => None,
+        };
+        Ok((Arc::new(UnixSocket(socket)), SocketAddr::Unix(peer)))
+    }
+
+    /// A stream connection is complete at once, waiting in the listener's
+    /// backlog until accepted, and is refused when the backlog is full.
+    fn connect(&self, addr: &SocketAddr) -> Result<(), Errno> {
+        let name = name(addr)?;
+        let target = lookup(name)?;
+        if target.kind != self.0.kind {
+            return Err(Errno::EPROTOTYPE);
+        }
+        if self.0.kind == SocketType::Datagram {
+            self.0.state.lock().connection = Connection::Peer(String::from(name));
+            return Ok(());
+        }
+
+        let local = {
+            let state = self.0.state.lock();
+            match state.connection {
+                Connection::Unconnected => state.name.clone(),
+                Connection::Connected { .. } => return Err(Errno::EISCONN),
+                _ => return Err(Errno::EINVAL),
+            }
+        };
+
+        let to_server = Pipe::new();
+        let to_client = Pipe::new();
+        let server = Socket::new(SocketType::Stream, Connection::Connected {
+            rx: to_server.clone(),
+            tx: to_client.clone(),
+            peer: local,
+        });
+        target.enqueue(server)?;
+
+        self.0.state.lock().connection = Connection::Connected {
+            rx: to_client,
+            tx: to_server,
+            peer: Some(String::from(name)),
+        };
+        Ok(())
+    }
+
+    /// Stream sends block like pipe writes unless `nonblocking`.
+    fn send(&self, buf: &[u8], to: Option<&SocketAddr>, nonblocking: bool) -> Result<usize, Errno> {
+        let to = to.map(name).transpose()?;
+        if self.0.kind == SocketType::Stream {
+            let tx = match (&self.0.state.lock().connection, to) {
+                (Connection::Connected { tx, .. }, None) => tx.clone(),
+                (Connection::Connected { .. }, Some(_)) => return Err(Errno::EISCONN),
+                (_, Some(_)) => return Err(Errno::EOPNOTSUPP),
+                (_, None) => return Err(Errno::ENOTCONN),
+            };
+            if nonblocking && tx.write_events() == 0 {
+                return Err(Errno::EAGAIN);
+            }
+            return tx.write(buf);
+        }
+
+        if buf.len() > DGRAM_MAX_SIZE {
+            return Err(Errno::EMSGSIZE);
+        }
+        let (from, peer) = {
+            let state = self.0.state.lock();
+            let peer = match &state.connection {
+                Connection::Peer(name) => Some(name.clone()),
+                _ => None,
+            };
+            (state.name.clone(), peer)
+        };
+        let target = to.map(String::from).or(peer).ok_or(Errno::EDESTADDRREQ)?;
+        let target = lookup(&target)?;
+        if target.kind != SocketType::Datagram {
+            return Err(Errno::EPROTOTYPE);
+        }
+        target.deliver(Datagram { from, data: Vec::from(buf) })?;
+        Ok(buf.len())
+    }
+
+    /// With nothing to receive the caller sleeps and `ERESTART` is
+    /// returned, unless `nonblocking` asks for `EAGAIN` instead.
+    fn recv(&self, buf: &mut [u8], nonblocking: bool) -> Result<(usize, SocketAddr), Errno> {
+        let mut state = self.0.state.lock();
+        if self.0.kind == SocketType::Stream {
+            let Connection::Connected { rx, peer, .. } = &state.connection else {
+                return Err(Errno::ENOTCONN);
+            };
+            let (rx, peer) = (rx.clone(), peer.clone());
+            drop(state);
+            if nonblocking && rx.read_events() == 0 {
+                return Err(Errno::EAGAIN);
+            }
+            return Ok((rx.read(buf)?, SocketAddr::Unix(peer)));
+        }
+
+        let Some(datagram) = state.datagrams.pop_front() else {
+            if nonblocking {
+                return Err(Errno::EAGAIN);
+            }
+            return Err(state.waiters.sleep());
+        };
+        let count = buf.len().min(datagram.data.len());
+        buf[..count].copy_from_slice(&datagram.data[..count]);
+        Ok((count, SocketAddr::Unix(datagram.from)))
+    }
+}
+
+impl File for UnixSocket {
+    fn read(&self, _offset: usize, buf: &mut [u8]) -> Result<usize, Errno> {
+        self.recv(buf, false).map(|(count, _)| count)
+    }
+
+    fn write(&self, _offset: usize, buf: &[u8]) -> Result<usize, Errno> {
+        self.send(buf, None, false)
+    }
+
+    /// A listener is readable with connections to accept. Datagram sockets
+    /// are always writable: a full destination fails instead of blocking.
+    fn poll(&self) -> u16 {
+        let state = self.0.state.lock();
+        match &state.connection {
+            Connection::Connected { rx, tx, .. } => rx.read_events() | tx.write_events(),
+            Connection::Listening { backlog, .. } if !backlog.is_empty() => POLLIN,
+            _ if self.0.kind == SocketType::Datagram && !state.datagrams.is_empty() => POLLIN | POLLOUT,
+            _ if self.0.kind == SocketType::Datagram => POLLOUT,
+            _ => 0,
+        }
+    }
+
+    fn poll_wait(&self, pid: u32) {
+        let mut guard = self.0.state.lock();
+        let state = &mut *guard;
+        match &state.connection {
+            Connection::Connected { rx, tx, .. } => {
+                rx.wait_readable(pid);
+                tx.wait_writable(pid);
+            }
+            _ => state.waiters.add(pid),
+        }
+    }
+
+    fn socket(&self) -> Result<&dyn crate::net::Socket, Errno> {
+        Ok(self)
+    }
+}
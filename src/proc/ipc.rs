@@ -0,0 +1,110 @@
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::proc::errno::Errno;
+use crate::proc::process::ProcessState;
+use crate::proc::scheduler::{ProcessManager, IDLE_PID};
+
+/// Largest message body.
+pub const MSG_MAX_SIZE: usize = 1024;
+/// Messages a mailbox holds before senders get `EAGAIN`.
+pub const MAILBOX_CAPACITY: usize = 32;
+/// Longest port name.
+pub const PORT_NAME_MAX: usize = 32;
+
+pub struct Message {
+    pub sender: u32,
+    pub data: Vec<u8>,
+}
+
+/// Messages queued for a process, oldest first.
+pub struct Mailbox {
+    messages: VecDeque<Message>,
+    /// The owner sleeps in `msg_recv` until a message arrives.
+    receiving: bool,
+}
+
+impl Mailbox {
+    pub const fn new() -> Self {
+        Mailbox { messages: VecDeque::new(), receiving: false }
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+        self.receiving = false;
+    }
+}
+
+impl ProcessManager {
+    /// Queues `data` in the mailbox of `to`, waking it if it is waiting for
+    /// a message. Sending never blocks: a full mailbox fails with `EAGAIN`.
+    pub fn send_message(&mut self, from: u32, to: u32, data: Vec<u8>) -> Result<(), Errno> {
+        if data.len() > MSG_MAX_SIZE {
+            return Err(Errno::EMSGSIZE);
+        }
+        let target = self.processes.get_mut(&to)
+            .filter(|process| process.pid != 0 && process.pid != IDLE_PID)
+            .filter(|process| process.state != ProcessState::Terminated)
+            .ok_or(Errno::ESRCH)?;
+        if target.mailbox.len() >= MAILBOX_CAPACITY {
+            return Err(Errno::EAGAIN);
+        }
+
+        target.mailbox.messages.push_back(Message { sender: from, data });
+        if core::mem::take(&mut target.mailbox.receiving) {
+            self.wake(to);
+        }
+        Ok(())
+    }
+
+    /// Takes the oldest message of `pid` if it fits in `capacity` bytes.
+    /// With an empty mailbox `pid` is put to sleep and `ERESTART` returned,
+    /// unless `nonblocking` asks for `EAGAIN` instead.
+    pub fn receive_message(&mut self, pid: u32, capacity: usize, nonblocking: bool) -> Result<Message, Errno> {
+        let process = self.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        match process.mailbox.messages.front() {
+            Some(message) if message.data.len() > capacity => Err(Errno::EMSGSIZE),
+            Some(_) => process.mailbox.messages.pop_front().ok_or(Errno::EAGAIN),
+            None if nonblocking => Err(Errno::EAGAIN),
+            None => {
+                process.mailbox.receiving = true;
+                process.state = ProcessState::Waiting;
+                Err(Errno::ERESTART)
+            }
+        }
+    }
+
+    /// Makes `name` refer to `pid`'s mailbox until `pid` exits.
+    pub fn register_port(&mut self, name: &str, pid: u32) -> Result<(), Errno> {
+        if name.is_empty() || name.len() > PORT_NAME_MAX {
+            return Err(Errno::EINVAL);
+        }
+        if self.ports.contains_key(name) {
+            return Err(Errno::EEXIST);
+        }
+        self.ports.insert(String::from(name), pid);
+        Ok(())
+    }
+
+    /// The process behind the port `name`.
+    pub fn lookup_port(&self, name: &str) -> Result<u32, Errno> {
+        self.ports.get(name).copied().ok_or(Errno::ENOENT)
+    }
+
+    /// Drops `pid`'s queued messages and ports, e.g. when it exits.
+    pub(crate) fn close_mailbox(&mut self, pid: u32) {
+        if let Some(process) = self.processes.get_mut(&pid) {
+            process.mailbox.clear();
+        }
+        self.ports.retain(|_, owner| *owner != pid);
+    }
+}
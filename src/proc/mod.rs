@@ -1,3 +1,10 @@
+pub mod errno;
+pub mod ipc;
 pub mod process;
+pub mod rlimit;
+pub mod rt;
 pub mod scheduler;
-pub mod syscall;
\ No newline at end of file
+pub mod signal;
+pub mod syscall;
+pub mod usercopy;
+pub mod wait;
\ No newline at end of file
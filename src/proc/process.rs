@@ -1,4 +1,15 @@
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
 use crate::arch::asm_switch::CpuState;
+use crate::arch::fpu::FpuState;
+use crate::fs::vfs::{File, FileTable};
+use crate::proc::ipc::Mailbox;
+use crate::mem::kstack::KernelStack;
+use crate::mem::shm::SharedMemory;
+use crate::proc::rlimit::ResourceLimits;
+use x86_64::structures::paging::PageTableFlags;
 use x86_64::PhysAddr;
 use x86_64::VirtAddr;
 
@@ -8,27 +19,172 @@ pub enum ProcessState {
     Running,
     Waiting,
     Terminated,
+    /// Suspended by a stop signal until it receives `SIGCONT`.
+    Stopped,
+}
+
+/// Scheduling class, numbered like Linux's `SCHED_*` constants.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum SchedPolicy {
+    /// Time-shared round robin below every real-time process.
+    Normal = 0,
+    /// Real-time, runs until it blocks, yields or is preempted by a higher
+    /// priority.
+    Fifo = 1,
+    /// Real-time like `Fifo`, but rotates among equal priorities each slice.
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(SchedPolicy::Normal),
+            1 => Some(SchedPolicy::Fifo),
+            2 => Some(SchedPolicy::RoundRobin),
+            _ => None,
+        }
+    }
+
+    pub fn is_realtime(self) -> bool {
+        self != SchedPolicy::Normal
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SchedPolicy::Normal => "normal",
+            SchedPolicy::Fifo => "fifo",
+            SchedPolicy::RoundRobin => "rr",
+        }
+    }
+}
+
+/// CPU time and scheduling counters of a process, times in timer ticks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ProcessStats {
+    pub user_ticks: u64,
+    pub system_ticks: u64,
+    /// Ticks of terminated children, folded in when they exit.
+    pub children_user_ticks: u64,
+    pub children_system_ticks: u64,
+    /// Switches away because the process blocked or exited.
+    pub voluntary_switches: u64,
+    /// Switches away while the process was still runnable.
+    pub involuntary_switches: u64,
+    pub page_faults: u64,
+}
+
+/// Snapshot of one process, as reported to `ps`/`top`.
+///
+/// Shared with user space, so the layout is fixed.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub parent_pid: u32,
+    pub state: u32,
+    pub priority: u32,
+    pub pgid: u32,
+    pub sid: u32,
+    pub policy: u32,
+    pub rt_priority: u32,
+    pub user_ticks: u64,
+    pub system_ticks: u64,
+    pub voluntary_switches: u64,
+    pub involuntary_switches: u64,
+    pub page_faults: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Code,
+    Stack,
+    Shared,
+    /// Zeroed private memory from `mmap`.
+    Anonymous,
+    /// A file mapped with `mmap`, read in page by page on first touch.
+    File,
+    /// Zeroed private memory from the heap's start to the `brk` break.
+    Heap,
+}
+
+/// A contiguous range of user pages mapped with the same flags.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryRegion {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub flags: PageTableFlags,
+    pub kind: RegionKind,
+}
+
+/// A `mmap` of a shared memory object, keeping its frames alive.
+pub struct SharedMapping {
+    pub start: VirtAddr,
+    pub object: Arc<SharedMemory>,
+}
+
+/// A `mmap` of a file. Its pages are read from the file when first
+/// touched, and those of a shared mapping written back when dirty.
+pub struct FileMapping {
+    pub start: VirtAddr,
+    pub end: VirtAddr,
+    pub file: Arc<dyn File>,
+    /// File offset `start` maps.
+    pub offset: usize,
+    /// Writes reach the file, instead of staying with the process.
+    pub shared: bool,
 }
 
 #[allow(dead_code)]
 pub struct ProcessMemory {
     pub page_table_addr: PhysAddr,
+    /// Tags the address space's TLB entries; 0 shares the kernel's.
+    pub pcid: u16,
+    /// Where `mmap` starts looking for free space.
+    pub mmap_base: VirtAddr,
     code_start: VirtAddr,
     data_start: VirtAddr,
     heap_start: VirtAddr,
+    /// End of the heap as `brk` last set it, not rounded to a page.
+    brk: VirtAddr,
     stack_start: VirtAddr,
     pages_allocated: usize,
+    regions: Vec<MemoryRegion>,
+    pub shared: Vec<SharedMapping>,
+    pub mapped_files: Vec<FileMapping>,
 }
 
 pub struct ProcessBlock {
     pub(crate) pid: u32,
     pub(crate) state: ProcessState,
     pub priority: u8,
+    pub policy: SchedPolicy,
+    /// Static priority, 1 to 99 for real-time policies and 0 otherwise.
+    pub rt_priority: u8,
     pub parent_pid: u32,
+    /// Process group, used for job control.
+    pub pgid: u32,
+    /// Session, the set of groups sharing a controlling console.
+    pub sid: u32,
+    /// Bit `n` set means signal `n` is ignored.
+    pub ignored_signals: u64,
     pub saved_state: *mut CpuState,
     pub memory: ProcessMemory,
-    pub kernel_stack: VirtAddr,
+    /// None for the kernel process, which runs on the boot stack, and once
+    /// the process has terminated.
+    pub kernel_stack: Option<KernelStack>,
+    /// Ticks used of the current time slice
     pub time: u64,
+    pub stats: ProcessStats,
+    /// x87/SSE/AVX registers, allocated on the process's first FPU use.
+    pub fpu: Option<FpuState>,
+    pub files: FileTable,
+    pub mailbox: Mailbox,
+    /// Tick a timed wait gives up at, kept while its system call restarts.
+    pub wake_at: Option<u64>,
+    pub limits: ResourceLimits,
+    /// Name the process was started with.
+    pub cmdline: String,
 }
 
 unsafe impl Send for ProcessBlock {}
@@ -38,6 +194,11 @@ impl ProcessBlock {
         self.pid
     }
 
+    /// Where the kernel stack starts, zero if there is none.
+    pub fn kernel_stack_top(&self) -> VirtAddr {
+        self.kernel_stack.as_ref().map_or(VirtAddr::zero(), KernelStack::top)
+    }
+
     pub fn get_state(&self) -> ProcessState {
         self.state
     }
@@ -45,6 +206,34 @@ impl ProcessBlock {
     pub fn set_state(&mut self, state: ProcessState) {
         self.state = state;
     }
+
+    pub fn get_state_name(&self) -> &'static str {
+        match self.state {
+            ProcessState::Ready => "ready",
+            ProcessState::Running => "running",
+            ProcessState::Waiting => "waiting",
+            ProcessState::Terminated => "terminated",
+            ProcessState::Stopped => "stopped",
+        }
+    }
+
+    pub fn info(&self) -> ProcessInfo {
+        ProcessInfo {
+            pid: self.pid,
+            parent_pid: self.parent_pid,
+            state: self.state as u32,
+            priority: self.priority as u32,
+            pgid: self.pgid,
+            sid: self.sid,
+            policy: self.policy as u32,
+            rt_priority: self.rt_priority as u32,
+            user_ticks: self.stats.user_ticks,
+            system_ticks: self.stats.system_ticks,
+            voluntary_switches: self.stats.voluntary_switches,
+            involuntary_switches: self.stats.involuntary_switches,
+            page_faults: self.stats.page_faults,
+        }
+    }
 }
 
 impl ProcessMemory {
@@ -57,11 +246,83 @@ impl ProcessMemory {
     ) -> Self {
         ProcessMemory {
             page_table_addr,
+            pcid: 0,
+            mmap_base: VirtAddr::new(crate::mem::shm::MMAP_BASE),
             code_start,
             data_start,
             heap_start,
+            brk: heap_start,
             stack_start,
             pages_allocated: 0,
+            regions: Vec::new(),
+            shared: Vec::new(),
+            mapped_files: Vec::new(),
+        }
+    }
+
+    /// Records a mapped user range for accounting and `/proc/<pid>/maps`.
+    pub fn add_region(&mut self, start: VirtAddr, end: VirtAddr, flags: PageTableFlags, kind: RegionKind) {
+        self.pages_allocated += ((end - start) / 4096) as usize;
+        self.regions.push(MemoryRegion { start, end, flags, kind });
+    }
+
+    /// Forgets the region starting at `start`.
+    pub fn remove_region(&mut self, start: VirtAddr) -> Option<MemoryRegion> {
+        let index = self.regions.iter().position(|region| region.start == start)?;
+        let region = self.regions.remove(index);
+        self.pages_allocated -= ((region.end - region.start) / 4096) as usize;
+        Some(region)
+    }
+
+    /// Lowest address in `[base, limit)` with `pages` unmapped pages.
+    pub fn find_free_range(&self, base: u64, limit: u64, pages: usize) -> Option<VirtAddr> {
+        self.find_free_aligned(base, limit, pages, 4096)
+    }
+
+    /// Like `find_free_range`, for a start aligned to `align` bytes.
+    pub fn find_free_aligned(&self, base: u64, limit: u64, pages: usize, align: u64) -> Option<VirtAddr> {
+        let size = (pages as u64).checked_mul(4096)?;
+        let mut candidate = base.checked_next_multiple_of(align)?;
+        let mut taken: Vec<&MemoryRegion> = self.regions.iter()
+            .filter(|region| region.end.as_u64() > base && region.start.as_u64() < limit)
+            .collect();
+        taken.sort_by_key(|region| region.start);
+
+        for region in taken {
+            if region.start.as_u64() >= candidate.checked_add(size)? {
+                break;
+            }
+            candidate = candidate.max(region.end.as_u64().checked_next_multiple_of(align)?);
         }
+        (candidate.checked_add(size)? <= limit).then(|| VirtAddr::new(candidate))
+    }
+
+    pub fn regions(&self) -> &[MemoryRegion] {
+        &self.regions
+    }
+
+    pub fn pages_allocated(&self) -> usize {
+        self.pages_allocated
+    }
+
+    pub fn code_start(&self) -> VirtAddr {
+        self.code_start
+    }
+
+    pub fn heap_start(&self) -> VirtAddr {
+        self.heap_start
+    }
+
+    pub fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    pub(crate) fn set_brk(&mut self, brk: VirtAddr) {
+        self.brk = brk;
+    }
+
+    /// Top of the user stack, or of the kernel stack for kernel threads.
+    pub fn stack_start(&self) -> VirtAddr {
+        self.stack_start
     }
 }
@@ -0,0 +1,116 @@
+use crate::drivers::pit::TIMER_HZ;
+use crate::fs::vfs::MAX_FILES;
+use crate::proc::errno::Errno;
+use crate::proc::process::ProcessStats;
+use crate::proc::signal::{SIGKILL, SIGXCPU};
+
+/// CPU time in seconds.
+pub const RLIMIT_CPU: u32 = 0;
+/// Number of live processes.
+pub const RLIMIT_NPROC: u32 = 6;
+/// Number of open descriptors.
+pub const RLIMIT_NOFILE: u32 = 7;
+/// Mapped user address space in bytes.
+pub const RLIMIT_AS: u32 = 9;
+
+pub const RLIM_INFINITY: u64 = u64::MAX;
+
+/// Default process limit, well below where `next_pid` or physical memory
+/// would run out.
+const DEFAULT_NPROC: u64 = 64;
+
+/// `struct rlimit`: the soft limit is enforced, the hard limit caps how far
+/// the soft one may be raised.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rlimit {
+    pub cur: u64,
+    pub max: u64,
+}
+
+impl Rlimit {
+    const fn new(value: u64) -> Self {
+        Rlimit { cur: value, max: value }
+    }
+}
+
+/// Limits of one process, inherited by its children.
+#[derive(Debug, Clone, Copy)]
+pub struct ResourceLimits {
+    cpu: Rlimit,
+    nproc: Rlimit,
+    nofile: Rlimit,
+    address_space: Rlimit,
+}
+
+impl ResourceLimits {
+    pub const fn new() -> Self {
+        ResourceLimits {
+            cpu: Rlimit::new(RLIM_INFINITY),
+            nproc: Rlimit::new(DEFAULT_NPROC),
+            nofile: Rlimit::new(MAX_FILES as u64),
+            address_space: Rlimit::new(RLIM_INFINITY),
+        }
+    }
+
+    pub fn get(&self, resource: u32) -> Result<Rlimit, Errno> {
+        match resource {
+            RLIMIT_CPU => Ok(self.cpu),
+            RLIMIT_NPROC => Ok(self.nproc),
+            RLIMIT_NOFILE => Ok(self.nofile),
+            RLIMIT_AS => Ok(self.address_space),
+            _ => Err(Errno::EINVAL),
+        }
+    }
+
+    /// Replaces a limit. Raising a hard limit is refused, there being no
+    /// privileged users to allow it.
+    pub fn set(&mut self, resource: u32, limit: Rlimit) -> Result<(), Errno> {
+        let current = self.get(resource)?;
+        if limit.cur > limit.max {
+            return Err(Errno::EINVAL);
+        }
+        if limit.max > current.max {
+            return Err(Errno::EPERM);
+        }
+
+        match resource {
+            RLIMIT_CPU => self.cpu = limit,
+            RLIMIT_NPROC => self.nproc = limit,
+            RLIMIT_NOFILE => self.nofile = limit,
+            _ => self.address_space = limit,
+        }
+        Ok(())
+    }
+
+    /// Soft limit on descriptors, as a table size.
+    pub fn max_files(&self) -> usize {
+        self.nofile.cur.min(MAX_FILES as u64) as usize
+    }
+
+    /// Whether `pages` user pages fit in the address space limit.
+    pub fn check_pages(&self, pages: usize) -> Result<(), Errno> {
+        match (pages as u64).checked_mul(4096) {
+            Some(bytes) if bytes <= self.address_space.cur => Ok(()),
+            _ => Err(Errno::ENOMEM),
+        }
+    }
+
+    /// Signal due on the tick that brought CPU time to `stats`, if any:
+    /// `SIGXCPU` each second past the soft limit, `SIGKILL` at the hard one.
+    pub fn cpu_signal(&self, stats: &ProcessStats) -> Option<u32> {
+        let ticks = stats.user_ticks + stats.system_ticks;
+        if ticks % TIMER_HZ != 0 {
+            return None;
+        }
+
+        let seconds = ticks / TIMER_HZ;
+        if seconds >= self.cpu.max {
+            Some(SIGKILL)
+        } else if seconds >= self.cpu.cur {
+            Some(SIGXCPU)
+        } else {
+            None
+        }
+    }
+}
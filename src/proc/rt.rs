@@ -0,0 +1,135 @@
+use crate::drivers::pit::TIMER_HZ;
+use crate::proc::errno::Errno;
+use crate::proc::process::{ProcessState, SchedPolicy};
+use crate::proc::scheduler::{ProcessManager, IDLE_PID};
+
+/// Ticks a normal or `SCHED_RR` process runs before the next one in line.
+pub const TIME_SLICE: u64 = 10;
+
+pub const MIN_RT_PRIORITY: u8 = 1;
+pub const MAX_RT_PRIORITY: u8 = 99;
+
+/// Real-time processes are throttled per period of one second...
+pub const RT_PERIOD: u64 = TIMER_HZ;
+/// ...once they have used this many of its ticks, 95% like Linux's default
+/// `sched_rt_runtime_us`. The rest is left to the normal class, so a
+/// runaway real-time loop slows the shell down but never locks it out.
+pub const RT_RUNTIME: u64 = RT_PERIOD * 95 / 100;
+
+impl ProcessManager {
+    /// Whether real-time processes used up their share of this period.
+    pub fn rt_throttled(&self) -> bool {
+        self.rt_ticks >= RT_RUNTIME
+    }
+
+    /// Charges the current tick to the real-time budget, starting a new
+    /// period when the last one is over.
+    pub fn account_rt_tick(&mut self) {
+        if self.ticks - self.rt_period_start >= RT_PERIOD {
+            self.rt_period_start = self.ticks;
+            self.rt_ticks = 0;
+        }
+
+        let realtime = self.current_pid
+            .and_then(|pid| self.processes.get(&pid))
+            .map_or(false, |process| process.policy.is_realtime());
+        if realtime {
+            self.rt_ticks += 1;
+            if self.rt_ticks == RT_RUNTIME {
+                self.rt_throttle_count += 1;
+            }
+        }
+    }
+
+    /// Called on each timer tick that interrupts the running `pid`. Returns
+    /// whether it must give up the CPU now.
+    pub fn tick_preempts(&mut self, pid: u32) -> bool {
+        let throttled = self.rt_throttled();
+        let best_realtime = if throttled {
+            None
+        } else {
+            self.rt_queues.iter().rev()
+                .find(|(_, queue)| !queue.is_empty())
+                .map(|(&priority, _)| priority)
+        };
+        let normal_ready = !self.ready_queue.is_empty();
+
+        let Some(process) = self.processes.get_mut(&pid) else {
+            return true;
+        };
+        process.time += 1;
+
+        match process.policy {
+            SchedPolicy::Normal => {
+                if best_realtime.is_some() || process.time >= TIME_SLICE {
+                    process.time = 0;
+                    return true;
+                }
+                false
+            }
+            SchedPolicy::Fifo | SchedPolicy::RoundRobin => {
+                // Preempted with time left, so it is requeued at the front
+                if throttled && normal_ready {
+                    return true;
+                }
+                if best_realtime.map_or(false, |best| best > process.rt_priority) {
+                    return true;
+                }
+
+                if process.policy == SchedPolicy::RoundRobin && process.time >= TIME_SLICE {
+                    process.time = 0;
+                    // Rotate only when an equal priority peer is waiting
+                    return best_realtime == Some(process.rt_priority);
+                }
+                false
+            }
+        }
+    }
+
+    /// Whether `caller` may change the scheduling of `pid`: only its own or
+    /// that of its children, so no process can starve the others by making
+    /// an arbitrary one real-time.
+    pub fn check_scheduler_permission(&self, caller: u32, pid: u32) -> Result<(), Errno> {
+        let target = self.processes.get(&pid).ok_or(Errno::ESRCH)?;
+        if pid != caller && target.parent_pid != caller {
+            return Err(Errno::EPERM);
+        }
+        Ok(())
+    }
+
+    /// `sched_setscheduler`: moves `pid` to `policy` at static `priority`,
+    /// 1 to 99 for the real-time policies and 0 for the normal one.
+    pub fn set_scheduler(&mut self, pid: u32, policy: SchedPolicy, priority: u8) -> Result<(), Errno> {
+        if pid == 0 || pid == IDLE_PID {
+            return Err(Errno::EPERM);
+        }
+        let valid = if policy.is_realtime() {
+            (MIN_RT_PRIORITY..=MAX_RT_PRIORITY).contains(&priority)
+        } else {
+            priority == 0
+        };
+        if !valid {
+            return Err(Errno::EINVAL);
+        }
+
+        let state = self.processes.get(&pid)
+            .map(|process| process.state)
+            .filter(|&state| state != ProcessState::Terminated)
+            .ok_or(Errno::ESRCH)?;
+
+        // A queued process moves to the queue of its new class
+        let queued = state == ProcessState::Ready;
+        if queued {
+            self.dequeue(pid);
+        }
+        if let Some(process) = self.processes.get_mut(&pid) {
+            process.policy = policy;
+            process.rt_priority = priority;
+            process.time = 0;
+        }
+        if queued {
+            self.enqueue(pid, false);
+        }
+        Ok(())
+    }
+}
@@ -1,39 +1,55 @@
 use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, VecDeque};
-
-use core::cell::UnsafeCell;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 use x86_64::structures::paging::PageTableFlags;
 use x86_64::registers::control::Cr3;
 use x86_64::VirtAddr;
 
 use crate::arch::asm_switch::CpuState;
-use crate::mem::memory::allocate_kernel_stack;
-use crate::proc::process::{ProcessBlock, ProcessMemory, ProcessState};
-
-
-pub struct SchedulerCell(UnsafeCell<ProcessManager>);
-
-unsafe impl Sync for SchedulerCell {}
-unsafe impl Send for SchedulerCell {}
-
-impl SchedulerCell {
-    const fn new(pm: ProcessManager) -> Self {
-        SchedulerCell(UnsafeCell::new(pm))
-    }
-
-    pub unsafe fn get(&self) -> &mut ProcessManager {
-        &mut *self.0.get()
-    }
-}
-
-pub static SCHEDULER: SchedulerCell = SchedulerCell::new(ProcessManager::new());
+use crate::arch::irq_lock::IrqLock;
+use crate::arch::pcid;
+use crate::arch::protection;
+use crate::mem::aslr;
+use crate::mem::kstack;
+use crate::fs::ramfs::RAMFS;
+use crate::fs::vfs::FileTable;
+use crate::proc::ipc::Mailbox;
+use crate::proc::errno::Errno;
+use crate::proc::process::{
+    ProcessBlock, ProcessInfo, ProcessMemory, ProcessState, ProcessStats, RegionKind, SchedPolicy,
+};
+use crate::proc::rlimit::{ResourceLimits, RLIMIT_NPROC};
+
+
+pub static SCHEDULER: IrqLock<ProcessManager> = IrqLock::new("SCHEDULER", ProcessManager::new());
+
+/// PID of the idle task. It is never handed out by `next_pid` and never
+/// placed on the ready queue.
+pub const IDLE_PID: u32 = u32::MAX - 1;
 
 pub struct ProcessManager {
     pub processes: BTreeMap<u32, Box<ProcessBlock>>,
+    /// Ready processes of the normal class.
     pub ready_queue: VecDeque<u32>,
+    /// Ready real-time processes, one queue per static priority. They all
+    /// run before anything in `ready_queue`.
+    pub rt_queues: BTreeMap<u8, VecDeque<u32>>,
+    /// Ticks used by real-time processes in the current throttling period.
+    pub rt_ticks: u64,
+    /// Tick the current throttling period started at.
+    pub rt_period_start: u64,
+    /// Number of periods in which real-time processes were throttled.
+    pub rt_throttle_count: u64,
     pub current_pid: Option<u32>,
     pub next_pid: u32,
+    /// Timer ticks since boot.
+    pub ticks: u64,
+    /// Timer ticks that found the idle task running.
+    pub idle_ticks: u64,
+    /// Named message ports and the processes receiving on them.
+    pub ports: BTreeMap<String, u32>,
 }
 
 impl ProcessManager {
@@ -41,22 +57,37 @@ impl ProcessManager {
         ProcessManager {
             processes: BTreeMap::new(),
             ready_queue: VecDeque::new(),
+            rt_queues: BTreeMap::new(),
+            rt_ticks: 0,
+            rt_period_start: 0,
+            rt_throttle_count: 0,
             current_pid: None,
             next_pid: 1,
+            ticks: 0,
+            idle_ticks: 0,
+            ports: BTreeMap::new(),
         }
     }
 
     pub fn schedule(&mut self) -> Option<u32> {
+        crate::proc::wait::wake_deferred(self);
+
         if let Some(current) = self.current_pid {
             if let Some(proc) = self.processes.get_mut(&current) {
                 if matches!(proc.state, ProcessState::Running) {
                     proc.state = ProcessState::Ready;
-                    self.ready_queue.push_back(current);
+                    // A preempted real-time process keeps its place at the
+                    // head of its queue; one that used up its slice or
+                    // yielded (time reset to 0) goes to the back
+                    let at_front = proc.time != 0;
+                    if current != IDLE_PID {
+                        self.enqueue(current, at_front);
+                    }
                 }
             }
         }
 
-        if let Some(next_pid) = self.ready_queue.pop_front() {
+        if let Some(next_pid) = self.pick_next() {
             if let Some(proc) = self.processes.get_mut(&next_pid) {
                 proc.state = ProcessState::Running;
                 self.current_pid = Some(next_pid);
@@ -64,23 +95,127 @@ impl ProcessManager {
             }
         }
 
+        // Nothing is runnable, fall back to the idle task if there is one
+        if let Some(idle) = self.processes.get_mut(&IDLE_PID) {
+            idle.state = ProcessState::Running;
+            self.current_pid = Some(IDLE_PID);
+            return Some(IDLE_PID);
+        }
+
         self.current_pid
     }
 
-    pub fn create_process(&mut self, program: &[u8]) -> u32 {
-        const USER_CODE_ADDR: u64 = 0x400000;
-        const USER_STACK_TOP: u64 = 0x800000;
-        const USER_STACK_BOTTOM: u64 = USER_STACK_TOP - 4096;
+    /// The process making the current system call.
+    pub fn current_process(&mut self) -> Result<&mut ProcessBlock, Errno> {
+        let pid = self.current_pid.ok_or(Errno::ESRCH)?;
+        self.processes.get_mut(&pid).map(|process| &mut **process).ok_or(Errno::ESRCH)
+    }
+
+    /// Puts a ready process on the queue of its scheduling class.
+    pub(crate) fn enqueue(&mut self, pid: u32, at_front: bool) {
+        let Some(process) = self.processes.get(&pid) else {
+            return;
+        };
+
+        if process.policy.is_realtime() {
+            let queue = self.rt_queues.entry(process.rt_priority).or_default();
+            if at_front {
+                queue.push_front(pid);
+            } else {
+                queue.push_back(pid);
+            }
+        } else {
+            self.ready_queue.push_back(pid);
+        }
+    }
 
-        let user_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE;
-        let user_stack_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+    /// Takes `pid` off whichever ready queue holds it.
+    pub(crate) fn dequeue(&mut self, pid: u32) {
+        self.ready_queue.retain(|&p| p != pid);
+        for queue in self.rt_queues.values_mut() {
+            queue.retain(|&p| p != pid);
+        }
+    }
+
+    /// Highest priority ready process. Real-time processes come first unless
+    /// they are throttled, and even then they still run when nothing else
+    /// wants the CPU.
+    fn pick_next(&mut self) -> Option<u32> {
+        if self.rt_throttled() {
+            self.ready_queue.pop_front().or_else(|| self.pop_realtime())
+        } else {
+            self.pop_realtime().or_else(|| self.ready_queue.pop_front())
+        }
+    }
+
+    fn pop_realtime(&mut self) -> Option<u32> {
+        self.rt_queues.values_mut().rev().find_map(|queue| queue.pop_front())
+    }
+
+    /// Whether any process other than the current one could run.
+    pub fn has_ready(&self) -> bool {
+        !self.ready_queue.is_empty() || self.rt_queues.values().any(|queue| !queue.is_empty())
+    }
+
+    /// Snapshot of every process for `ps`/`top`.
+    pub fn process_info(&self) -> Vec<ProcessInfo> {
+        self.processes.values().map(|process| process.info()).collect()
+    }
+
+    /// Percentage of timer ticks since boot spent outside the idle task.
+    pub fn cpu_usage(&self) -> u64 {
+        if self.ticks == 0 {
+            return 0;
+        }
+        (self.ticks - self.idle_ticks) * 100 / self.ticks
+    }
+
+    /// Loads `program`, linked to run at `aslr::FIXED_CODE_BASE`, into a
+    /// new address space and queues it. Fails when the parent's resource
+    /// limits or physical memory would be exceeded.
+    pub fn create_process(&mut self, program: &[u8]) -> Result<u32, Errno> {
+        self.load_process(program, false)
+    }
+
+    /// Like `create_process`, for a program that runs at any address. Its
+    /// code is placed at a random base too.
+    pub fn create_pie_process(&mut self, program: &[u8]) -> Result<u32, Errno> {
+        self.load_process(program, true)
+    }
+
+    fn load_process(&mut self, program: &[u8], position_independent: bool) -> Result<u32, Errno> {
+        // Writable or executable, never both
+        let user_flags = PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE;
+        let user_stack_flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE
+            | protection::no_execute();
+
+        // Children inherit their parent's limits and are checked against them
+        let parent_pid = self.current_pid.unwrap_or(0);
+        let limits = self.processes.get(&parent_pid)
+            .map(|parent| parent.limits)
+            .unwrap_or_else(ResourceLimits::new);
+
+        let live = self.processes.values()
+            .filter(|process| process.pid != 0 && process.pid != IDLE_PID)
+            .filter(|process| process.state != ProcessState::Terminated)
+            .count();
+        if live as u64 >= limits.get(RLIMIT_NPROC)?.cur {
+            return Err(Errno::EAGAIN);
+        }
+
+        let num_pages = (program.len() + 4095) / 4096;
+        limits.check_pages(num_pages + 1)?;
+
+        let layout = aslr::layout(num_pages, position_independent);
+        let code_addr = layout.code_base.as_u64();
+        let stack_top = layout.stack_top.as_u64();
+        let stack_bottom = stack_top - 4096;
 
         let pid = self.next_pid;
         self.next_pid += 1;
 
-        let kernel_stack = allocate_kernel_stack();
-        let stack_top = kernel_stack.as_u64();
-        let state_ptr = (stack_top - core::mem::size_of::<CpuState>() as u64) as *mut CpuState;
+        let kernel_stack = kstack::allocate(pid)?;
+        let state_ptr = (kernel_stack.top().as_u64() - core::mem::size_of::<CpuState>() as u64) as *mut CpuState;
 
         unsafe {
             *state_ptr = CpuState {
@@ -99,10 +234,10 @@ impl ProcessManager {
                 rcx: 0,
                 rbx: 0,
                 rax: 0,
-                rip: USER_CODE_ADDR,
+                rip: code_addr,
                 cs: crate::arch::gdt::user_code_selector().0 as u64,
                 rflags: 0x202,
-                rsp: USER_STACK_TOP,
+                rsp: stack_top,
                 ss: crate::arch::gdt::user_data_selector().0 as u64,
             };
         }
@@ -110,19 +245,21 @@ impl ProcessManager {
         let phys_mem_offset = unsafe {
             VirtAddr::new(crate::mem::memory::PHYS_MEM_OFFSET)
         };
-        let frame_alloc = unsafe { crate::mem::memory::FRAME_ALLOCATOR.get() };
-        let page_table_frame = crate::mem::memory::create_process_page_table(frame_alloc, phys_mem_offset);
+        // Frames mapped before an allocation failure are lost: the frame
+        // allocator cannot take frames back
+        let mut frame_alloc = crate::mem::memory::FRAME_ALLOCATOR.lock();
+        let frame_alloc = &mut *frame_alloc;
+        let page_table_frame = crate::mem::memory::create_process_page_table(frame_alloc, phys_mem_offset)?;
 
         // Map code pages
-        let num_pages = (program.len() + 4095) / 4096;
         let mut offset = 0;
 
         for i in 0..num_pages {
-            let page_addr = USER_CODE_ADDR + (i as u64 * 4096);
+            let page_addr = code_addr + (i as u64 * 4096);
             let code_frame = crate::mem::memory::map_user_page(
                 page_table_frame, phys_mem_offset,
                 frame_alloc, VirtAddr::new(page_addr), user_flags,
-            );
+            )?;
 
             let dst = (phys_mem_offset + code_frame.start_address().as_u64()).as_mut_ptr::<u8>();
             let remaining = program.len() - offset;
@@ -141,29 +278,74 @@ impl ProcessManager {
         // Map stack page
         crate::mem::memory::map_user_page(
             page_table_frame, phys_mem_offset,
-            frame_alloc, VirtAddr::new(USER_STACK_BOTTOM), user_stack_flags,
-        );
+            frame_alloc, VirtAddr::new(stack_bottom), user_stack_flags,
+        )?;
+
+        // Children start in their parent's process group, session and
+        // scheduling class
+        let (pgid, sid, policy, rt_priority) = self.processes.get(&parent_pid)
+            .map(|parent| (parent.pgid, parent.sid, parent.policy, parent.rt_priority))
+            .unwrap_or((0, 0, SchedPolicy::Normal, 0));
 
-        let process = Box::new(ProcessBlock {
+        let mut process = Box::new(ProcessBlock {
             pid,
             state: ProcessState::Ready,
             priority: 1,
-            parent_pid: 0,
+            policy,
+            rt_priority,
+            parent_pid,
+            pgid,
+            sid,
+            ignored_signals: 0,
             saved_state: state_ptr,
             memory: ProcessMemory::new(
                 page_table_frame.start_address(),
-                VirtAddr::new(USER_CODE_ADDR),
+                layout.code_base,
                 VirtAddr::new(0),
-                VirtAddr::new(crate::mem::allocator::HEAP_START as u64),
-                kernel_stack,
+                layout.heap_base,
+                layout.stack_top,
             ),
-            kernel_stack,
+            kernel_stack: Some(kernel_stack),
             time: 0,
+            stats: ProcessStats::default(),
+            fpu: None,
+            files: FileTable::with_console(),
+            mailbox: Mailbox::new(),
+            wake_at: None,
+            limits,
+            cmdline: String::new(),
         });
 
+        process.memory.add_region(
+            layout.code_base,
+            layout.code_base + num_pages as u64 * 4096,
+            user_flags,
+            RegionKind::Code,
+        );
+        process.memory.add_region(
+            VirtAddr::new(stack_bottom),
+            layout.stack_top,
+            user_stack_flags,
+            RegionKind::Stack,
+        );
+
+        process.memory.mmap_base = layout.mmap_base;
+        process.memory.pcid = pcid::allocate();
+
         self.processes.insert(pid, process);
-        self.ready_queue.push_back(pid);
-        pid
+        self.enqueue(pid, false);
+        Ok(pid)
+    }
+
+    /// Starts the ramfs program `name` as a new process.
+    pub fn spawn(&mut self, name: &str) -> Result<u32, Errno> {
+        let program = RAMFS.lock().find(name).map(|file| file.data).ok_or(Errno::ENOENT)?;
+
+        let pid = self.create_process(program)?;
+        if let Some(process) = self.processes.get_mut(&pid) {
+            process.cmdline = String::from(name);
+        }
+        Ok(pid)
     }
 
     pub fn init_kernel_process(&mut self) {
@@ -171,7 +353,12 @@ impl ProcessManager {
             pid: 0,
             state: ProcessState::Running,
             priority: 1,
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
             parent_pid: 0,
+            pgid: 0,
+            sid: 0,
+            ignored_signals: 0,
             saved_state: core::ptr::null_mut(),
             memory: ProcessMemory::new(
                 Cr3::read().0.start_address(),
@@ -180,39 +367,175 @@ impl ProcessManager {
                 VirtAddr::new(crate::mem::allocator::HEAP_START as u64),
                 VirtAddr::new(0),
             ),
-            kernel_stack: VirtAddr::new(0),
+            kernel_stack: None,
             time: 0,
+            stats: ProcessStats::default(),
+            fpu: None,
+            files: FileTable::new(),
+            mailbox: Mailbox::new(),
+            wake_at: None,
+            limits: ResourceLimits::new(),
+            cmdline: String::from("kernel"),
         });
 
         self.processes.insert(0, process_zero);
         self.current_pid = Some(0);
     }
 
+    /// Creates the kernel thread that runs whenever the ready queue is empty.
+    ///
+    /// It only executes `hlt`, so QEMU sleeps instead of spinning, and it is
+    /// chosen by `schedule` as a last resort rather than through the queue.
+    pub fn init_idle_task(&mut self) {
+        let kernel_stack = kstack::allocate(IDLE_PID).expect("no kernel stack for the idle task");
+        let state_ptr = (kernel_stack.top().as_u64() - core::mem::size_of::<CpuState>() as u64) as *mut CpuState;
+
+        let mut state = CpuState::default();
+        state.rip = crate::hlt_loop as *const () as u64;
+        state.cs = crate::arch::gdt::kernel_code_selector().0 as u64;
+        state.ss = crate::arch::gdt::kernel_data_selector().0 as u64;
+        // Below the saved state, with the alignment of a freshly called function
+        state.rsp = ((state_ptr as u64) & !0xF) - 8;
+        unsafe { *state_ptr = state };
+
+        let idle = Box::new(ProcessBlock {
+            pid: IDLE_PID,
+            state: ProcessState::Ready,
+            priority: 0,
+            policy: SchedPolicy::Normal,
+            rt_priority: 0,
+            parent_pid: 0,
+            pgid: 0,
+            sid: 0,
+            ignored_signals: 0,
+            saved_state: state_ptr,
+            memory: ProcessMemory::new(
+                Cr3::read().0.start_address(),
+                VirtAddr::new(0),
+                VirtAddr::new(0),
+                VirtAddr::new(0),
+                kernel_stack.top(),
+            ),
+            kernel_stack: Some(kernel_stack),
+            time: 0,
+            stats: ProcessStats::default(),
+            fpu: None,
+            files: FileTable::new(),
+            mailbox: Mailbox::new(),
+            wake_at: None,
+            limits: ResourceLimits::new(),
+            cmdline: String::from("idle"),
+        });
+
+        self.processes.insert(IDLE_PID, idle);
+    }
+
     pub fn terminate_process(&mut self, pid: u32) {
         let process = self.processes.get_mut(&pid);
 
-        if process.is_none() {
+        if process.is_none() || pid == IDLE_PID {
             return;
         }
 
         let process = process.unwrap();
-
-        if self.current_pid == Some(pid) {
-            self.current_pid = self.ready_queue.front().copied();
+        // Tearing down twice would count its CPU time into the parent again
+        if process.state == ProcessState::Terminated {
+            return;
         }
 
         process.state = ProcessState::Terminated;
-        self.ready_queue.retain(|&p| p != pid);
+        process.fpu = None;
+        // Still mapped until a new process reuses it, so an exiting process
+        // can finish its system call on it
+        process.kernel_stack = None;
+        process.files.clear();
+        crate::arch::fpu::release(pid);
 
         if self.current_pid == Some(pid) {
             self.current_pid = None;
         }
+
+        // Fold our CPU time into the parent for times()/getrusage(RUSAGE_CHILDREN)
+        let stats = process.stats;
+        let parent_pid = process.parent_pid;
+        if parent_pid != pid {
+            if let Some(parent) = self.processes.get_mut(&parent_pid) {
+                parent.stats.children_user_ticks += stats.user_ticks + stats.children_user_ticks;
+                parent.stats.children_system_ticks += stats.system_ticks + stats.children_system_ticks;
+            }
+        }
+
+        self.dequeue(pid);
+        self.unmap_all_shared(pid);
+        self.unmap_all_anonymous(pid);
+        self.unmap_all_files(pid);
+        self.close_mailbox(pid);
+
+        // Its address space stays loaded until the switch away, and a new
+        // owner of the PCID flushes it first
+        if let Some(process) = self.processes.get_mut(&pid) {
+            pcid::release(core::mem::take(&mut process.memory.pcid));
+        }
+    }
+
+    /// Suspends `pid` until `continue_process` is called on it.
+    pub fn stop_process(&mut self, pid: u32) {
+        if let Some(process) = self.processes.get_mut(&pid) {
+            if matches!(process.state, ProcessState::Ready | ProcessState::Running | ProcessState::Waiting) {
+                process.state = ProcessState::Stopped;
+                self.dequeue(pid);
+            }
+        }
+    }
+
+    /// Resumes a stopped process. It re-runs whatever system call it was
+    /// blocked in, so it simply goes back on the ready queue.
+    pub fn continue_process(&mut self, pid: u32) {
+        if let Some(process) = self.processes.get_mut(&pid) {
+            if process.state == ProcessState::Stopped {
+                process.state = ProcessState::Ready;
+                self.enqueue(pid, false);
+            }
+        }
+    }
+
+    /// Makes a process blocked in `Waiting` runnable again.
+    pub fn wake(&mut self, pid: u32) {
+        if let Some(process) = self.processes.get_mut(&pid) {
+            if process.state == ProcessState::Waiting {
+                process.state = ProcessState::Ready;
+                self.enqueue(pid, false);
+            }
+        }
+    }
+
+    /// Wakes the processes whose timed wait ran out.
+    pub fn wake_expired(&mut self) {
+        let now = self.ticks;
+        let expired: Vec<u32> = self.processes.values()
+            .filter(|process| process.state == ProcessState::Waiting)
+            .filter(|process| process.wake_at.map_or(false, |deadline| deadline <= now))
+            .map(|process| process.pid)
+            .collect();
+        for pid in expired {
+            self.wake(pid);
+        }
     }
 
     pub fn reset(&mut self) {
+        for process in self.processes.values() {
+            pcid::release(process.memory.pcid);
+        }
         self.processes.clear();
         self.ready_queue.clear();
+        self.rt_queues.clear();
+        self.rt_ticks = 0;
+        self.rt_period_start = 0;
+        self.rt_throttle_count = 0;
         self.current_pid = None;
         self.next_pid = 1;
+        self.ticks = 0;
+        self.idle_ticks = 0;
+        self.ports.clear();
     }
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use alloc::vec::Vec;
+
+use crate::drivers::input::INPUT;
+use crate::proc::errno::Errno;
+use crate::proc::process::ProcessState;
+use crate::proc::scheduler::{ProcessManager, IDLE_PID, SCHEDULER};
+
+pub const SIGINT: u32 = 2;
+pub const SIGKILL: u32 = 9;
+pub const SIGTERM: u32 = 15;
+pub const SIGCONT: u32 = 18;
+pub const SIGSTOP: u32 = 19;
+pub const SIGTSTP: u32 = 20;
+pub const SIGTTIN: u32 = 21;
+pub const SIGXCPU: u32 = 24;
+
+/// Highest signal number accepted by `kill` and `sigaction`.
+pub const MAX_SIGNAL: u32 = 31;
+
+/// What a signal does to a process that does not ignore it. There are no
+/// user-space handlers, only these default actions.
+enum Action {
+    Terminate,
+    Stop,
+    Continue,
+}
+
+fn default_action(signal: u32) -> Action {
+    match signal {
+        SIGSTOP | SIGTSTP | SIGTTIN => Action::Stop,
+        SIGCONT => Action::Continue,
+        _ => Action::Terminate,
+    }
+}
+
+/// `SIGKILL` and `SIGSTOP` can never be ignored.
+pub fn can_ignore(signal: u32) -> bool {
+    signal != SIGKILL && signal != SIGSTOP
+}
+
+impl ProcessManager {
+    /// Delivers `signal` to `pid`. Signal 0 only checks that `pid` exists.
+    pub fn send_signal(&mut self, pid: u32, signal: u32) -> Result<(), Errno> {
+        if signal > MAX_SIGNAL {
+            return Err(Errno::EINVAL);
+        }
+        if pid == 0 || pid == IDLE_PID {
+            return Err(Errno::EPERM);
+        }
+
+        let process = self.processes.get(&pid).ok_or(Errno::ESRCH)?;
+        // A zombie still exists, but there is nothing left to act on
+        if signal == 0 || process.get_state() == ProcessState::Terminated {
+            return Ok(());
+        }
+
+        // SIGCONT resumes even when ignored, like POSIX requires
+        let ignored = process.ignored_signals & (1 << signal) != 0;
+        match default_action(signal) {
+            Action::Continue => self.continue_process(pid),
+            _ if ignored => {}
+            Action::Stop => self.stop_process(pid),
+            Action::Terminate => self.terminate_process(pid),
+        }
+        Ok(())
+    }
+
+    /// Delivers `signal` to every live member of process group `pgid`.
+    pub fn signal_group(&mut self, pgid: u32, signal: u32) -> Result<(), Errno> {
+        let members: Vec<u32> = self.processes.values()
+            .filter(|process| process.pgid == pgid && process.get_pid() != 0)
+            .filter(|process| process.get_state() != ProcessState::Terminated)
+            .map(|process| process.get_pid())
+            .collect();
+
+        if members.is_empty() {
+            return Err(Errno::ESRCH);
+        }
+        for pid in members {
+            self.send_signal(pid, signal)?;
+        }
+        Ok(())
+    }
+
+    /// Whether some live process in session `sid` belongs to group `pgid`.
+    pub fn group_in_session(&self, pgid: u32, sid: u32) -> bool {
+        self.processes.values().any(|process| {
+            process.pgid == pgid
+                && process.sid == sid
+                && process.get_state() != ProcessState::Terminated
+        })
+    }
+}
+
+/// Sends `signal` to the console's foreground process group, for Ctrl-C
+/// and Ctrl-Z typed on the keyboard.
+pub fn signal_foreground(signal: u32) {
+    let pgid = INPUT.lock().foreground_pgid;
+    if pgid != 0 {
+        let _ = SCHEDULER.lock().signal_group(pgid, signal);
+    }
+}
@@ -1,6 +1,110 @@
+use x86_64::VirtAddr;
+use x86_64::registers::model_specific::{Efer, EferFlags, KernelGsBase, LStar, SFMask, Star};
+use x86_64::registers::rflags::RFlags;
+
 use crate::arch::asm_switch::CpuState;
-use crate::proc::scheduler::SCHEDULER;
+use crate::arch::gdt;
+use crate::arch::protection;
+use crate::drivers::pit::TIMER_HZ;
+use crate::proc::errno::Errno;
+use crate::proc::ipc::MSG_MAX_SIZE;
 use crate::drivers::input::INPUT;
+use crate::proc::process::{ProcessInfo, ProcessState, ProcessStats, RegionKind, SchedPolicy};
+use crate::proc::rlimit::Rlimit;
+use crate::proc::scheduler::{ProcessManager, SCHEDULER};
+use crate::proc::signal;
+use crate::proc::usercopy::{
+    check_user_range, copy_bytes_from_user, copy_bytes_to_user, copy_from_user, copy_str_from_user,
+    copy_to_user,
+};
+use crate::arch::interrupts::INTERRUPT_COUNTS;
+use crate::fs::pipe;
+use crate::fs::poll::{self, Epoll, EpollEvent, PollFd, POLLNVAL};
+use crate::mem::shm;
+use crate::fs::vfs::{self, OpenFile};
+use crate::net::{self, SocketAddr, SocketType, SOCKADDR_MAX_SIZE};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::sync::atomic::Ordering;
+
+/// Largest transfer a single read/write performs; callers see a short count.
+const MAX_IO_SIZE: usize = 4096;
+
+const RUSAGE_SELF: i64 = 0;
+const RUSAGE_CHILDREN: i64 = -1;
+
+const PROT_WRITE: u64 = 2;
+const MAP_SHARED: u64 = 0x01;
+const MAP_PRIVATE: u64 = 0x02;
+const MAP_FIXED: u64 = 0x10;
+const MAP_ANONYMOUS: u64 = 0x20;
+
+/// `msg_recv`, `sendto` and `recvfrom` flag: fail with `EAGAIN` instead of
+/// waiting.
+const MSG_DONTWAIT: u64 = 0x40;
+
+const SIG_DFL: u64 = 0;
+const SIG_IGN: u64 = 1;
+
+/// `struct sched_param`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SchedParam {
+    sched_priority: i32,
+}
+
+/// `struct tms`, in clock ticks of `TIMER_HZ`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Tms {
+    utime: u64,
+    stime: u64,
+    cutime: u64,
+    cstime: u64,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Timeval {
+    sec: u64,
+    usec: u64,
+}
+
+impl Timeval {
+    fn from_ticks(ticks: u64) -> Self {
+        Timeval {
+            sec: ticks / TIMER_HZ,
+            usec: (ticks % TIMER_HZ) * (1_000_000 / TIMER_HZ),
+        }
+    }
+}
+
+/// `struct rusage` with the Linux layout. Fields we do not track stay zero.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Rusage {
+    utime: Timeval,
+    stime: Timeval,
+    maxrss: u64,
+    ixrss: u64,
+    idrss: u64,
+    isrss: u64,
+    minflt: u64,
+    majflt: u64,
+    nswap: u64,
+    inblock: u64,
+    oublock: u64,
+    msgsnd: u64,
+    msgrcv: u64,
+    nsignals: u64,
+    nvcsw: u64,
+    nivcsw: u64,
+}
+
+extern "C" {
+    fn syscall_fast_entry();
+}
 
 core::arch::global_asm!(
     ".global syscall_interrupt_entry",
@@ -47,79 +151,751 @@ core::arch::global_asm!(
     "iretq",
 );
 
+// Entry point for the `syscall` instruction. The CPU leaves the user `rip`
+// in `rcx` and `rflags` in `r11` and does not switch stacks, so the stub
+// swaps to the per-CPU block, moves onto the process's kernel stack and
+// builds the same `CpuState` frame the `int 0x80` path produces. When
+// `syscall_dispatch` hands back the frame it was given we return with
+// `sysretq`; any other frame (a different process, or a non-canonical
+// return address) goes out through `iretq`.
+core::arch::global_asm!(
+    ".global syscall_fast_entry",
+    "syscall_fast_entry:",
+
+    "swapgs",
+    "mov qword ptr gs:[8], rsp",
+    "mov rsp, qword ptr gs:[0]",
+
+    "push qword ptr gs:[24]",
+    "push qword ptr gs:[8]",
+    "push r11",
+    "push qword ptr gs:[16]",
+    "push rcx",
+
+    "push rax",
+    "push rbx",
+    "push rcx",
+    "push rdx",
+    "push rsi",
+    "push rdi",
+    "push rbp",
+    "push r8",
+    "push r9",
+    "push r10",
+    "push r11",
+    "push r12",
+    "push r13",
+    "push r14",
+    "push r15",
+
+    "mov rdi, rsp",
+    "mov rbx, rsp",
+    "sub rsp, 256",
+    "and rsp, 0xFFFFFFFFFFFFFFF0",
+    "call syscall_dispatch",
+    "cmp rax, rbx",
+    "mov rsp, rax",
+
+    "pop r15",
+    "pop r14",
+    "pop r13",
+    "pop r12",
+    "pop r11",
+    "pop r10",
+    "pop r9",
+    "pop r8",
+    "pop rbp",
+    "pop rdi",
+    "pop rsi",
+    "pop rdx",
+    "pop rcx",
+    "pop rbx",
+    "pop rax",
+    "jne 2f",
+
+    "mov rcx, qword ptr [rsp]",
+    "mov r11, rcx",
+    "shl r11, 16",
+    "sar r11, 16",
+    "cmp r11, rcx",
+    "jne 2f",
+
+    "mov r11, qword ptr [rsp + 16]",
+    "mov rsp, qword ptr [rsp + 24]",
+    "swapgs",
+    "sysretq",
+
+    "2:",
+    "swapgs",
+    "iretq",
+);
+
+/// Enables the `syscall`/`sysret` instructions.
+///
+/// Must run after `gdt::init`, since STAR is built from the GDT selectors
+/// and the user data/code descriptors have to sit next to each other in
+/// the order `sysret` expects.
+pub fn init_fast_syscalls() {
+    unsafe {
+        let cpu_local = &raw mut gdt::CPU_LOCAL;
+        (*cpu_local).user_cs = gdt::user_code_selector().0 as u64;
+        (*cpu_local).user_ss = gdt::user_data_selector().0 as u64;
+        KernelGsBase::write(VirtAddr::from_ptr(cpu_local));
+
+        Star::write(
+            gdt::user_code_selector(),
+            gdt::user_data_selector(),
+            gdt::kernel_code_selector(),
+            gdt::kernel_data_selector(),
+        ).expect("GDT layout is not compatible with sysret");
+        LStar::write(VirtAddr::new(syscall_fast_entry as *const () as u64));
+        // Enter the kernel with interrupts off, like the `int 0x80` gate.
+        SFMask::write(RFlags::INTERRUPT_FLAG | RFlags::TRAP_FLAG | RFlags::DIRECTION_FLAG | RFlags::ALIGNMENT_CHECK);
+
+        Efer::update(|flags| *flags |= EferFlags::SYSTEM_CALL_EXTENSIONS);
+    }
+}
+
+/// Returns the calling process's descriptor `fd`.
+fn current_file(fd: u64) -> Result<Arc<OpenFile>, Errno> {
+    SCHEDULER.lock().current_process()?.files.get(fd as usize)
+}
+
 fn sys_read(state: &mut CpuState) -> *mut CpuState {
-    let input = unsafe { INPUT.get() };
-    let buffer = state.rdi as *mut u8;
-    let length = state.rsi as usize;
-    let mut count = 0;
+    let fd = state.rdi;
+    let buffer = state.rsi;
+    let length = (state.rdx as usize).min(MAX_IO_SIZE);
+
+    let result = current_file(fd).and_then(|file| {
+        check_user_range(buffer, length)?;
+        let mut data = vec![0u8; length];
+        let count = file.read(&mut data)?;
+        copy_bytes_to_user(buffer, &data[..count])?;
+        Ok(count as u64)
+    });
+
+    finish_io(state, result)
+}
+
+fn sys_write(state: &mut CpuState) -> *mut CpuState {
+    let fd = state.rdi;
+    let buffer = state.rsi;
+    let length = (state.rdx as usize).min(MAX_IO_SIZE);
 
-    while count < length {
-        if let Some(byte) = input.pop() {
-            unsafe { *buffer.add(count) = byte; }
-            count += 1;
-        } else {
-            break;
+    let result = current_file(fd).and_then(|file| {
+        let data = copy_bytes_from_user(buffer, length)?;
+        Ok(file.write(&data)? as u64)
+    });
+
+    finish_io(state, result)
+}
+
+/// Completes a read or write. One that put the caller to sleep or stopped
+/// it is issued again once the process runs.
+fn finish_io(state: &mut CpuState, result: Result<u64, Errno>) -> *mut CpuState {
+    if result == Err(Errno::ERESTART) {
+        // Back up over the 2-byte `int 0x80`/`syscall`
+        state.rip -= 2;
+        return reschedule_if_blocked(state);
+    }
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// `pipe(fds)`: stores the read and write descriptors of a new pipe in the
+/// two `u32`s at `rdi`.
+fn sys_pipe(state: &mut CpuState) -> *mut CpuState {
+    let result = check_user_range(state.rdi, core::mem::size_of::<[u32; 2]>()).and_then(|()| {
+        let (reader, writer) = pipe::pipe();
+        let mut scheduler = SCHEDULER.lock();
+        let process = scheduler.current_process()?;
+        let max_files = process.limits.max_files();
+
+        let read_fd = process.files.insert(OpenFile::new(reader), max_files)?;
+        match process.files.insert(OpenFile::new(writer), max_files) {
+            Ok(write_fd) => Ok([read_fd as u32, write_fd as u32]),
+            Err(err) => {
+                let _ = process.files.remove(read_fd);
+                Err(err)
+            }
+        }
+    });
+
+    state.rax = match result.and_then(|fds| copy_to_user(state.rdi, &fds)) {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// Opens the path at `rdi` (`rsi` bytes long) and returns a new descriptor.
+fn sys_open(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_str_from_user(state.rdi, state.rsi as usize).and_then(|path| {
+        let file = vfs::open(&path)?;
+        let mut scheduler = SCHEDULER.lock();
+        let process = scheduler.current_process()?;
+        let max_files = process.limits.max_files();
+        Ok(process.files.insert(OpenFile::new(file), max_files)? as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_close(state: &mut CpuState) -> *mut CpuState {
+    // The file is only dropped after the scheduler lock is released
+    let result = SCHEDULER.lock().current_process()
+        .and_then(|process| process.files.remove(state.rdi as usize));
+
+    state.rax = match result {
+        Ok(_) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `poll(fds, nfds, timeout)`: waits until one of the `struct pollfd`s at
+/// `rdi` has events, for at most `timeout` ms (negative: forever). Returns
+/// how many have.
+fn sys_poll(state: &mut CpuState) -> *mut CpuState {
+    let result = poll_fds(state.rdi, state.rsi as usize, state.rdx as i32 as i64);
+    finish_io(state, result)
+}
+
+fn poll_fds(addr: u64, count: usize, timeout_ms: i64) -> Result<u64, Errno> {
+    let max_files = SCHEDULER.lock().current_process()?.limits.max_files();
+    if count > max_files {
+        return Err(Errno::EINVAL);
+    }
+
+    let size = core::mem::size_of::<PollFd>();
+    let mut fds = (0..count)
+        .map(|index| copy_from_user::<PollFd>(addr + (index * size) as u64))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // Negative descriptors are skipped, unknown ones reported as POLLNVAL
+    let files: Vec<Option<Result<Arc<OpenFile>, Errno>>> = fds.iter()
+        .map(|pollfd| (pollfd.fd >= 0).then(|| current_file(pollfd.fd as u64)))
+        .collect();
+
+    let mut ready = 0;
+    for (pollfd, file) in fds.iter_mut().zip(&files) {
+        pollfd.revents = match file {
+            None => 0,
+            Some(Ok(file)) => poll::events(file, pollfd.events as u16) as i16,
+            Some(Err(_)) => POLLNVAL as i16,
+        };
+        if pollfd.revents != 0 {
+            ready += 1;
         }
     }
 
-    state.rax = count as u64;
+    if ready == 0 {
+        let files: Vec<Arc<OpenFile>> = files.into_iter().flatten().flatten().collect();
+        poll::sleep(&files, timeout_ms)?;
+    }
+    poll::finish();
+
+    for (index, pollfd) in fds.iter().enumerate() {
+        copy_to_user(addr + (index * size) as u64, pollfd)?;
+    }
+    Ok(ready)
+}
+
+/// `epoll_create(size)`: the size hint is ignored, as on Linux.
+fn sys_epoll_create(state: &mut CpuState) -> *mut CpuState {
+    let result = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.current_process().and_then(|process| {
+            let max_files = process.limits.max_files();
+            process.files.insert(OpenFile::new(Epoll::new()), max_files)
+        })
+    };
+
+    state.rax = match result {
+        Ok(fd) => fd as u64,
+        Err(err) => err.as_return(),
+    };
     state as *mut CpuState
 }
 
-fn sys_write(state: &mut CpuState) -> *mut CpuState {
+/// `epoll_ctl(epfd, op, fd, event)` with `event` in `r10`, ignored for
+/// `EPOLL_CTL_DEL`.
+fn sys_epoll_ctl(state: &mut CpuState) -> *mut CpuState {
+    let op = state.rsi;
+    let fd = state.rdx as i32;
+
+    let result = current_file(state.rdi).and_then(|epoll_file| {
+        let event = match op {
+            poll::EPOLL_CTL_DEL => EpollEvent { events: 0, data: 0 },
+            _ => copy_from_user::<EpollEvent>(state.r10)?,
+        };
+        let file = current_file(fd as u64)?;
+        if Arc::ptr_eq(&file, &epoll_file) {
+            return Err(Errno::EINVAL);
+        }
+        epoll_file.epoll()?.control(op, fd, &file, event)
+    });
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `epoll_wait(epfd, events, maxevents, timeout)` with `timeout` in `r10`.
+fn sys_epoll_wait(state: &mut CpuState) -> *mut CpuState {
+    let addr = state.rsi;
+    let max = state.rdx as i32;
+    let timeout_ms = state.r10 as i32 as i64;
+
+    let result = current_file(state.rdi).and_then(|epoll_file| {
+        if max <= 0 {
+            return Err(Errno::EINVAL);
+        }
+        let size = core::mem::size_of::<EpollEvent>();
+        check_user_range(addr, max as usize * size)?;
+
+        let (ready, files) = epoll_file.epoll()?.ready(max as usize);
+        if ready.is_empty() {
+            poll::sleep(&files, timeout_ms)?;
+        }
+        poll::finish();
+
+        for (index, event) in ready.iter().enumerate() {
+            copy_to_user(addr + (index * size) as u64, event)?;
+        }
+        Ok(ready.len() as u64)
+    });
+
+    finish_io(state, result)
+}
+
+/// `ftruncate(fd, length)`.
+fn sys_ftruncate(state: &mut CpuState) -> *mut CpuState {
+    let result = current_file(state.rdi).and_then(|file| file.truncate(state.rsi as usize));
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `shm_open(name, name_len, flags)`: opens a shared memory object and
+/// returns a descriptor for `ftruncate` and `mmap`.
+fn sys_shm_open(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_str_from_user(state.rdi, state.rsi as usize).and_then(|name| {
+        let file = shm::open(&name, state.rdx)?;
+        let mut scheduler = SCHEDULER.lock();
+        let process = scheduler.current_process()?;
+        let max_files = process.limits.max_files();
+        Ok(process.files.insert(OpenFile::new(file), max_files)? as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_shm_unlink(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_str_from_user(state.rdi, state.rsi as usize).and_then(|name| shm::unlink(&name));
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `mmap(addr, length, prot, flags, fd, offset)` with the last three in
+/// `r10`, `r8` and `r9`. Maps files, shared memory objects and
+/// `MAP_ANONYMOUS` memory, placed wherever the kernel sees fit.
+fn sys_mmap(state: &mut CpuState) -> *mut CpuState {
     let length = state.rsi as usize;
-    let buffer = state.rdi as *const u8;
+    let writable = state.rdx & PROT_WRITE != 0;
+    let flags = state.r10;
+
+    let result = if flags & MAP_FIXED != 0 || flags & (MAP_SHARED | MAP_PRIVATE) == 0 {
+        Err(Errno::EINVAL)
+    } else if flags & MAP_ANONYMOUS != 0 {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.current_process().map(|process| process.get_pid()).and_then(|pid| {
+            let addr = scheduler.map_anonymous(pid, length, writable)?;
+            Ok(addr.as_u64())
+        })
+    } else {
+        current_file(state.r8).and_then(|file| {
+            let shared = flags & MAP_SHARED != 0;
+            let object = file.mmap();
+            let mut scheduler = SCHEDULER.lock();
+            let pid = scheduler.current_process()?.get_pid();
+            let offset = state.r9 as usize;
+            let addr = match object {
+                // Shared memory is mapped whole, everything else page by page
+                Ok(object) if shared => scheduler.map_shared(pid, object, offset, length, writable)?,
+                _ => scheduler.map_file(pid, file.file(), offset, length, writable, shared)?,
+            };
+            Ok(addr.as_u64())
+        })
+    };
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_munmap(state: &mut CpuState) -> *mut CpuState {
+    let result = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.current_process().map(|process| process.get_pid()).and_then(|pid| {
+            let start = VirtAddr::try_new(state.rdi).map_err(|_| Errno::EINVAL)?;
+            let kind = scheduler.processes[&pid].memory.regions().iter()
+                .find(|region| region.start == start)
+                .map(|region| region.kind);
+            match kind {
+                Some(RegionKind::Anonymous) => scheduler.unmap_anonymous(pid, start, state.rsi as usize),
+                Some(RegionKind::File) => scheduler.unmap_file(pid, start, state.rsi as usize),
+                _ => scheduler.unmap_shared(pid, start, state.rsi as usize),
+            }
+        })
+    };
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `brk(addr)`: moves the end of the heap, which starts at a randomized
+/// address past the code. Returns the new break, or the old one if `addr`
+/// can't be had.
+fn sys_brk(state: &mut CpuState) -> *mut CpuState {
+    let result = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.current_process().map(|process| process.get_pid())
+            .and_then(|pid| scheduler.brk(pid, state.rdi))
+    };
+
+    state.rax = match result {
+        Ok(brk) => brk.as_u64(),
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `msync(addr, length, flags)`: writes shared file mappings back now. Every
+/// flag means the same, as nothing is written back in the background.
+fn sys_msync(state: &mut CpuState) -> *mut CpuState {
+    let result = {
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.current_process().map(|process| process.get_pid()).and_then(|pid| {
+            let start = VirtAddr::try_new(state.rdi).map_err(|_| Errno::EINVAL)?;
+            scheduler.sync_files(pid, start, state.rsi as usize)
+        })
+    };
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `msg_send(pid, buf, len)`: queues a copy of `buf` for `pid`, which may
+/// come from `port_lookup`.
+fn sys_msg_send(state: &mut CpuState) -> *mut CpuState {
+    let to = state.rdi as u32;
+    let length = state.rdx as usize;
 
-    let slice = unsafe { core::slice::from_raw_parts(buffer, length) };
-    if let Ok(s) = core::str::from_utf8(slice) {
-        crate::print!("{}", s);
+    let result = if length > MSG_MAX_SIZE {
+        Err(Errno::EMSGSIZE)
+    } else {
+        copy_bytes_from_user(state.rsi, length).and_then(|data| {
+            let mut scheduler = SCHEDULER.lock();
+            let from = scheduler.current_process()?.get_pid();
+            scheduler.send_message(from, to, data)
+        })
+    };
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `msg_recv(buf, len, flags, sender)`: takes the oldest message, storing
+/// the sender's pid at `r10` unless it is null. Returns the message length.
+fn sys_msg_recv(state: &mut CpuState) -> *mut CpuState {
+    let buffer = state.rdi;
+    let capacity = (state.rsi as usize).min(MSG_MAX_SIZE);
+    let nonblocking = state.rdx & MSG_DONTWAIT != 0;
+    let sender_addr = state.r10;
+
+    let message = check_user_range(buffer, capacity).and_then(|()| {
+        // Validated up front, so a received message is never lost
+        if sender_addr != 0 {
+            check_user_range(sender_addr, core::mem::size_of::<u32>())?;
+        }
+        let mut scheduler = SCHEDULER.lock();
+        let pid = scheduler.current_process()?.get_pid();
+        scheduler.receive_message(pid, capacity, nonblocking)
+    });
+
+    let result = message.and_then(|message| {
+        copy_bytes_to_user(buffer, &message.data)?;
+        if sender_addr != 0 {
+            copy_to_user(sender_addr, &message.sender)?;
+        }
+        Ok(message.data.len() as u64)
+    });
+
+    finish_io(state, result)
+}
+
+/// `port_register(name, name_len)`: names the caller's mailbox.
+fn sys_port_register(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_str_from_user(state.rdi, state.rsi as usize).and_then(|name| {
+        let mut scheduler = SCHEDULER.lock();
+        let pid = scheduler.current_process()?.get_pid();
+        scheduler.register_port(&name, pid)
+    });
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `port_lookup(name, name_len)`: returns the pid receiving on a port.
+fn sys_port_lookup(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_str_from_user(state.rdi, state.rsi as usize)
+        .and_then(|name| SCHEDULER.lock().lookup_port(&name));
+
+    state.rax = match result {
+        Ok(pid) => pid as u64,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// Reads the `struct sockaddr_*` of `len` bytes at `addr`.
+fn read_address(addr: u64, len: usize) -> Result<SocketAddr, Errno> {
+    if len > SOCKADDR_MAX_SIZE {
+        return Err(Errno::EINVAL);
+    }
+    SocketAddr::parse(&copy_bytes_from_user(addr, len)?)
+}
+
+/// Checks the buffer an address will be stored in, so that nothing taken
+/// from a socket is lost to a bad pointer. A null `addr` means none.
+fn check_address_buffer(addr: u64, len_addr: u64) -> Result<(), Errno> {
+    if addr == 0 {
+        return Ok(());
+    }
+    let capacity = copy_from_user::<u32>(len_addr)?;
+    check_user_range(addr, capacity as usize)
+}
+
+/// Stores `address` as a `struct sockaddr_*` at `addr`, cut to the
+/// capacity in the `u32` at `len_addr`, which is set to the full length.
+fn store_address(addr: u64, len_addr: u64, address: &SocketAddr) -> Result<(), Errno> {
+    if addr == 0 {
+        return Ok(());
     }
+    let capacity = copy_from_user::<u32>(len_addr)? as usize;
+    let bytes = address.encode();
+    copy_bytes_to_user(addr, &bytes[..bytes.len().min(capacity)])?;
+    copy_to_user(len_addr, &(bytes.len() as u32))
+}
+
+/// `socket(domain, type, protocol)`, for `AF_UNIX` and `AF_INET`.
+fn sys_socket(state: &mut CpuState) -> *mut CpuState {
+    let result = net::socket(state.rdi, state.rsi, state.rdx).and_then(|file| {
+        let mut scheduler = SCHEDULER.lock();
+        let process = scheduler.current_process()?;
+        let max_files = process.limits.max_files();
+        Ok(process.files.insert(OpenFile::new(file), max_files)? as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// `bind(fd, addr, addrlen)`.
+fn sys_bind(state: &mut CpuState) -> *mut CpuState {
+    let result = current_file(state.rdi).and_then(|file| {
+        let addr = read_address(state.rsi, state.rdx as usize)?;
+        file.socket()?.bind(&addr)
+    });
 
-    state.rax = 0;
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
     state as *mut CpuState
 }
 
+/// `listen(fd, backlog)`.
+fn sys_listen(state: &mut CpuState) -> *mut CpuState {
+    let backlog = (state.rsi as i32).max(0) as usize;
+    let result = current_file(state.rdi).and_then(|file| file.socket()?.listen(backlog));
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `accept(fd, addr, addrlen)`: waits for a connection and returns a new
+/// descriptor for it, storing the peer's address at `rsi` unless it is null.
+fn sys_accept(state: &mut CpuState) -> *mut CpuState {
+    let addr = state.rsi;
+    let len_addr = state.rdx;
+
+    let result = current_file(state.rdi).and_then(|file| {
+        check_address_buffer(addr, len_addr)?;
+        let (socket, peer) = file.socket()?.accept()?;
+        let fd = {
+            let mut scheduler = SCHEDULER.lock();
+            let process = scheduler.current_process()?;
+            let max_files = process.limits.max_files();
+            process.files.insert(OpenFile::new(socket), max_files)?
+        };
+        store_address(addr, len_addr, &peer)?;
+        Ok(fd as u64)
+    });
+
+    finish_io(state, result)
+}
+
+/// `connect(fd, addr, addrlen)`.
+fn sys_connect(state: &mut CpuState) -> *mut CpuState {
+    let result = current_file(state.rdi).and_then(|file| {
+        let addr = read_address(state.rsi, state.rdx as usize)?;
+        file.socket()?.connect(&addr)
+    });
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// `sendto(fd, buf, len, flags, addr, addrlen)` with the last three in
+/// `r10`, `r8` and `r9`. A null `addr` sends to the connected peer, which
+/// makes this `send` too.
+fn sys_sendto(state: &mut CpuState) -> *mut CpuState {
+    let buffer = state.rsi;
+    let length = state.rdx as usize;
+    let nonblocking = state.r10 & MSG_DONTWAIT != 0;
+    let addr = state.r8;
+    let addr_len = state.r9 as usize;
+
+    let result = current_file(state.rdi).and_then(|file| {
+        let socket = file.socket()?;
+        let length = match socket.kind() {
+            SocketType::Stream => length.min(MAX_IO_SIZE),
+            SocketType::Datagram if length > MAX_IO_SIZE => return Err(Errno::EMSGSIZE),
+            SocketType::Datagram => length,
+        };
+        let to = match addr {
+            0 => None,
+            addr => Some(read_address(addr, addr_len)?),
+        };
+        let data = copy_bytes_from_user(buffer, length)?;
+        Ok(socket.send(&data, to.as_ref(), nonblocking)? as u64)
+    });
+
+    finish_io(state, result)
+}
+
+/// `recvfrom(fd, buf, len, flags, addr, addrlen)` with the last three in
+/// `r10`, `r8` and `r9`: the sender's address is stored at `r8` unless it
+/// is null, which makes this `recv`.
+fn sys_recvfrom(state: &mut CpuState) -> *mut CpuState {
+    let buffer = state.rsi;
+    let length = (state.rdx as usize).min(MAX_IO_SIZE);
+    let nonblocking = state.r10 & MSG_DONTWAIT != 0;
+    let addr = state.r8;
+    let len_addr = state.r9;
+
+    let result = current_file(state.rdi).and_then(|file| {
+        check_user_range(buffer, length)?;
+        check_address_buffer(addr, len_addr)?;
+        let mut data = vec![0u8; length];
+        let (count, from) = file.socket()?.recv(&mut data, nonblocking)?;
+        copy_bytes_to_user(buffer, &data[..count])?;
+        store_address(addr, len_addr, &from)?;
+        Ok(count as u64)
+    });
+
+    finish_io(state, result)
+}
+
 fn sys_exit(_state: &mut CpuState) -> *mut CpuState {
-    unsafe {
-        let scheduler = SCHEDULER.get();
-        let pid = scheduler.current_pid.unwrap();
+    let mut scheduler = SCHEDULER.lock();
+    // Already gone if a signal killed it since its last system call
+    if let Some(pid) = scheduler.current_pid {
         scheduler.terminate_process(pid);
-
-        crate::arch::asm_switch::switch_to_next(core::ptr::null_mut())
     }
+
+    unsafe { crate::arch::asm_switch::switch_to_next(&mut scheduler, core::ptr::null_mut()) }
 }
 
+/// Starts the program named at `rdi` (`rsi` bytes long). A non-zero `rdx`
+/// points at three `i32`s: the caller's descriptors to hand the child as
+/// its stdin, stdout and stderr, -1 leaving the console in place.
 fn sys_start_process(state: &mut CpuState) -> *mut CpuState {
-    let name_ptr = state.rdi as *const u8;
-    let name_len = state.rsi as usize;
+    let stdio = match state.rdx {
+        0 => Ok([-1; 3]),
+        addr => copy_from_user::<[i32; 3]>(addr),
+    };
+    let result = stdio.and_then(|stdio| {
+        let name = copy_str_from_user(state.rdi, state.rsi as usize)?;
+        let mut scheduler = SCHEDULER.lock();
 
-    let name_slice = unsafe { core::slice::from_raw_parts(name_ptr, name_len) };
+        let mut files = [None, None, None];
+        for (slot, &fd) in files.iter_mut().zip(stdio.iter()) {
+            if fd >= 0 {
+                *slot = Some(scheduler.current_process()?.files.get(fd as usize)?);
+            }
+        }
 
-    if let Ok(name) = core::str::from_utf8(name_slice) {
-        let fs = unsafe { crate::fs::ramfs::RAMFS.get() };
-        if let Some(file) = fs.find(name) {
-            let scheduler = unsafe { SCHEDULER.get() };
-            let pid = scheduler.create_process(file.data);
-            state.rax = pid as u64;
-        } else {
-            state.rax = u64::MAX;
+        let pid = scheduler.spawn(&name)?;
+        let child = scheduler.processes.get_mut(&pid).ok_or(Errno::ESRCH)?;
+        for (fd, file) in files.into_iter().enumerate() {
+            if let Some(file) = file {
+                child.files.install(fd, file)?;
+            }
         }
-    } else {
-        state.rax = u64::MAX;
-    }
+        Ok(pid)
+    });
 
+    state.rax = match result {
+        Ok(pid) => pid as u64,
+        Err(err) => err.as_return(),
+    };
     state as *mut CpuState
 }
 
 fn sys_wait_process(state: &mut CpuState) -> *mut CpuState {
     let pid = state.rdi as u32;
-    let scheduler = unsafe { SCHEDULER.get() };
+    let scheduler = SCHEDULER.lock();
 
     if let Some(process) = scheduler.processes.get(&pid) {
         match process.get_state() {
             crate::proc::process::ProcessState::Terminated => {
                 state.rax = 0;
             }
+            crate::proc::process::ProcessState::Stopped => {
+                state.rax = 2;
+            }
             _ => {
                 state.rax = 1;
             }
@@ -131,32 +907,426 @@ fn sys_wait_process(state: &mut CpuState) -> *mut CpuState {
     state as *mut CpuState
 }
 
+fn sys_getpid(state: &mut CpuState) -> *mut CpuState {
+    let scheduler = SCHEDULER.lock();
+    state.rax = scheduler.current_pid.unwrap_or(0) as u64;
+    state as *mut CpuState
+}
+
+fn current_stats() -> ProcessStats {
+    SCHEDULER.lock().current_process()
+        .map(|process| process.stats)
+        .unwrap_or_default()
+}
+
+fn sys_times(state: &mut CpuState) -> *mut CpuState {
+    let stats = current_stats();
+    let tms = Tms {
+        utime: stats.user_ticks,
+        stime: stats.system_ticks,
+        cutime: stats.children_user_ticks,
+        cstime: stats.children_system_ticks,
+    };
+
+    state.rax = if state.rdi == 0 {
+        SCHEDULER.lock().ticks
+    } else {
+        match copy_to_user(state.rdi, &tms) {
+            Ok(()) => SCHEDULER.lock().ticks,
+            Err(err) => err.as_return(),
+        }
+    };
+    state as *mut CpuState
+}
+
+fn sys_getrusage(state: &mut CpuState) -> *mut CpuState {
+    let stats = current_stats();
+
+    let usage = match state.rdi as i64 {
+        RUSAGE_SELF => Rusage {
+            utime: Timeval::from_ticks(stats.user_ticks),
+            stime: Timeval::from_ticks(stats.system_ticks),
+            minflt: stats.page_faults,
+            nvcsw: stats.voluntary_switches,
+            nivcsw: stats.involuntary_switches,
+            ..Rusage::default()
+        },
+        RUSAGE_CHILDREN => Rusage {
+            utime: Timeval::from_ticks(stats.children_user_ticks),
+            stime: Timeval::from_ticks(stats.children_system_ticks),
+            ..Rusage::default()
+        },
+        _ => {
+            state.rax = Errno::EINVAL.as_return();
+            return state as *mut CpuState;
+        }
+    };
+
+    state.rax = match copy_to_user(state.rsi, &usage) {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    state as *mut CpuState
+}
+
+/// Fills the user array at `rdi` with up to `rsi` `ProcessInfo` records and
+/// returns how many were written.
+fn sys_process_info(state: &mut CpuState) -> *mut CpuState {
+    let buffer = state.rdi;
+    let capacity = state.rsi as usize;
+
+    let checked = capacity.checked_mul(core::mem::size_of::<ProcessInfo>())
+        .ok_or(Errno::EFAULT)
+        .and_then(|len| check_user_range(buffer, len));
+    if let Err(err) = checked {
+        state.rax = err.as_return();
+        return state as *mut CpuState;
+    }
+
+    // Snapshot first: user memory is never touched with a lock held
+    let infos = SCHEDULER.lock().process_info();
+    let mut count = 0;
+    for info in infos.iter().take(capacity) {
+        let dst = buffer + (count * core::mem::size_of::<ProcessInfo>()) as u64;
+        if let Err(err) = copy_to_user(dst, info) {
+            state.rax = err.as_return();
+            return state as *mut CpuState;
+        }
+        count += 1;
+    }
+
+    state.rax = count as u64;
+    state as *mut CpuState
+}
+
 fn sys_yield(state: &mut CpuState) -> *mut CpuState {
-    unsafe {
-        let scheduler = SCHEDULER.get();
-        if let Some(pid) = scheduler.current_pid {
-            if let Some(process) = scheduler.processes.get_mut(&pid) {
-                process.saved_state = state as *mut CpuState;
-                process.time = 0;
+    let mut scheduler = SCHEDULER.lock();
+    if let Ok(process) = scheduler.current_process() {
+        process.saved_state = state as *mut CpuState;
+        process.time = 0;
+    }
+    unsafe { crate::arch::asm_switch::switch_to_next(&mut scheduler, state as *mut CpuState) }
+}
+
+fn sys_getrlimit(state: &mut CpuState) -> *mut CpuState {
+    // Copied out once the lock is dropped, as the page may need faulting in
+    let limit = SCHEDULER.lock().current_process()
+        .and_then(|process| process.limits.get(state.rdi as u32));
+    let result = limit
+        .and_then(|limit| copy_to_user(state.rsi, &limit))
+        .map(|()| 0);
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_setrlimit(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_from_user::<Rlimit>(state.rsi).and_then(|limit| {
+        SCHEDULER.lock().current_process()?.limits.set(state.rdi as u32, limit)?;
+        Ok(0)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// Resolves a `pid` argument, 0 meaning the caller.
+fn target_pid(scheduler: &mut ProcessManager, pid: u64) -> Result<u32, Errno> {
+    match pid as u32 {
+        0 => scheduler.current_process().map(|process| process.get_pid()),
+        pid => Ok(pid),
+    }
+}
+
+/// `sched_setscheduler(pid, policy, param)`, for the caller or one of its
+/// children.
+fn sys_sched_setscheduler(state: &mut CpuState) -> *mut CpuState {
+    let result = copy_from_user::<SchedParam>(state.rdx).and_then(|param| {
+        let policy = SchedPolicy::from_raw(state.rsi).ok_or(Errno::EINVAL)?;
+        let priority = u8::try_from(param.sched_priority).map_err(|_| Errno::EINVAL)?;
+        let mut scheduler = SCHEDULER.lock();
+        let caller = scheduler.current_process()?.get_pid();
+        let pid = target_pid(&mut scheduler, state.rdi)?;
+        scheduler.check_scheduler_permission(caller, pid)?;
+        scheduler.set_scheduler(pid, policy, priority)?;
+        Ok(0)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_sched_getscheduler(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let result = target_pid(&mut scheduler, state.rdi).and_then(|pid| {
+        let process = scheduler.processes.get(&pid).ok_or(Errno::ESRCH)?;
+        Ok(process.policy as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_sched_getparam(state: &mut CpuState) -> *mut CpuState {
+    let priority = {
+        let mut scheduler = SCHEDULER.lock();
+        target_pid(&mut scheduler, state.rdi).and_then(|pid| {
+            let process = scheduler.processes.get(&pid).ok_or(Errno::ESRCH)?;
+            Ok(process.rt_priority)
+        })
+    };
+    let result = priority.and_then(|priority| {
+        let param = SchedParam { sched_priority: priority as i32 };
+        copy_to_user(state.rsi, &param)?;
+        Ok(0)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// Leaves the caller if it is no longer running, e.g. after stopping or
+/// killing itself with a signal.
+fn reschedule_if_blocked(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let fallback = match scheduler.current_process() {
+        Ok(process) if process.get_state() == ProcessState::Running => return state as *mut CpuState,
+        Ok(process) => {
+            process.saved_state = state as *mut CpuState;
+            process.time = 0;
+            state as *mut CpuState
+        }
+        Err(_) => core::ptr::null_mut(),
+    };
+    unsafe { crate::arch::asm_switch::switch_to_next(&mut scheduler, fallback) }
+}
+
+/// `kill(pid, sig)`: `pid > 0` names a process, `0` the caller's group and
+/// `-pgid` a whole group.
+fn sys_kill(state: &mut CpuState) -> *mut CpuState {
+    let target = state.rdi as i64;
+    let signal = state.rsi as u32;
+
+    let result = {
+        let mut scheduler = SCHEDULER.lock();
+        match target {
+            pid if pid > 0 => scheduler.send_signal(pid as u32, signal),
+            0 => scheduler.current_process()
+                .map(|process| process.pgid)
+                .and_then(|pgid| scheduler.signal_group(pgid, signal)),
+            -1 => Err(Errno::EINVAL),
+            pgid => scheduler.signal_group((-pgid) as u32, signal),
+        }
+    };
+
+    state.rax = match result {
+        Ok(()) => 0,
+        Err(err) => err.as_return(),
+    };
+    reschedule_if_blocked(state)
+}
+
+/// `setpgid(pid, pgid)`, either 0 meaning the caller. The target must be the
+/// caller or one of its children, in the caller's session.
+fn sys_setpgid(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let result = scheduler.current_process()
+        .map(|caller| (caller.get_pid(), caller.sid))
+        .and_then(|(caller_pid, caller_sid)| {
+            let pid = match state.rdi as u32 {
+                0 => caller_pid,
+                pid => pid,
+            };
+            let pgid = match state.rsi as u32 {
+                0 => pid,
+                pgid => pgid,
+            };
+
+            let target = scheduler.processes.get(&pid).ok_or(Errno::ESRCH)?;
+            if pid != caller_pid && target.parent_pid != caller_pid {
+                return Err(Errno::ESRCH);
+            }
+            if target.sid != caller_sid || target.sid == pid {
+                return Err(Errno::EPERM);
             }
+            if pgid != pid && !scheduler.group_in_session(pgid, caller_sid) {
+                return Err(Errno::EPERM);
+            }
+
+            scheduler.processes.get_mut(&pid).ok_or(Errno::ESRCH)?.pgid = pgid;
+            Ok(0)
+        });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_getpgid(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let result = target_pid(&mut scheduler, state.rdi).and_then(|pid| {
+        scheduler.processes.get(&pid)
+            .map(|process| process.pgid as u64)
+            .ok_or(Errno::ESRCH)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// Makes the caller the leader of a new session and process group. The
+/// first session created takes the console as its controlling terminal.
+fn sys_setsid(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let result = scheduler.current_process().and_then(|process| {
+        let pid = process.get_pid();
+        if process.pgid == pid {
+            return Err(Errno::EPERM);
         }
-        crate::arch::asm_switch::switch_to_next(state as *mut CpuState)
-    }
+        process.pgid = pid;
+        process.sid = pid;
+
+        let mut input = INPUT.lock();
+        if input.session.is_none() {
+            input.session = Some(pid);
+            input.foreground_pgid = pid;
+        }
+        Ok(pid as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_getsid(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let result = target_pid(&mut scheduler, state.rdi).and_then(|pid| {
+        scheduler.processes.get(&pid)
+            .map(|process| process.sid as u64)
+            .ok_or(Errno::ESRCH)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// A subset of `rt_sigaction`: `rsi` is `SIG_DFL` (0) or `SIG_IGN` (1) and
+/// the previous disposition is returned the same way.
+fn sys_sigaction(state: &mut CpuState) -> *mut CpuState {
+    let signal = state.rdi as u32;
+    let handler = state.rsi;
+
+    let result = SCHEDULER.lock().current_process().and_then(|process| {
+        if signal == 0 || signal > signal::MAX_SIGNAL || !signal::can_ignore(signal) {
+            return Err(Errno::EINVAL);
+        }
+        let bit = 1u64 << signal;
+        let previous = (process.ignored_signals & bit != 0) as u64;
+        match handler {
+            SIG_DFL => process.ignored_signals &= !bit,
+            SIG_IGN => process.ignored_signals |= bit,
+            _ => return Err(Errno::EINVAL),
+        }
+        Ok(previous)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+/// Makes `rdi` the console's foreground process group. Only members of the
+/// session that owns the console may do this.
+fn sys_tcsetpgrp(state: &mut CpuState) -> *mut CpuState {
+    let pgid = state.rdi as u32;
+    let mut scheduler = SCHEDULER.lock();
+    let mut input = INPUT.lock();
+
+    let result = scheduler.current_process().map(|process| process.sid).and_then(|sid| {
+        if input.session != Some(sid) {
+            return Err(Errno::ENOTTY);
+        }
+        if !scheduler.group_in_session(pgid, sid) {
+            return Err(Errno::EPERM);
+        }
+        input.foreground_pgid = pgid;
+        Ok(0)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
+}
+
+fn sys_tcgetpgrp(state: &mut CpuState) -> *mut CpuState {
+    let mut scheduler = SCHEDULER.lock();
+    let input = INPUT.lock();
+    let result = scheduler.current_process().and_then(|process| {
+        if input.session != Some(process.sid) {
+            return Err(Errno::ENOTTY);
+        }
+        Ok(input.foreground_pgid as u64)
+    });
+
+    state.rax = result.unwrap_or_else(Errno::as_return);
+    state as *mut CpuState
 }
 
 #[no_mangle]
 pub extern "C" fn syscall_dispatch(current_state: *mut CpuState) -> *mut CpuState {
     let state: &mut CpuState = unsafe { &mut *current_state };
+    INTERRUPT_COUNTS.syscall.fetch_add(1, Ordering::Relaxed);
+    // `int 0x80` keeps the caller's RFLAGS.AC, which would lift SMAP
+    protection::end_user_access();
 
     match state.rax {
         0 => sys_read(state),
         1 => sys_write(state),
+        2 => sys_open(state),
         3 => sys_start_process(state),
         4 => sys_wait_process(state),
         5 => sys_yield(state),
+        6 => sys_close(state),
+        7 => sys_poll(state),
+        22 => sys_pipe(state),
+        26 => sys_msync(state),
+        9 => sys_mmap(state),
+        11 => sys_munmap(state),
+        12 => sys_brk(state),
+        13 => sys_sigaction(state),
+        39 => sys_getpid(state),
+        41 => sys_socket(state),
+        42 => sys_connect(state),
+        43 => sys_accept(state),
+        44 => sys_sendto(state),
+        45 => sys_recvfrom(state),
+        49 => sys_bind(state),
+        50 => sys_listen(state),
         60 => sys_exit(state),
+        62 => sys_kill(state),
+        77 => sys_ftruncate(state),
+        97 => sys_getrlimit(state),
+        98 => sys_getrusage(state),
+        100 => sys_times(state),
+        109 => sys_setpgid(state),
+        112 => sys_setsid(state),
+        121 => sys_getpgid(state),
+        124 => sys_getsid(state),
+        143 => sys_sched_getparam(state),
+        144 => sys_sched_setscheduler(state),
+        145 => sys_sched_getscheduler(state),
+        160 => sys_setrlimit(state),
+        213 => sys_epoll_create(state),
+        232 => sys_epoll_wait(state),
+        233 => sys_epoll_ctl(state),
+        500 => sys_process_info(state),
+        501 => sys_tcsetpgrp(state),
+        502 => sys_tcgetpgrp(state),
+        503 => sys_shm_open(state),
+        504 => sys_shm_unlink(state),
+        505 => sys_msg_send(state),
+        506 => sys_msg_recv(state),
+        507 => sys_port_register(state),
+        508 => sys_port_lookup(state),
         _ => {
-            state.rax = u64::MAX;
+            state.rax = Errno::ENOSYS.as_return();
             state as *mut CpuState
         }
     }
@@ -0,0 +1,105 @@
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::MaybeUninit;
+
+use crate::arch::protection::UserAccess;
+use crate::proc::errno::Errno;
+
+/// End of the lower canonical half, where all user mappings live.
+pub const USER_SPACE_END: u64 = 0x0000_8000_0000_0000;
+
+/// Longest path or name accepted from user space.
+pub const MAX_PATH_LEN: usize = 256;
+
+/// Checks that `[addr, addr + len)` is non-null and entirely inside user space.
+pub fn check_user_range(addr: u64, len: usize) -> Result<(), Errno> {
+    let end = addr.checked_add(len as u64).ok_or(Errno::EFAULT)?;
+    if addr == 0 || end > USER_SPACE_END {
+        return Err(Errno::EFAULT);
+    }
+    Ok(())
+}
+
+extern "C" {
+    /// Copies `len` bytes from `src` to `dst` and returns how many were
+    /// left when a fault on an unmapped page cut the copy short.
+    fn user_copy_bytes(dst: *mut u8, src: *const u8, len: usize) -> usize;
+    static user_copy_fault_ip: u8;
+    static user_copy_fixup_ip: u8;
+}
+
+// The only instruction that touches user memory. A page fault on it that
+// nothing can resolve resumes at `user_copy_fixup_ip` instead of halting,
+// with `rcx` still holding what was left to copy.
+core::arch::global_asm!(
+    ".global user_copy_bytes",
+    ".global user_copy_fault_ip",
+    ".global user_copy_fixup_ip",
+    "user_copy_bytes:",
+    "mov rcx, rdx",
+    "user_copy_fault_ip:",
+    "rep movsb",
+    "user_copy_fixup_ip:",
+    "mov rax, rcx",
+    "ret",
+);
+
+/// Where the page fault handler resumes a fault at `ip`, if it hit a user
+/// copy. The copy then fails with `EFAULT`.
+pub fn fixup(ip: u64) -> Option<u64> {
+    let (fault, fixup) = unsafe { (&user_copy_fault_ip as *const u8 as u64, &user_copy_fixup_ip as *const u8 as u64) };
+    (ip == fault).then_some(fixup)
+}
+
+/// Copies `len` bytes between a checked user range and the kernel.
+fn copy(dst: *mut u8, src: *const u8, len: usize) -> Result<(), Errno> {
+    let _access = UserAccess::begin();
+    match unsafe { user_copy_bytes(dst, src, len) } {
+        0 => Ok(()),
+        _ => Err(Errno::EFAULT),
+    }
+}
+
+/// Writes `value` to the user address `addr`.
+pub fn copy_to_user<T: Copy>(addr: u64, value: &T) -> Result<(), Errno> {
+    check_user_range(addr, core::mem::size_of::<T>())?;
+    copy(addr as *mut u8, value as *const T as *const u8, core::mem::size_of::<T>())
+}
+
+/// Reads a `T` from the user address `addr`.
+pub fn copy_from_user<T: Copy>(addr: u64) -> Result<T, Errno> {
+    check_user_range(addr, core::mem::size_of::<T>())?;
+    let mut value = MaybeUninit::<T>::uninit();
+    copy(value.as_mut_ptr() as *mut u8, addr as *const u8, core::mem::size_of::<T>())?;
+    Ok(unsafe { value.assume_init() })
+}
+
+/// Copies `buf` to the user address `addr`.
+pub fn copy_bytes_to_user(addr: u64, buf: &[u8]) -> Result<(), Errno> {
+    if buf.is_empty() {
+        return Ok(());
+    }
+    check_user_range(addr, buf.len())?;
+    copy(addr as *mut u8, buf.as_ptr(), buf.len())
+}
+
+/// Copies `len` bytes from the user address `addr` into a kernel buffer.
+pub fn copy_bytes_from_user(addr: u64, len: usize) -> Result<Vec<u8>, Errno> {
+    if len == 0 {
+        return Ok(Vec::new());
+    }
+    check_user_range(addr, len)?;
+    let mut buf = vec![0u8; len];
+    copy(buf.as_mut_ptr(), addr as *const u8, len)?;
+    Ok(buf)
+}
+
+/// Copies a path or name of `len` bytes from user space, which must be UTF-8.
+pub fn copy_str_from_user(addr: u64, len: usize) -> Result<String, Errno> {
+    if len > MAX_PATH_LEN {
+        return Err(Errno::ENAMETOOLONG);
+    }
+    let bytes = copy_bytes_from_user(addr, len)?;
+    String::from_utf8(bytes).map_err(|_| Errno::EINVAL)
+}
@@ -0,0 +1,71 @@
+use alloc::vec::Vec;
+
+use crate::arch::irq_lock::IrqLock;
+use crate::proc::errno::Errno;
+use crate::proc::process::ProcessState;
+use crate::proc::scheduler::{ProcessManager, SCHEDULER};
+
+/// Wakeups issued while the scheduler was locked further up the stack, e.g.
+/// by a pipe end dropped as its process is torn down. `schedule` delivers
+/// them.
+static DEFERRED_WAKEUPS: IrqLock<Vec<u32>> = IrqLock::new("DEFERRED_WAKEUPS", Vec::new());
+
+/// Processes sleeping in `Waiting` until some event, like data arriving in
+/// a pipe.
+pub struct WaitQueue {
+    waiters: Vec<u32>,
+}
+
+impl WaitQueue {
+    pub const fn new() -> Self {
+        WaitQueue { waiters: Vec::new() }
+    }
+
+    /// Puts the calling process to sleep on this queue. Returns the error
+    /// for the caller to pass up: `ERESTART`, so the system call runs again
+    /// once the process is woken, or `EAGAIN` outside of a process.
+    pub fn sleep(&mut self) -> Errno {
+        let mut scheduler = SCHEDULER.lock();
+        let Ok(process) = scheduler.current_process() else {
+            return Errno::EAGAIN;
+        };
+
+        process.set_state(ProcessState::Waiting);
+        let pid = process.get_pid();
+        self.add(pid);
+        Errno::ERESTART
+    }
+
+    /// Queues `pid` without putting it to sleep, for `poll` which sleeps on
+    /// several queues at once.
+    pub fn add(&mut self, pid: u32) {
+        if !self.waiters.contains(&pid) {
+            self.waiters.push(pid);
+        }
+    }
+
+    /// Makes every sleeper runnable again. They re-check whatever they were
+    /// waiting for, so waking too many is harmless.
+    pub fn wake_all(&mut self) {
+        if self.waiters.is_empty() {
+            return;
+        }
+        if SCHEDULER.is_locked() {
+            DEFERRED_WAKEUPS.lock().append(&mut self.waiters);
+            return;
+        }
+
+        let mut scheduler = SCHEDULER.lock();
+        for pid in self.waiters.drain(..) {
+            scheduler.wake(pid);
+        }
+    }
+}
+
+/// Delivers the wakeups `WaitQueue::wake_all` had to put off.
+pub(crate) fn wake_deferred(scheduler: &mut ProcessManager) {
+    let pids = core::mem::take(&mut *DEFERRED_WAKEUPS.lock());
+    for pid in pids {
+        scheduler.wake(pid);
+    }
+}
@@ -0,0 +1,141 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Times the slab caches against a plain first-fit heap, the kernel
+//! allocator before them, on the same workloads. Cycle counts go to the
+//! serial port; nothing is asserted about them, as emulators time poorly.
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use bootloader::{entry_point, BootInfo};
+use core::alloc::Layout;
+use core::arch::x86_64::_rdtsc;
+use core::panic::PanicInfo;
+use core::ptr::{addr_of_mut, NonNull};
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::serial_println;
+use linked_list_allocator::Heap;
+use x86_64::VirtAddr;
+
+const OBJECTS: usize = 1000;
+const ROUNDS: usize = 10;
+/// Room for `OBJECTS` blocks of the largest size measured.
+const FIRST_FIT_SIZE: usize = 2 * 1024 * 1024;
+
+static mut FIRST_FIT_MEMORY: [u8; FIRST_FIT_SIZE] = [0; FIRST_FIT_SIZE];
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    // The heap grows past its initial size through the shared allocator
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// An allocator under test.
+trait Allocator {
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+/// The kernel's global allocator, slab caches included.
+struct Kernel;
+
+impl Allocator for Kernel {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { alloc::alloc::dealloc(ptr, layout) }
+    }
+}
+
+/// A first-fit heap of its own, over a static buffer.
+struct FirstFit(Heap);
+
+impl FirstFit {
+    fn new() -> Self {
+        let mut heap = Heap::empty();
+        unsafe { heap.init(addr_of_mut!(FIRST_FIT_MEMORY) as usize, FIRST_FIT_SIZE) };
+        FirstFit(heap)
+    }
+}
+
+impl Allocator for FirstFit {
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        self.0.allocate_first_fit(layout).map_or(core::ptr::null_mut(), NonNull::as_ptr)
+    }
+
+    fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.0.deallocate(NonNull::new(ptr).unwrap(), layout) }
+    }
+}
+
+/// Allocates `OBJECTS` blocks, frees every other one and allocates them
+/// again, so the first-fit heap has holes to walk. Returns cycles per
+/// allocation or free.
+fn churn(allocator: &mut impl Allocator, layout: Layout) -> u64 {
+    let mut blocks = Vec::with_capacity(OBJECTS);
+    let start = unsafe { _rdtsc() };
+    for _ in 0..ROUNDS {
+        for _ in 0..OBJECTS {
+            let ptr = allocator.allocate(layout);
+            assert!(!ptr.is_null());
+            blocks.push(ptr);
+        }
+        for ptr in blocks.iter_mut().step_by(2) {
+            allocator.deallocate(*ptr, layout);
+            *ptr = allocator.allocate(layout);
+            assert!(!ptr.is_null());
+        }
+        for ptr in blocks.drain(..) {
+            allocator.deallocate(ptr, layout);
+        }
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+    cycles / (ROUNDS * OBJECTS * 3) as u64
+}
+
+fn compare(size: usize) {
+    let layout = Layout::from_size_align(size, 8).unwrap();
+    // A first run fills the slab caches, which are then measured warm
+    churn(&mut Kernel, layout);
+    let slab = churn(&mut Kernel, layout);
+    let first_fit = churn(&mut FirstFit::new(), layout);
+    serial_println!("{} bytes: slab {} cycles, first-fit {} cycles", size, slab, first_fit);
+}
+
+#[test_case]
+fn bench_small_objects() {
+    compare(32);
+}
+
+#[test_case]
+fn bench_process_sized_objects() {
+    compare(128);
+}
+
+#[test_case]
+fn bench_large_objects() {
+    compare(1024);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
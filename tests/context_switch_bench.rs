@@ -0,0 +1,88 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+//! Times switching between two address spaces and touching a working set
+//! in each, keeping TLB entries across the switch through PCIDs and
+//! flushing them as before. Cycle counts go to the serial port. QEMU only
+//! offers PCIDs with a CPU model that has them, e.g. `-cpu max`.
+
+extern crate alloc;
+
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::arch::x86_64::_rdtsc;
+use core::panic::PanicInfo;
+use game_os::arch::pcid;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::serial_println;
+use x86_64::{PhysAddr, VirtAddr};
+
+const SWITCHES: usize = 1000;
+/// Pages touched after each switch.
+const WORKING_SET: usize = 64;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// Cycles per switch and working set walk between two fresh address
+/// spaces, keeping their TLB entries if `preserve`.
+fn switch_cycles(preserve: bool) -> u64 {
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let spaces: [(PhysAddr, u16); 2] = core::array::from_fn(|_| {
+        let table = memory::create_process_page_table(&mut *memory::FRAME_ALLOCATOR.lock(), phys_mem_offset).unwrap();
+        (table.start_address(), pcid::allocate())
+    });
+    let mut working_set = vec![0u8; WORKING_SET * 4096];
+
+    let start = unsafe { _rdtsc() };
+    for round in 0..SWITCHES {
+        let (table, pcid) = spaces[round % 2];
+        // The first load of each flushes what a previous owner left
+        unsafe { pcid::load(table, pcid, preserve && round >= 2) };
+        for page in working_set.chunks_mut(4096) {
+            page[0] = page[0].wrapping_add(1);
+        }
+    }
+    let cycles = unsafe { _rdtsc() } - start;
+
+    unsafe { pcid::load(memory::kernel_page_table().start_address(), 0, true) };
+    for (_, pcid) in spaces {
+        pcid::release(pcid);
+    }
+    cycles / SWITCHES as u64
+}
+
+#[test_case]
+fn bench_context_switch() {
+    if !pcid::enabled() {
+        serial_println!("no PCID support: every switch flushes the TLB");
+    }
+    let flushing = switch_cycles(false);
+    let tagged = switch_cycles(true);
+    serial_println!(
+        "switch + {} pages: {} cycles flushing, {} cycles with PCIDs",
+        WORKING_SET, flushing, tagged
+    );
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,204 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::arch::pcid;
+use game_os::fs::ramfs::RAMFS;
+use game_os::fs::vfs;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::mem::shm::{self, O_CREAT};
+use game_os::proc::errno::Errno;
+use game_os::proc::scheduler::{ProcessManager, SCHEDULER};
+use game_os::proc::usercopy::copy_bytes_to_user;
+use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessManager) -> R,
+{
+    f(&mut SCHEDULER.lock())
+}
+
+/// Physical address `addr` maps to in `pid`'s address space.
+fn translate(s: &ProcessManager, pid: u32, addr: VirtAddr) -> Option<PhysAddr> {
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let table = phys_mem_offset + s.processes[&pid].memory.page_table_addr.as_u64();
+    let table = unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), phys_mem_offset) };
+    table.translate_addr(addr)
+}
+
+/// A page and a half of a file for mapping.
+static MAPPED_FILE: [u8; 6144] = [0x5A; 6144];
+
+/// Copies `bytes` to `addr` through `pid`'s own address space, as the CPU
+/// would for the process.
+fn write_user(s: &ProcessManager, pid: u32, addr: u64, bytes: &[u8]) {
+    let memory = &s.processes[&pid].memory;
+    unsafe { pcid::switch_to(memory.page_table_addr, memory.pcid) };
+    let written = copy_bytes_to_user(addr, bytes);
+    unsafe { pcid::switch_to(memory::kernel_page_table().start_address(), 0) };
+    written.unwrap();
+}
+
+/// The byte at `addr` in `pid`'s address space.
+fn read_user_byte(s: &ProcessManager, pid: u32, addr: VirtAddr) -> u8 {
+    let phys = translate(s, pid, addr).unwrap();
+    unsafe { *((memory::PHYS_MEM_OFFSET + phys.as_u64()) as *const u8) }
+}
+
+#[test_case]
+fn test_file_mappings_read_pages_on_first_touch() {
+    RAMFS.lock().add("mapped", &MAPPED_FILE);
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = vfs::open("mapped").unwrap();
+        let start = s.map_file(pid, file.clone(), 0, 8192, false, false).unwrap();
+        assert_eq!(translate(s, pid, start), None);
+        assert_eq!(s.map_file(pid, file.clone(), 0, 4096, true, true), Err(Errno::EACCES));
+
+        // Only the page touched is read in, and zeroed past the file's end
+        s.handle_file_fault(pid, start + 4100u64, false).unwrap();
+        assert_eq!(translate(s, pid, start), None);
+        assert_eq!(read_user_byte(s, pid, start + 4096u64), 0x5A);
+        assert_eq!(read_user_byte(s, pid, start + 6143u64), 0x5A);
+        assert_eq!(read_user_byte(s, pid, start + 6144u64), 0);
+
+        assert_eq!(s.handle_file_fault(pid, start, true), Err(Errno::EACCES));
+        assert_eq!(s.handle_file_fault(pid, start + 8192u64, false), Err(Errno::EFAULT));
+
+        let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+        s.unmap_file(pid, start, 8192).unwrap();
+        assert_eq!(translate(s, pid, start + 4096u64), None);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated - 1);
+    });
+}
+
+#[test_case]
+fn test_shared_file_mappings_write_back() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = shm::open("mapped-shm", O_CREAT).unwrap();
+        file.truncate(4096).unwrap();
+        let shared = s.map_file(pid, file.clone(), 0, 4096, true, true).unwrap();
+        let private = s.map_file(pid, file.clone(), 0, 4096, true, false).unwrap();
+        s.handle_file_fault(pid, shared, true).unwrap();
+        s.handle_file_fault(pid, private, true).unwrap();
+
+        // Written through the mappings, so the CPU marks the pages dirty
+        write_user(s, pid, shared.as_u64(), b"shared");
+        write_user(s, pid, private.as_u64() + 8, b"private");
+
+        let mut contents = [0u8; 15];
+        file.read(0, &mut contents).unwrap();
+        assert_eq!(contents, [0; 15]);
+
+        s.sync_files(pid, shared, 4096).unwrap();
+        file.read(0, &mut contents).unwrap();
+        assert_eq!(&contents[..6], b"shared");
+        assert_eq!(contents[6..], [0; 9]);
+
+        // Unmapping writes back too
+        write_user(s, pid, shared.as_u64(), b"SHARED");
+        s.unmap_file(pid, shared, 4096).unwrap();
+        file.read(0, &mut contents).unwrap();
+        assert_eq!(&contents[..6], b"SHARED");
+        shm::unlink("mapped-shm").unwrap();
+    });
+}
+
+#[test_case]
+fn test_shared_file_mappings_share_pages() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let first = s.create_process(LOOP_PROGRAM).unwrap();
+        let second = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = shm::open("shared-pages", O_CREAT).unwrap();
+        file.truncate(4096).unwrap();
+        let other = shm::open("shared-pages", 0).unwrap();
+        let one = s.map_file(first, file.clone(), 0, 4096, true, true).unwrap();
+        let two = s.map_file(second, other, 0, 4096, true, true).unwrap();
+        s.handle_file_fault(first, one, true).unwrap();
+        s.handle_file_fault(second, two, true).unwrap();
+        assert!(translate(s, first, one).is_some());
+        assert_eq!(translate(s, first, one), translate(s, second, two));
+
+        // Neither write-back undoes the other's write
+        write_user(s, first, one.as_u64(), b"one");
+        write_user(s, second, two.as_u64() + 8, b"two");
+        assert_eq!(read_user_byte(s, first, one + 8u64), b't');
+        s.sync_files(second, two, 4096).unwrap();
+        s.sync_files(first, one, 4096).unwrap();
+        let mut contents = [0u8; 11];
+        file.read(0, &mut contents).unwrap();
+        assert_eq!(&contents, b"one\0\0\0\0\0two");
+
+        // The page outlives the first mapping, not the last
+        let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+        s.unmap_file(first, one, 4096).unwrap();
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated);
+        assert_eq!(read_user_byte(s, second, two), b'o');
+        s.terminate_process(second);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated - 1);
+        shm::unlink("shared-pages").unwrap();
+    });
+}
+
+#[test_case]
+fn test_file_mapping_ranges_are_checked() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = shm::open("mapped-range", O_CREAT).unwrap();
+        file.truncate(4096).unwrap();
+        assert_eq!(s.map_file(pid, file.clone(), 0xFFFF_FFFF_FFFF_F000, 4096, false, false), Err(Errno::EINVAL));
+        assert_eq!(s.map_file(pid, file.clone(), 0, usize::MAX - 4096, false, false), Err(Errno::ENOMEM));
+
+        let start = s.map_file(pid, file, 0, 4096, true, true).unwrap();
+        assert_eq!(s.sync_files(pid, start, 1 << 48), Err(Errno::ENOMEM));
+        assert_eq!(s.sync_files(pid, start, usize::MAX), Err(Errno::ENOMEM));
+        s.sync_files(pid, start, 4096).unwrap();
+        shm::unlink("mapped-range").unwrap();
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,148 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use core::sync::atomic::Ordering;
+use game_os::arch::asm_switch::{switch_to_next, CpuState};
+use game_os::arch::fpu::FpuState;
+use game_os::arch::interrupts::INTERRUPT_COUNTS;
+use game_os::arch::pcid;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::proc::scheduler::SCHEDULER;
+use x86_64::registers::control::{Cr0, Cr0Flags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+
+    // Processes are created, which takes frames from the global allocator
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn read_xmm0() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("movq {}, xmm0", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+fn write_xmm0(value: u64) {
+    unsafe { core::arch::asm!("movq xmm0, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+fn read_xmm1() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("movq {}, xmm1", out(reg) value, options(nomem, nostack)) };
+    value
+}
+
+fn write_xmm1(value: u64) {
+    unsafe { core::arch::asm!("movq xmm1, {}", in(reg) value, options(nomem, nostack)) };
+}
+
+/// Switches processes the way the timer does until `pid` runs. The
+/// scheduler is unlocked on return, as the #NM handler takes it.
+fn switch_until(pid: u32) {
+    let mut fallback = CpuState::default();
+    for _ in 0..8 {
+        let mut scheduler = SCHEDULER.lock();
+        unsafe { switch_to_next(&mut scheduler, &mut fallback) };
+        if scheduler.current_pid == Some(pid) {
+            return;
+        }
+    }
+    panic!("process {} was never scheduled", pid);
+}
+
+#[test_case]
+fn save_areas_are_independent() {
+    x86_64::instructions::interrupts::without_interrupts(|| unsafe {
+        core::arch::asm!("clts", options(nomem, nostack));
+
+        let mut first = FpuState::new();
+        let mut second = FpuState::new();
+
+        write_xmm0(0x1111_2222_3333_4444);
+        first.save();
+        write_xmm0(0x5555_6666_7777_8888);
+        second.save();
+
+        first.restore();
+        assert_eq!(read_xmm0(), 0x1111_2222_3333_4444);
+        second.restore();
+        assert_eq!(read_xmm0(), 0x5555_6666_7777_8888);
+
+        Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED));
+    });
+}
+
+#[test_case]
+fn registers_follow_their_process_across_switches() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let (a, b) = {
+            let mut scheduler = SCHEDULER.lock();
+            scheduler.reset();
+            scheduler.init_kernel_process();
+            let a = scheduler.create_process(LOOP_PROGRAM).unwrap();
+            (a, scheduler.create_process(LOOP_PROGRAM).unwrap())
+        };
+        let traps = INTERRUPT_COUNTS.device_not_available.load(Ordering::Relaxed);
+
+        switch_until(a);
+        write_xmm0(0x1111_2222_3333_4444);
+        write_xmm1(0x5555_6666_7777_8888);
+
+        // B's first use saves A's registers before clobbering them
+        switch_until(b);
+        write_xmm0(0x9999_AAAA_BBBB_CCCC);
+        write_xmm1(0xDDDD_EEEE_FFFF_0000);
+
+        switch_until(a);
+        assert_eq!(read_xmm0(), 0x1111_2222_3333_4444);
+        assert_eq!(read_xmm1(), 0x5555_6666_7777_8888);
+        // Each process trapped once on its first FPU instruction after a switch
+        assert_eq!(INTERRUPT_COUNTS.device_not_available.load(Ordering::Relaxed), traps + 3);
+
+        unsafe { pcid::switch_to(memory::kernel_page_table().start_address(), 0) };
+        let mut scheduler = SCHEDULER.lock();
+        scheduler.terminate_process(a);
+        scheduler.terminate_process(b);
+        scheduler.reset();
+        unsafe { Cr0::update(|flags| flags.insert(Cr0Flags::TASK_SWITCHED)) };
+    });
+}
+
+#[test_case]
+fn sse_is_enabled() {
+    let cr0 = Cr0::read();
+    assert!(!cr0.contains(Cr0Flags::EMULATE_COPROCESSOR));
+    assert!(cr0.contains(Cr0Flags::MONITOR_COPROCESSOR));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -8,11 +8,16 @@ extern crate alloc;
 
 use bootloader::{entry_point, BootInfo};
 use core::panic::PanicInfo;
-use game_os::allocator;
+use game_os::mem::allocator;
 use alloc::boxed::Box;
+use alloc::vec;
 use alloc::vec::Vec;
-use game_os::allocator::HEAP_SIZE;
-use game_os::memory::{self, BootInfoFrameAllocator};
+use core::alloc::Layout;
+use game_os::mem::allocator::HEAP_SIZE;
+use game_os::mem::slab;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::Translate;
 use x86_64::VirtAddr;
 
 entry_point!(main);
@@ -22,10 +27,15 @@ fn main(boot_info: &'static BootInfo) -> ! {
     game_os::init();
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe {
+    let frame_allocator = unsafe {
         BootInfoFrameAllocator::init(&boot_info.memory_map)
     };
-    allocator::init_heap(&mut mapper, &mut frame_allocator)
+
+    // Growing the heap takes frames from the global allocator
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
         .expect("heap initialization failed");
 
     test_main();
@@ -59,6 +69,73 @@ fn many_boxes() {
     }
 }
 
+#[test_case]
+fn stats_track_allocations() {
+    let before = allocator::stats();
+    let block = Box::new([0u8; 4096]);
+    let during = allocator::stats();
+    assert_eq!(during.allocations, before.allocations + 1);
+    assert!(during.used >= before.used + 4096);
+    assert!(during.peak >= during.used);
+    assert!(during.largest_free <= during.free);
+    drop(block);
+    assert_eq!(allocator::stats().allocations, before.allocations);
+}
+
+#[test_case]
+fn small_blocks_are_reused() {
+    let first = Box::new(7u64);
+    let addr = &*first as *const u64;
+    drop(first);
+    let second = Box::new(9u64);
+    assert_eq!(&*second as *const u64, addr);
+}
+
+#[test_case]
+fn slab_blocks_are_aligned() {
+    for size in slab::SIZE_CLASSES {
+        let layout = Layout::from_size_align(size, size).unwrap();
+        let ptr = unsafe { alloc::alloc::alloc(layout) };
+        assert!(!ptr.is_null());
+        assert_eq!(ptr as usize % size, 0);
+        unsafe { alloc::alloc::dealloc(ptr, layout) };
+    }
+}
+
+#[test_case]
+fn slab_stats_count_blocks() {
+    let before = allocator::slab_stats()[3];
+    assert_eq!(before.block_size, 64);
+    let block = Box::new([0u8; 64]);
+    let during = allocator::slab_stats()[3];
+    assert_eq!(during.in_use, before.in_use + 1);
+    drop(block);
+    assert_eq!(allocator::slab_stats()[3].in_use, before.in_use);
+}
+
+#[test_case]
+fn test_heap_grows_on_demand() {
+    let block = vec![0u8; 2 * allocator::HEAP_SIZE];
+    assert_eq!(block.len(), 2 * allocator::HEAP_SIZE);
+    let heap = allocator::stats();
+    assert!(heap.size > allocator::HEAP_SIZE);
+    assert!(heap.size <= allocator::HEAP_MAX_SIZE);
+    assert!(heap.peak >= block.len());
+}
+
+#[test_case]
+fn test_heap_grows_with_huge_pages() {
+    let block = vec![0u8; 5 * 1024 * 1024];
+    let heap = allocator::stats();
+    let kernel = unsafe { memory::kernel_mapper() };
+    let huge = (allocator::HEAP_START..allocator::HEAP_START + heap.size)
+        .step_by(2 * 1024 * 1024)
+        .map(|addr| VirtAddr::new(addr as u64).align_up(2 * 1024 * 1024u64))
+        .any(|addr| matches!(kernel.translate(addr), TranslateResult::Mapped { frame: MappedFrame::Size2MiB(_), .. }));
+    assert!(huge);
+    drop(block);
+}
+
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     game_os::test_panic_handler(info)
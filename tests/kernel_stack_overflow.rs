@@ -0,0 +1,89 @@
+#![feature(abi_x86_interrupt)]
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+use bootloader::{entry_point, BootInfo};
+use game_os::arch::gdt;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::mem::{allocator, kstack};
+use game_os::{exit_qemu, serial_print, serial_println, QemuExitCode};
+use lazy_static::lazy_static;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::VirtAddr;
+
+const PID: u32 = 42;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("kernel_stack_overflow::guard_page_names_process...\t");
+
+    gdt::init();
+    init_test_idt();
+
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    // Overflow a stack from the stack region, as a system call would
+    let stack = kstack::allocate(PID).expect("no kernel stack");
+    unsafe {
+        core::arch::asm!(
+            "mov rsp, {top}",
+            "call {overflow}",
+            top = in(reg) stack.top().as_u64(),
+            overflow = sym stack_overflow,
+            options(noreturn),
+        );
+    }
+}
+
+#[allow(unconditional_recursion)]
+extern "C" fn stack_overflow() {
+    stack_overflow(); // for each recursion, the return address is pushed
+    volatile::Volatile::new(0).read(); // prevent tail recursion optimizations
+}
+
+lazy_static! {
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        let mut idt = InterruptDescriptorTable::new();
+        unsafe {
+            idt.double_fault
+                .set_handler_fn(test_double_fault_handler)
+                .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
+        }
+
+        idt
+    };
+}
+
+extern "x86-interrupt" fn test_double_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    _error_code: u64,
+) -> ! {
+    if kstack::overflowed(Cr2::read()) == Some(PID) {
+        serial_println!("[ok]");
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        serial_println!("[failed]");
+        serial_println!("Error: double fault at {:?} not in process {}'s stack guard", Cr2::read(), PID);
+        exit_qemu(QemuExitCode::Failed);
+    }
+    loop {}
+}
+
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,160 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::arch::{pcid, protection};
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::proc::errno::Errno;
+use game_os::proc::process::ProcessState;
+use game_os::proc::scheduler::SCHEDULER;
+use game_os::proc::usercopy::{copy_from_user, copy_to_user};
+use x86_64::registers::control::{Cr4, Cr4Flags};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// Writes `jmp $` to its stack and jumps there.
+const EXECUTE_STACK: &[u8] = &[
+    0x48, 0x83, 0xEC, 0x08,             // sub rsp, 8
+    0x66, 0xC7, 0x04, 0x24, 0xEB, 0xFE, // mov word ptr [rsp], 0xFEEB
+    0xFF, 0xE4,                         // jmp rsp
+];
+
+/// Overwrites its first instruction, then spins.
+const WRITE_CODE: &[u8] = &[
+    0xC6, 0x04, 0x25, 0x00, 0x00, 0x40, 0x00, 0x90, // mov byte ptr [0x400000], 0x90
+    0xEB, 0xFE,                                     // jmp $
+];
+
+/// Writes its stack and reads its code, which is allowed, then spins.
+const WELL_BEHAVED: &[u8] = &[
+    0x48, 0x83, 0xEC, 0x08,                         // sub rsp, 8
+    0xC6, 0x04, 0x24, 0x01,                         // mov byte ptr [rsp], 1
+    0x8A, 0x04, 0x25, 0x00, 0x00, 0x40, 0x00,       // mov al, byte ptr [0x400000]
+    0xEB, 0xFE,                                     // jmp $
+];
+
+/// Timer ticks a process gets to misbehave in.
+const DEADLINE: u64 = 100;
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+/// Starts `program` next to this test, which runs as the kernel process.
+fn start(program: &[u8]) -> u32 {
+    let mut scheduler = SCHEDULER.lock();
+    scheduler.reset();
+    scheduler.init_kernel_process();
+    scheduler.init_idle_task();
+    scheduler.create_process(program).unwrap()
+}
+
+/// Lets `pid` run until it terminates or `DEADLINE` ticks pass, and says
+/// whether it terminated.
+fn run(pid: u32) -> bool {
+    let deadline = SCHEDULER.lock().ticks + DEADLINE;
+    loop {
+        {
+            let mut scheduler = SCHEDULER.lock();
+            if scheduler.processes[&pid].get_state() == ProcessState::Terminated {
+                return true;
+            }
+            if scheduler.ticks >= deadline {
+                scheduler.terminate_process(pid);
+                return false;
+            }
+        }
+        x86_64::instructions::hlt();
+    }
+}
+
+#[test_case]
+fn test_executing_the_stack_kills_the_process() {
+    if !protection::nx_enabled() {
+        return;
+    }
+    let pid = start(EXECUTE_STACK);
+    assert!(run(pid));
+}
+
+#[test_case]
+fn test_writing_code_kills_the_process() {
+    let pid = start(WRITE_CODE);
+    assert!(run(pid));
+}
+
+#[test_case]
+fn test_allowed_accesses_do_not_kill() {
+    let pid = start(WELL_BEHAVED);
+    assert!(!run(pid));
+}
+
+#[test_case]
+fn test_smep_and_smap_follow_the_cpu() {
+    let cr4 = Cr4::read();
+    assert_eq!(cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION), protection::smep_enabled());
+    assert_eq!(cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION), protection::smap_enabled());
+}
+
+#[test_case]
+fn test_user_copies_pass_smap() {
+    let pid = start(WELL_BEHAVED);
+    let mut scheduler = SCHEDULER.lock();
+    let memory = &scheduler.processes[&pid].memory;
+    let (table, tag) = (memory.page_table_addr, memory.pcid);
+
+    // In the process's address space, with the scheduler lock keeping the
+    // timer from switching away
+    let stack = memory.stack_start().as_u64() - 4096;
+    unsafe { pcid::switch_to(table, tag) };
+    let copied = copy_to_user(stack, &0x1234_5678u32).and_then(|()| copy_from_user::<u32>(stack));
+    unsafe { pcid::switch_to(memory::kernel_page_table().start_address(), 0) };
+
+    assert_eq!(copied, Ok(0x1234_5678));
+    scheduler.terminate_process(pid);
+}
+
+#[test_case]
+fn test_user_copies_to_unmapped_pages_fail() {
+    let pid = start(WELL_BEHAVED);
+    let mut scheduler = SCHEDULER.lock();
+    let memory = &scheduler.processes[&pid].memory;
+    let (table, tag) = (memory.page_table_addr, memory.pcid);
+
+    // Nothing is mapped this low but the code, nor straddling the stack top
+    let unmapped = 0x1000;
+    let straddling = memory.stack_start().as_u64() - 2;
+    unsafe { pcid::switch_to(table, tag) };
+    let written = copy_to_user(unmapped, &1u64);
+    let read = copy_from_user::<u64>(unmapped);
+    let partial = copy_to_user(straddling, &1u32);
+    unsafe { pcid::switch_to(memory::kernel_page_table().start_address(), 0) };
+
+    assert_eq!(written, Err(Errno::EFAULT));
+    assert_eq!(read, Err(Errno::EFAULT));
+    assert_eq!(partial, Err(Errno::EFAULT));
+    scheduler.terminate_process(pid);
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,194 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::string::String;
+use bootloader::{entry_point, BootInfo};
+use core::net::Ipv4Addr;
+use core::panic::PanicInfo;
+use game_os::fs::poll::{POLLHUP, POLLIN};
+use game_os::fs::vfs;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::net::{self, ipv4, unix, SocketAddr, AF_INET, AF_UNIX, SOCK_DGRAM, SOCK_STREAM};
+use game_os::proc::errno::Errno;
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn local_addr(name: &str) -> SocketAddr {
+    SocketAddr::Unix(Some(String::from(name)))
+}
+
+#[test_case]
+fn test_stream_socket_connects_through_listener() {
+    let server = net::socket(AF_UNIX, SOCK_STREAM, 0).unwrap();
+    let listener = server.socket().unwrap();
+    listener.bind(&local_addr("test-game")).unwrap();
+    listener.listen(1).unwrap();
+    assert_eq!(vfs::open("/test-game").err(), Some(Errno::ENXIO));
+
+    let client = net::socket(AF_UNIX, SOCK_STREAM, 0).unwrap();
+    client.socket().unwrap().connect(&local_addr("test-game")).unwrap();
+    let other = net::socket(AF_UNIX, SOCK_STREAM, 0).unwrap();
+    assert_eq!(other.socket().unwrap().connect(&local_addr("test-game")), Err(Errno::ECONNREFUSED));
+
+    let (connection, peer) = listener.accept().unwrap();
+    assert_eq!(peer, SocketAddr::Unix(None));
+    assert_eq!(listener.accept().err(), Some(Errno::EAGAIN));
+
+    let mut buf = [0u8; 8];
+    assert_eq!(client.write(0, b"move"), Ok(4));
+    assert_eq!(connection.read(0, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"move");
+    assert_eq!(connection.write(0, b"ok"), Ok(2));
+    assert_eq!(client.read(0, &mut buf), Ok(2));
+
+    drop(client);
+    assert_eq!(connection.poll() & POLLHUP, POLLHUP);
+    assert_eq!(connection.read(0, &mut buf), Ok(0));
+}
+
+#[test_case]
+fn test_datagram_socket_reports_sender() {
+    let server = net::socket(AF_UNIX, SOCK_DGRAM, 0).unwrap();
+    let client = net::socket(AF_UNIX, SOCK_DGRAM, 0).unwrap();
+    let (server, client) = (server.socket().unwrap(), client.socket().unwrap());
+    server.bind(&local_addr("test-dgram-server")).unwrap();
+    client.bind(&local_addr("test-dgram-client")).unwrap();
+
+    assert_eq!(client.send(b"x", None, false), Err(Errno::EDESTADDRREQ));
+    assert_eq!(client.send(b"ping", Some(&local_addr("test-dgram-server")), false), Ok(4));
+    client.connect(&local_addr("test-dgram-server")).unwrap();
+    assert_eq!(client.send(b"pong", None, false), Ok(4));
+
+    let mut buf = [0u8; 2];
+    let (count, from) = server.recv(&mut buf, true).unwrap();
+    assert_eq!((count, &buf, from), (2, b"pi", local_addr("test-dgram-client")));
+    assert_eq!(server.recv(&mut buf, true).unwrap().0, 2);
+    assert_eq!(server.recv(&mut buf, true).err(), Some(Errno::EAGAIN));
+}
+
+#[test_case]
+fn test_socket_names_are_released_on_close() {
+    let first = net::socket(AF_UNIX, SOCK_STREAM, 0).unwrap();
+    first.socket().unwrap().bind(&local_addr("test-name")).unwrap();
+    let second = net::socket(AF_UNIX, SOCK_STREAM, 0).unwrap();
+    assert_eq!(second.socket().unwrap().bind(&local_addr("test-name")), Err(Errno::EADDRINUSE));
+    assert_eq!(second.socket().unwrap().connect(&local_addr("test-name")), Err(Errno::ECONNREFUSED));
+
+    drop(first);
+    assert!(!unix::is_bound("test-name"));
+    assert_eq!(second.socket().unwrap().connect(&local_addr("test-name")), Err(Errno::ENOENT));
+    second.socket().unwrap().bind(&local_addr("test-name")).unwrap();
+}
+
+#[test_case]
+fn test_socket_addresses_round_trip() {
+    let addr = SocketAddr::Inet(Ipv4Addr::new(10, 0, 2, 2), 8080);
+    let bytes = addr.encode();
+    assert_eq!(bytes.len(), 16);
+    assert_eq!(&bytes[2..8], &[0x1F, 0x90, 10, 0, 2, 2]);
+    assert_eq!(SocketAddr::parse(&bytes), Ok(addr));
+    assert_eq!(SocketAddr::parse(&local_addr("test-path").encode()), Ok(local_addr("test-path")));
+    assert_eq!(SocketAddr::parse(&[0xFF, 0]), Err(Errno::EAFNOSUPPORT));
+}
+
+#[test_case]
+fn test_ipv4_checksum() {
+    // A textbook example header, its checksum included
+    let header = [
+        0x45, 0x00, 0x00, 0x73, 0x00, 0x00, 0x40, 0x00, 0x40, 0x11,
+        0xB8, 0x61, 0xC0, 0xA8, 0x00, 0x01, 0xC0, 0xA8, 0x00, 0xC7,
+    ];
+    assert_eq!(ipv4::checksum(&header), 0);
+    let packet = ipv4::build(Ipv4Addr::LOCALHOST, Ipv4Addr::LOCALHOST, ipv4::PROTO_UDP, 7, b"odd");
+    assert_eq!(ipv4::checksum(&packet[..ipv4::HEADER_SIZE]), 0);
+    assert!(ipv4::parse(&packet).is_some_and(|(_, payload)| payload == b"odd"));
+}
+
+#[test_case]
+fn test_udp_over_loopback() {
+    let server = net::socket(AF_INET, SOCK_DGRAM, 0).unwrap();
+    let client = net::socket(AF_INET, SOCK_DGRAM, 0).unwrap();
+    let server_addr = SocketAddr::Inet(Ipv4Addr::LOCALHOST, 7001);
+    server.socket().unwrap().bind(&server_addr).unwrap();
+    let other = net::socket(AF_INET, SOCK_DGRAM, 0).unwrap();
+    assert_eq!(other.socket().unwrap().bind(&server_addr), Err(Errno::EADDRINUSE));
+
+    assert_eq!(client.socket().unwrap().send(b"ping", Some(&server_addr), false), Ok(4));
+    net::poll();
+    let mut buf = [0u8; 8];
+
+    let (count, from) = server.socket().unwrap().recv(&mut buf, true).unwrap();
+    assert_eq!(&buf[..count], b"ping");
+    let SocketAddr::Inet(addr, port) = from else {
+        panic!("not an inet address");
+    };
+    assert_eq!(addr, Ipv4Addr::LOCALHOST);
+    assert!(port >= net::EPHEMERAL_PORT_START);
+}
+
+#[test_case]
+fn test_tcp_connection_over_loopback() {
+    let server = net::socket(AF_INET, SOCK_STREAM, 0).unwrap();
+    let server_addr = SocketAddr::Inet(Ipv4Addr::LOCALHOST, 7002);
+    let listener = server.socket().unwrap();
+    listener.bind(&server_addr).unwrap();
+    listener.listen(4).unwrap();
+
+    // Outside a process the handshake can't be slept through
+    let client = net::socket(AF_INET, SOCK_STREAM, 0).unwrap();
+    assert_eq!(client.socket().unwrap().connect(&server_addr), Err(Errno::EAGAIN));
+    net::poll();
+    assert_eq!(client.socket().unwrap().connect(&server_addr), Ok(()));
+    assert_eq!(client.socket().unwrap().connect(&server_addr), Err(Errno::EISCONN));
+
+    let (connection, peer) = listener.accept().unwrap();
+    assert!(matches!(peer, SocketAddr::Inet(addr, _) if addr == Ipv4Addr::LOCALHOST));
+    assert_eq!(listener.accept().err(), Some(Errno::EAGAIN));
+
+    let mut buf = [0u8; 8];
+    assert_eq!(client.write(0, b"move"), Ok(4));
+    net::poll();
+    assert_eq!(connection.poll() & POLLIN, POLLIN);
+    assert_eq!(connection.read(0, &mut buf), Ok(4));
+    assert_eq!(&buf[..4], b"move");
+
+    drop(client);
+    net::poll();
+    assert_eq!(connection.read(0, &mut buf), Ok(0));
+    assert_eq!(net::socket(AF_INET, SOCK_STREAM, 17).err(), Some(Errno::EPROTONOSUPPORT));
+}
+
+#[test_case]
+fn test_tcp_connect_to_closed_port_is_refused() {
+    let client = net::socket(AF_INET, SOCK_STREAM, 0).unwrap();
+    let addr = SocketAddr::Inet(Ipv4Addr::LOCALHOST, 7003);
+    assert_eq!(client.socket().unwrap().connect(&addr), Err(Errno::EAGAIN));
+    net::poll();
+    assert_eq!(client.socket().unwrap().connect(&addr), Err(Errno::ECONNREFUSED));
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
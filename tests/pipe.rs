@@ -0,0 +1,99 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::fs::pipe::{pipe, PIPE_CAPACITY};
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::proc::errno::Errno;
+use game_os::proc::process::ProcessState;
+use game_os::proc::scheduler::{ProcessManager, SCHEDULER};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessManager) -> R,
+{
+    f(&mut SCHEDULER.lock())
+}
+
+#[test_case]
+fn test_pipe_transfers_then_reports_eof() {
+    let (reader, writer) = pipe();
+    assert_eq!(writer.write(0, b"hello"), Ok(5));
+
+    let mut buf = [0u8; 8];
+    assert_eq!(reader.read(0, &mut buf), Ok(5));
+    assert_eq!(&buf[..5], b"hello");
+
+    drop(writer);
+    assert_eq!(reader.read(0, &mut buf), Ok(0));
+}
+
+#[test_case]
+fn test_pipe_write_without_reader_fails() {
+    let (reader, writer) = pipe();
+    drop(reader);
+    assert_eq!(writer.write(0, b"x"), Err(Errno::EPIPE));
+}
+
+#[test_case]
+fn test_pipe_write_is_bounded() {
+    let (_reader, writer) = pipe();
+    let data = [0u8; PIPE_CAPACITY + 100];
+    assert_eq!(writer.write(0, &data), Ok(PIPE_CAPACITY));
+}
+
+#[test_case]
+fn test_pipe_reader_sleeps_until_written() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let pid = with_scheduler(|s| {
+            s.reset();
+            s.init_kernel_process();
+            let pid = s.create_process(LOOP_PROGRAM).unwrap();
+            assert_eq!(s.schedule(), Some(pid));
+            pid
+        });
+
+        let (reader, writer) = pipe();
+        let mut buf = [0u8; 4];
+        assert_eq!(reader.read(0, &mut buf), Err(Errno::ERESTART));
+        with_scheduler(|s| assert_eq!(s.processes[&pid].get_state(), ProcessState::Waiting));
+
+        assert_eq!(writer.write(0, b"hi"), Ok(2));
+        with_scheduler(|s| {
+            assert_eq!(s.processes[&pid].get_state(), ProcessState::Ready);
+            s.reset();
+        });
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,131 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::fs::pipe::pipe;
+use game_os::fs::poll::{self, Epoll, EpollEvent, EPOLL_CTL_ADD, EPOLL_CTL_DEL, POLLERR, POLLHUP, POLLIN, POLLOUT};
+use game_os::fs::vfs::OpenFile;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::proc::errno::Errno;
+use game_os::proc::process::ProcessState;
+use game_os::proc::scheduler::{ProcessManager, SCHEDULER};
+use x86_64::VirtAddr;
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessManager) -> R,
+{
+    f(&mut SCHEDULER.lock())
+}
+
+#[test_case]
+fn test_pipe_readiness() {
+    let (reader, writer) = pipe();
+    assert_eq!(reader.poll(), 0);
+    assert_eq!(writer.poll(), POLLOUT);
+
+    writer.write(0, b"x").unwrap();
+    assert_eq!(reader.poll(), POLLIN);
+    drop(writer);
+    assert_eq!(reader.poll(), POLLIN | POLLHUP);
+
+    let (reader, writer) = pipe();
+    drop(reader);
+    assert_eq!(writer.poll(), POLLERR);
+}
+
+#[test_case]
+fn test_epoll_reports_ready_interest() {
+    let epoll = Epoll::new();
+    let (reader, writer) = pipe();
+    let reader = OpenFile::new(reader);
+    let event = EpollEvent { events: POLLIN as u32, data: 42 };
+
+    let instance = epoll.epoll().unwrap();
+    instance.control(EPOLL_CTL_ADD, 3, &reader, event).unwrap();
+    assert_eq!(instance.control(EPOLL_CTL_ADD, 3, &reader, event), Err(Errno::EEXIST));
+    assert!(instance.ready(8).0.is_empty());
+
+    writer.write(0, b"x").unwrap();
+    let (ready, files) = instance.ready(8);
+    assert_eq!(files.len(), 1);
+    assert_eq!((ready[0].events, { ready[0].data }), (POLLIN as u32, 42));
+
+    instance.control(EPOLL_CTL_DEL, 3, &reader, event).unwrap();
+    assert!(instance.ready(8).1.is_empty());
+
+    // Closed files leave the interest list by themselves
+    instance.control(EPOLL_CTL_ADD, 3, &reader, event).unwrap();
+    drop(files);
+    drop(reader);
+    assert!(instance.ready(8).1.is_empty());
+}
+
+#[test_case]
+fn test_poll_sleep_times_out() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let pid = with_scheduler(|s| {
+            s.reset();
+            s.init_kernel_process();
+            let pid = s.create_process(LOOP_PROGRAM).unwrap();
+            assert_eq!(s.schedule(), Some(pid));
+            pid
+        });
+
+        assert_eq!(poll::sleep(&[], 10), Err(Errno::ERESTART));
+        let deadline = with_scheduler(|s| {
+            let process = &s.processes[&pid];
+            assert_eq!(process.get_state(), ProcessState::Waiting);
+            process.wake_at.unwrap()
+        });
+
+        with_scheduler(|s| {
+            s.ticks = deadline - 1;
+            s.wake_expired();
+            assert_eq!(s.processes[&pid].get_state(), ProcessState::Waiting);
+            s.ticks = deadline;
+            s.wake_expired();
+            assert_eq!(s.processes[&pid].get_state(), ProcessState::Ready);
+            assert_eq!(s.schedule(), Some(0));
+            assert_eq!(s.schedule(), Some(pid));
+        });
+
+        // The restarted call finds its deadline passed
+        assert_eq!(poll::sleep(&[], 10), Ok(()));
+        with_scheduler(|s| {
+            assert_eq!(s.processes[&pid].wake_at, None);
+            s.reset();
+        });
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -25,13 +25,10 @@ fn main(boot_info: &'static BootInfo) -> ! {
     };
 
     // Store globals
-    unsafe {
-        memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64();
-        memory::FRAME_ALLOCATOR.init(frame_allocator);
-    }
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
 
-    let alloc = unsafe { memory::FRAME_ALLOCATOR.get() };
-    allocator::init_heap(&mut mapper, alloc)
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
         .expect("heap initialization failed");
     
     test_main();
@@ -43,8 +40,7 @@ where
     F: FnOnce(&mut game_os::scheduler::ProcessManager) -> R,
 {
     x86_64::instructions::interrupts::without_interrupts(|| {
-        let scheduler = unsafe { SCHEDULER.get() };
-        f(scheduler)
+        f(&mut SCHEDULER.lock())
     })
 }
 
@@ -59,8 +55,8 @@ fn test_create_two_processes() {
         s.reset();
         s.init_kernel_process();
 
-        let pid1 = s.create_process(nop_process);
-        let pid2 = s.create_process(nop_process);
+        let pid1 = s.create_process(nop_process).unwrap();
+        let pid2 = s.create_process(nop_process).unwrap();
 
         assert_eq!(pid1, 1);
         assert_eq!(pid2, 2);
@@ -77,9 +73,9 @@ fn test_schedule_order() {
         s.reset();
         s.init_kernel_process();
 
-        let pid1 = s.create_process(nop_process);
-        let pid2 = s.create_process(nop_process);
-        let pid3 = s.create_process(nop_process);
+        let pid1 = s.create_process(nop_process).unwrap();
+        let pid2 = s.create_process(nop_process).unwrap();
+        let pid3 = s.create_process(nop_process).unwrap();
 
         assert_eq!(s.schedule(), Some(pid1));
         assert_eq!(s.schedule(), Some(pid2));
@@ -96,9 +92,9 @@ fn test_terminate_skips_dead() {
         s.reset();
         s.init_kernel_process();
 
-        let pid1 = s.create_process(nop_process);
-        let pid2 = s.create_process(nop_process);
-        let pid3 = s.create_process(nop_process);
+        let pid1 = s.create_process(nop_process).unwrap();
+        let pid2 = s.create_process(nop_process).unwrap();
+        let pid3 = s.create_process(nop_process).unwrap();
 
         s.terminate_process(pid2);
         assert_eq!(s.schedule(), Some(pid1));
@@ -115,8 +111,8 @@ fn test_terminate_current() {
         s.reset();
         s.init_kernel_process();
 
-        let pid1 = s.create_process(nop_process);
-        let pid2 = s.create_process(nop_process);
+        let pid1 = s.create_process(nop_process).unwrap();
+        let pid2 = s.create_process(nop_process).unwrap();
 
         s.schedule();
         assert_eq!(s.current_pid, Some(pid1));
@@ -137,7 +133,7 @@ fn test_ten_processes() {
 
         let mut pids = [0u32; 10];
         for i in 0..10 {
-            pids[i] = s.create_process(nop_process);
+            pids[i] = s.create_process(nop_process).unwrap();
         }
 
         for i in 0..10 {
@@ -159,7 +155,7 @@ fn test_initial_cpu_state() {
         s.reset();
         s.init_kernel_process();
 
-        let pid = s.create_process(nop_process);
+        let pid = s.create_process(nop_process).unwrap();
         let process = s.processes.get(&pid).unwrap();
 
         assert!(!process.saved_state.is_null());
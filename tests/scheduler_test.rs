@@ -0,0 +1,783 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::arch::pcid;
+use game_os::fs::vfs::{self, OpenFile};
+use game_os::mem::allocator;
+use game_os::mem::anon;
+use game_os::mem::aslr;
+use game_os::mem::kstack;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::mem::shm::{self, O_CREAT, O_EXCL};
+use game_os::proc::errno::Errno;
+use game_os::proc::ipc::{MAILBOX_CAPACITY, MSG_MAX_SIZE};
+use game_os::proc::process::{ProcessState, SchedPolicy};
+use game_os::proc::rt::{RT_PERIOD, RT_RUNTIME, TIME_SLICE};
+use game_os::proc::rlimit::{ResourceLimits, Rlimit, RLIMIT_AS, RLIMIT_NOFILE, RLIMIT_NPROC};
+use game_os::proc::scheduler::{ProcessManager, IDLE_PID, SCHEDULER};
+use game_os::proc::signal::{SIGCONT, SIGINT, SIGKILL, SIGTSTP};
+use x86_64::structures::paging::mapper::{MappedFrame, TranslateResult};
+use x86_64::structures::paging::{OffsetPageTable, PageTable, PageTableFlags, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe {
+        BootInfoFrameAllocator::init(&boot_info.memory_map)
+    };
+
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessManager) -> R,
+{
+    f(&mut SCHEDULER.lock())
+}
+
+#[test_case]
+fn test_scheduler_lock_masks_interrupts() {
+    use x86_64::instructions::interrupts;
+
+    assert!(interrupts::are_enabled());
+    {
+        let _scheduler = SCHEDULER.lock();
+        assert!(!interrupts::are_enabled());
+        assert!(SCHEDULER.is_locked());
+    }
+    assert!(interrupts::are_enabled());
+    assert!(!SCHEDULER.is_locked());
+}
+
+#[test_case]
+fn test_idle_runs_when_nothing_ready() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        s.init_idle_task();
+
+        s.processes.get_mut(&0).unwrap().set_state(ProcessState::Waiting);
+        assert_eq!(s.schedule(), Some(IDLE_PID));
+        assert_eq!(s.schedule(), Some(IDLE_PID));
+        assert!(!s.ready_queue.contains(&IDLE_PID));
+    });
+}
+
+#[test_case]
+fn test_idle_yields_to_ready_process() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        s.init_idle_task();
+
+        s.processes.get_mut(&0).unwrap().set_state(ProcessState::Waiting);
+        assert_eq!(s.schedule(), Some(IDLE_PID));
+
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+        assert_eq!(s.schedule(), Some(pid));
+        assert_eq!(s.processes.get(&IDLE_PID).unwrap().get_state(), ProcessState::Ready);
+        assert!(!s.ready_queue.contains(&IDLE_PID));
+
+        s.terminate_process(pid);
+        assert_eq!(s.schedule(), Some(IDLE_PID));
+    });
+}
+
+#[test_case]
+fn test_idle_cannot_be_terminated() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        s.init_idle_task();
+
+        s.terminate_process(IDLE_PID);
+        assert_ne!(s.processes.get(&IDLE_PID).unwrap().get_state(), ProcessState::Terminated);
+    });
+}
+
+#[test_case]
+fn test_stop_and_continue() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let pid1 = s.create_process(LOOP_PROGRAM).unwrap();
+        let pid2 = s.create_process(LOOP_PROGRAM).unwrap();
+
+        s.send_signal(pid1, SIGTSTP).unwrap();
+        assert_eq!(s.processes.get(&pid1).unwrap().get_state(), ProcessState::Stopped);
+        assert_eq!(s.schedule(), Some(pid2));
+
+        s.send_signal(pid1, SIGCONT).unwrap();
+        assert_eq!(s.processes.get(&pid1).unwrap().get_state(), ProcessState::Ready);
+        assert_eq!(s.schedule(), Some(0));
+        assert_eq!(s.schedule(), Some(pid1));
+    });
+}
+
+#[test_case]
+fn test_signals_to_zombies_do_nothing() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+        s.processes.get_mut(&pid).unwrap().stats.user_ticks = 5;
+
+        s.send_signal(pid, SIGKILL).unwrap();
+        assert_eq!(s.processes[&0].stats.children_user_ticks, 5);
+
+        // Still there to be waited for, but not counted twice
+        assert_eq!(s.send_signal(pid, SIGINT), Ok(()));
+        s.terminate_process(pid);
+        assert_eq!(s.processes[&0].stats.children_user_ticks, 5);
+        assert_eq!(s.processes[&pid].get_state(), ProcessState::Terminated);
+    });
+}
+
+#[test_case]
+fn test_signal_group_honors_ignored() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let pid1 = s.create_process(LOOP_PROGRAM).unwrap();
+        let pid2 = s.create_process(LOOP_PROGRAM).unwrap();
+        let pid3 = s.create_process(LOOP_PROGRAM).unwrap();
+        for pid in [pid1, pid2] {
+            s.processes.get_mut(&pid).unwrap().pgid = pid1;
+        }
+        s.processes.get_mut(&pid2).unwrap().ignored_signals = 1 << SIGINT;
+
+        s.signal_group(pid1, SIGINT).unwrap();
+        assert_eq!(s.processes.get(&pid1).unwrap().get_state(), ProcessState::Terminated);
+        assert_eq!(s.processes.get(&pid2).unwrap().get_state(), ProcessState::Ready);
+        assert_eq!(s.processes.get(&pid3).unwrap().get_state(), ProcessState::Ready);
+
+        // SIGKILL cannot be ignored
+        s.send_signal(pid2, SIGKILL).unwrap();
+        assert_eq!(s.processes.get(&pid2).unwrap().get_state(), ProcessState::Terminated);
+        assert!(s.signal_group(pid1, SIGINT).is_err());
+        assert!(s.send_signal(0, SIGKILL).is_err());
+    });
+}
+
+#[test_case]
+fn test_process_limit() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let limit = Rlimit { cur: 2, max: 2 };
+        s.processes.get_mut(&0).unwrap().limits.set(RLIMIT_NPROC, limit).unwrap();
+
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+        s.create_process(LOOP_PROGRAM).unwrap();
+        assert_eq!(s.create_process(LOOP_PROGRAM), Err(Errno::EAGAIN));
+
+        // Exited processes no longer count
+        s.terminate_process(pid);
+        assert!(s.create_process(LOOP_PROGRAM).is_ok());
+    });
+}
+
+#[test_case]
+fn test_address_space_limit() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        // One code page and one stack page fit, nothing more
+        let limits = &mut s.processes.get_mut(&0).unwrap().limits;
+        limits.set(RLIMIT_AS, Rlimit { cur: 2 * 4096, max: 2 * 4096 }).unwrap();
+        assert!(s.create_process(LOOP_PROGRAM).is_ok());
+        assert_eq!(s.create_process(&[0x90; 4097]), Err(Errno::ENOMEM));
+    });
+}
+
+#[test_case]
+fn test_hard_limit_cannot_be_raised() {
+    let mut limits = ResourceLimits::new();
+    limits.set(RLIMIT_NOFILE, Rlimit { cur: 4, max: 8 }).unwrap();
+    assert_eq!(limits.max_files(), 4);
+    assert_eq!(limits.set(RLIMIT_NOFILE, Rlimit { cur: 9, max: 9 }), Err(Errno::EPERM));
+    assert_eq!(limits.set(RLIMIT_NOFILE, Rlimit { cur: 8, max: 4 }), Err(Errno::EINVAL));
+    assert!(limits.set(RLIMIT_NOFILE, Rlimit { cur: 8, max: 8 }).is_ok());
+}
+
+#[test_case]
+fn test_realtime_runs_before_normal() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let normal = s.create_process(LOOP_PROGRAM).unwrap();
+        let low = s.create_process(LOOP_PROGRAM).unwrap();
+        let high = s.create_process(LOOP_PROGRAM).unwrap();
+        s.set_scheduler(low, SchedPolicy::RoundRobin, 10).unwrap();
+        s.set_scheduler(high, SchedPolicy::Fifo, 20).unwrap();
+
+        assert_eq!(s.schedule(), Some(high));
+        // A FIFO process that yields with nothing of equal priority runs again
+        s.processes.get_mut(&high).unwrap().time = 0;
+        assert_eq!(s.schedule(), Some(high));
+
+        s.terminate_process(high);
+        assert_eq!(s.schedule(), Some(low));
+        s.terminate_process(low);
+        assert_eq!(s.schedule(), Some(normal));
+        assert_eq!(s.schedule(), Some(0));
+    });
+}
+
+#[test_case]
+fn test_realtime_preempts_on_tick() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let normal = s.create_process(LOOP_PROGRAM).unwrap();
+        assert_eq!(s.schedule(), Some(normal));
+        assert!(!s.tick_preempts(normal));
+
+        let realtime = s.create_process(LOOP_PROGRAM).unwrap();
+        s.set_scheduler(realtime, SchedPolicy::Fifo, 1).unwrap();
+        assert!(s.tick_preempts(normal));
+        assert_eq!(s.schedule(), Some(realtime));
+
+        // FIFO has no time slice
+        for _ in 0..2 * TIME_SLICE {
+            assert!(!s.tick_preempts(realtime));
+        }
+    });
+}
+
+#[test_case]
+fn test_realtime_throttling() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let realtime = s.create_process(LOOP_PROGRAM).unwrap();
+        s.set_scheduler(realtime, SchedPolicy::Fifo, 50).unwrap();
+        assert_eq!(s.schedule(), Some(realtime));
+
+        // Out of budget, the kernel process gets the CPU
+        s.rt_ticks = RT_RUNTIME;
+        assert!(s.tick_preempts(realtime));
+        assert_eq!(s.schedule(), Some(0));
+
+        // A new period lifts the throttle
+        s.ticks = s.rt_period_start + RT_PERIOD;
+        s.account_rt_tick();
+        assert!(!s.rt_throttled());
+        assert_eq!(s.schedule(), Some(realtime));
+    });
+}
+
+#[test_case]
+fn test_set_scheduler_limited_to_self_and_children() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let parent = s.create_process(LOOP_PROGRAM).unwrap();
+        let other = s.create_process(LOOP_PROGRAM).unwrap();
+        s.current_pid = Some(parent);
+        let child = s.create_process(LOOP_PROGRAM).unwrap();
+        s.current_pid = Some(0);
+
+        assert_eq!(s.check_scheduler_permission(parent, parent), Ok(()));
+        assert_eq!(s.check_scheduler_permission(parent, child), Ok(()));
+        assert_eq!(s.check_scheduler_permission(parent, other), Err(Errno::EPERM));
+        assert_eq!(s.check_scheduler_permission(child, parent), Err(Errno::EPERM));
+        assert_eq!(s.check_scheduler_permission(parent, 9999), Err(Errno::ESRCH));
+    });
+}
+
+#[test_case]
+fn test_set_scheduler_validates_priority() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+        assert_eq!(s.set_scheduler(pid, SchedPolicy::Fifo, 0), Err(Errno::EINVAL));
+        assert_eq!(s.set_scheduler(pid, SchedPolicy::RoundRobin, 100), Err(Errno::EINVAL));
+        assert_eq!(s.set_scheduler(pid, SchedPolicy::Normal, 5), Err(Errno::EINVAL));
+        assert_eq!(s.set_scheduler(0, SchedPolicy::Fifo, 5), Err(Errno::EPERM));
+    });
+}
+
+/// Reads `file` from the start to its end.
+fn read_to_string(file: &dyn vfs::File) -> String {
+    let mut text = vec![];
+    let mut buf = [0u8; 64];
+    loop {
+        let count = file.read(text.len(), &mut buf).unwrap();
+        if count == 0 {
+            break;
+        }
+        text.extend_from_slice(&buf[..count]);
+    }
+    String::from_utf8(text).unwrap()
+}
+
+#[test_case]
+fn test_proc_status_describes_process() {
+    let pid = with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        s.create_process(LOOP_PROGRAM).unwrap()
+    });
+
+    let status = read_to_string(&*vfs::open(&format!("/proc/{}/status", pid)).unwrap());
+    assert!(status.contains(&format!("Pid: {}\n", pid)));
+    assert!(status.contains("PPid: 0\n"));
+    assert!(status.contains("State: "));
+
+    let meminfo = read_to_string(&*vfs::open("/proc/meminfo").unwrap());
+    assert!(meminfo.starts_with("PhysTotal:"));
+    assert!(meminfo.contains("PhysUsed:"));
+}
+
+#[test_case]
+fn test_unknown_paths_fail_to_open() {
+    let pid = with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        s.create_process(LOOP_PROGRAM).unwrap()
+    });
+
+    assert_eq!(vfs::open("/no-such-file").err(), Some(Errno::ENOENT));
+    assert_eq!(vfs::open("/proc/no-such-file").err(), Some(Errno::ENOENT));
+    assert_eq!(vfs::open(&format!("/proc/{}/no-such-file", pid)).err(), Some(Errno::ENOENT));
+    assert_eq!(vfs::open(&format!("/proc/{}/status", pid + 1000)).err(), Some(Errno::ENOENT));
+}
+
+#[test_case]
+fn test_closed_descriptors_are_bad() {
+    let file = vfs::open("/proc/uptime").unwrap();
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+        let process = s.processes.get_mut(&pid).unwrap();
+        let max_files = process.limits.max_files();
+
+        let fd = process.files.insert(OpenFile::new(file), max_files).unwrap();
+        assert!(process.files.get(fd).is_ok());
+        assert!(process.files.remove(fd).is_ok());
+        assert_eq!(process.files.get(fd).err(), Some(Errno::EBADF));
+        assert_eq!(process.files.remove(fd).err(), Some(Errno::EBADF));
+    });
+}
+
+/// Physical address `addr` maps to in `pid`'s address space.
+fn translate(s: &ProcessManager, pid: u32, addr: VirtAddr) -> Option<PhysAddr> {
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let table = phys_mem_offset + s.processes[&pid].memory.page_table_addr.as_u64();
+    let table = unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), phys_mem_offset) };
+    table.translate_addr(addr)
+}
+
+/// The byte at `addr` in `pid`'s address space.
+fn read_user_byte(s: &ProcessManager, pid: u32, addr: VirtAddr) -> u8 {
+    let phys = translate(s, pid, addr).unwrap();
+    unsafe { *((memory::PHYS_MEM_OFFSET + phys.as_u64()) as *const u8) }
+}
+
+/// Whether `addr` is mapped by a 2 MiB page in `table`.
+fn is_huge(table: &OffsetPageTable, addr: VirtAddr) -> bool {
+    matches!(table.translate(addr), TranslateResult::Mapped { frame: MappedFrame::Size2MiB(_), .. })
+}
+
+/// A mapper over `pid`'s tables.
+fn process_table(s: &ProcessManager, pid: u32) -> OffsetPageTable<'static> {
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let table = phys_mem_offset + s.processes[&pid].memory.page_table_addr.as_u64();
+    unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), phys_mem_offset) }
+}
+
+#[test_case]
+fn test_processes_have_private_address_spaces() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        // Both spin like LOOP_PROGRAM, on a different byte
+        let first = s.create_process(&[0xEB, 0xFE, 0x11]).unwrap();
+        let second = s.create_process(&[0xEB, 0xFE, 0x22]).unwrap();
+
+        let code = VirtAddr::new(0x400000);
+        let first_frame = translate(s, first, code).unwrap();
+        let second_frame = translate(s, second, code).unwrap();
+        assert_ne!(first_frame, second_frame);
+
+        let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+        let read = |frame: PhysAddr| unsafe { *((phys_mem_offset + frame.as_u64() + 2) as *const u8) };
+        assert_eq!(read(first_frame), 0x11);
+        assert_eq!(read(second_frame), 0x22);
+
+        // The kernel's tables never see user pages
+        let kernel = memory::kernel_page_table().start_address().as_u64();
+        let kernel = unsafe { &mut *((phys_mem_offset + kernel) as *mut PageTable) };
+        let kernel = unsafe { OffsetPageTable::new(kernel, VirtAddr::new(phys_mem_offset)) };
+        assert_ne!(kernel.translate_addr(code), Some(first_frame));
+        assert_ne!(kernel.translate_addr(code), Some(second_frame));
+    });
+}
+
+#[test_case]
+fn test_kernel_half_is_not_user_accessible() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+        let table = phys_mem_offset + s.processes[&pid].memory.page_table_addr.as_u64();
+        let table = unsafe { &*(table as *const PageTable) };
+        for entry in table.iter().skip(256) {
+            assert!(!entry.flags().contains(PageTableFlags::USER_ACCESSIBLE));
+        }
+    });
+}
+
+#[test_case]
+fn test_kernel_stacks_have_guard_pages() {
+    let stack = kstack::allocate(77).unwrap();
+    let bottom = stack.top() - stack.size() as u64;
+    let kernel = unsafe { memory::kernel_mapper() };
+    assert!(kernel.translate_addr(stack.top() - 8u64).is_some());
+    assert!(kernel.translate_addr(bottom).is_some());
+    assert!(kernel.translate_addr(bottom - 8u64).is_none());
+    assert_eq!(kstack::overflowed(bottom - 8u64), Some(77));
+    assert_eq!(kstack::overflowed(bottom), None);
+    assert_eq!(kstack::overflowed(stack.top() - 8u64), None);
+    drop(stack);
+    assert_eq!(kstack::overflowed(bottom - 8u64), None);
+}
+
+#[test_case]
+fn test_freed_kernel_stacks_are_reused() {
+    let first = kstack::allocate(1).unwrap();
+    let top = first.top();
+    drop(first);
+    let second = kstack::allocate(2).unwrap();
+    assert_eq!(second.top(), top);
+    assert_eq!(kstack::overflowed(top - second.size() as u64 - 8u64), Some(2));
+}
+
+#[test_case]
+fn test_kernel_stack_size_is_configurable() {
+    assert_eq!(kstack::set_stack_size(1000), Err(Errno::EINVAL));
+    assert_eq!(kstack::set_stack_size(kstack::KSTACK_SLOT_SIZE), Err(Errno::EINVAL));
+
+    kstack::set_stack_size(16 * 1024).unwrap();
+    let stack = kstack::allocate(3);
+    kstack::set_stack_size(kstack::KSTACK_DEFAULT_SIZE).unwrap();
+    let stack = stack.unwrap();
+    assert_eq!(stack.size(), 16 * 1024);
+    let kernel = unsafe { memory::kernel_mapper() };
+    assert!(kernel.translate_addr(stack.top() - 16 * 1024u64).is_some());
+    assert!(kernel.translate_addr(stack.top() - 16 * 1024u64 - 8u64).is_none());
+}
+
+#[test_case]
+fn test_processes_get_distinct_pcids() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let first = s.create_process(LOOP_PROGRAM).unwrap();
+        let second = s.create_process(LOOP_PROGRAM).unwrap();
+        let first_pcid = s.processes[&first].memory.pcid;
+        let second_pcid = s.processes[&second].memory.pcid;
+        if pcid::enabled() {
+            assert_ne!(first_pcid, 0);
+            assert_ne!(first_pcid, second_pcid);
+        } else {
+            assert_eq!((first_pcid, second_pcid), (0, 0));
+        }
+
+        s.terminate_process(first);
+        assert_eq!(s.processes[&first].memory.pcid, 0);
+        let third = s.create_process(LOOP_PROGRAM).unwrap();
+        assert_eq!(s.processes[&third].memory.pcid, first_pcid);
+    });
+}
+
+#[test_case]
+fn test_process_layouts_are_randomized() {
+    // Even in a `no-aslr` build, which only changes the default
+    let enabled = aslr::enabled();
+    aslr::set_enabled(true);
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let first = s.create_pie_process(LOOP_PROGRAM).unwrap();
+        let second = s.create_pie_process(LOOP_PROGRAM).unwrap();
+
+        for pid in [first, second] {
+            let memory = &s.processes[&pid].memory;
+            assert!(memory.code_start().as_u64() >= aslr::PIE_BASE);
+            assert!(memory.stack_start().as_u64() <= aslr::STACK_TOP);
+            assert!(memory.heap_start() > memory.code_start());
+            assert!(translate(s, pid, memory.code_start()).is_some());
+            assert!(translate(s, pid, memory.stack_start() - 8u64).is_some());
+        }
+
+        // Each base has millions of choices, so two processes agreeing on
+        // all of them means nothing moved
+        let layout = |pid: u32| {
+            let memory = &s.processes[&pid].memory;
+            (memory.code_start(), memory.heap_start(), memory.stack_start(), memory.mmap_base)
+        };
+        assert_ne!(layout(first), layout(second));
+    });
+    aslr::set_enabled(enabled);
+}
+
+#[test_case]
+fn test_aslr_can_be_disabled() {
+    let enabled = aslr::enabled();
+    aslr::set_enabled(false);
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let fixed = s.create_process(LOOP_PROGRAM).unwrap();
+        let again = s.create_process(LOOP_PROGRAM).unwrap();
+        let pie = s.create_pie_process(LOOP_PROGRAM).unwrap();
+
+        let memory = &s.processes[&fixed].memory;
+        assert_eq!(memory.code_start(), VirtAddr::new(aslr::FIXED_CODE_BASE));
+        assert_eq!(memory.heap_start(), VirtAddr::new(aslr::FIXED_CODE_BASE + 4096));
+        assert_eq!(memory.stack_start(), VirtAddr::new(aslr::FIXED_STACK_TOP));
+        assert_eq!(memory.mmap_base, VirtAddr::new(shm::MMAP_BASE));
+        assert_eq!(s.processes[&again].memory.heap_start(), memory.heap_start());
+        assert_eq!(s.processes[&pie].memory.code_start(), VirtAddr::new(aslr::PIE_BASE));
+    });
+    aslr::set_enabled(enabled);
+}
+
+#[test_case]
+fn test_brk_grows_heap_from_its_base() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_pie_process(LOOP_PROGRAM).unwrap();
+        let heap = s.processes[&pid].memory.heap_start();
+
+        assert_eq!(s.brk(pid, 0), Ok(heap));
+        assert_eq!(s.brk(pid, heap.as_u64() - 1), Ok(heap));
+        assert_eq!(translate(s, pid, heap), None);
+
+        let grown = heap + 5000u64;
+        assert_eq!(s.brk(pid, grown.as_u64()), Ok(grown));
+        assert!(translate(s, pid, heap).is_some());
+        assert!(translate(s, pid, heap + 8191u64).is_some());
+        assert_eq!(translate(s, pid, heap + 8192u64), None);
+        assert_eq!(read_user_byte(s, pid, heap + 4999u64), 0);
+
+        // The heap can't grow into the stack
+        let stack = s.processes[&pid].memory.stack_start();
+        assert_eq!(s.brk(pid, stack.as_u64()), Ok(grown));
+
+        assert_eq!(s.brk(pid, heap.as_u64() + 1), Ok(heap + 1u64));
+        assert_eq!(translate(s, pid, heap + 4096u64), None);
+        assert_eq!(s.brk(pid, heap.as_u64()), Ok(heap));
+        assert_eq!(translate(s, pid, heap), None);
+    });
+}
+
+#[test_case]
+fn test_shared_memory_maps_same_frames() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let first = s.create_process(LOOP_PROGRAM).unwrap();
+        let second = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = shm::open("/test-shm", O_CREAT | O_EXCL).unwrap();
+        assert_eq!(shm::open("test-shm", O_CREAT | O_EXCL).err(), Some(Errno::EEXIST));
+        file.truncate(8192).unwrap();
+        assert_eq!(file.truncate(4096), Err(Errno::EINVAL));
+
+        let object = file.mmap().unwrap();
+        let first_addr = s.map_shared(first, object.clone(), 0, 8192, true).unwrap();
+        let second_addr = s.map_shared(second, object, 4096, 4096, false).unwrap();
+        assert!(translate(s, first, first_addr + 4096u64).is_some());
+        assert_eq!(translate(s, first, first_addr + 4096u64), translate(s, second, second_addr));
+    });
+}
+
+#[test_case]
+fn test_shared_memory_freed_after_last_unmap() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let first = s.create_process(LOOP_PROGRAM).unwrap();
+        let second = s.create_process(LOOP_PROGRAM).unwrap();
+
+        let file = shm::open("freed-shm", O_CREAT).unwrap();
+        file.truncate(4096).unwrap();
+        let first_addr = s.map_shared(first, file.mmap().unwrap(), 0, 4096, true).unwrap();
+        let second_addr = s.map_shared(second, file.mmap().unwrap(), 0, 4096, true).unwrap();
+        shm::unlink("freed-shm").unwrap();
+        drop(file);
+
+        let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+        s.unmap_shared(first, first_addr, 4096).unwrap();
+        assert_eq!(translate(s, first, first_addr), None);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated);
+
+        s.terminate_process(second);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated - 1);
+        assert_eq!(translate(s, second, second_addr), None);
+    });
+}
+
+#[test_case]
+fn test_messages_are_delivered_in_order() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let sender = s.create_process(LOOP_PROGRAM).unwrap();
+        let receiver = s.create_process(LOOP_PROGRAM).unwrap();
+
+        s.send_message(sender, receiver, vec![1, 2, 3]).unwrap();
+        s.send_message(sender, receiver, vec![4]).unwrap();
+        assert_eq!(s.receive_message(receiver, 2, true).err(), Some(Errno::EMSGSIZE));
+
+        let first = s.receive_message(receiver, 16, true).unwrap();
+        assert_eq!((first.sender, first.data.as_slice()), (sender, &[1, 2, 3][..]));
+        assert_eq!(s.receive_message(receiver, 16, true).unwrap().data, vec![4]);
+        assert_eq!(s.receive_message(receiver, 16, true).err(), Some(Errno::EAGAIN));
+    });
+}
+
+#[test_case]
+fn test_message_limits() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        assert_eq!(s.send_message(0, pid, vec![0; MSG_MAX_SIZE + 1]), Err(Errno::EMSGSIZE));
+        for _ in 0..MAILBOX_CAPACITY {
+            s.send_message(0, pid, vec![0]).unwrap();
+        }
+        assert_eq!(s.send_message(0, pid, vec![0]), Err(Errno::EAGAIN));
+        assert_eq!(s.send_message(0, 999, vec![0]), Err(Errno::ESRCH));
+    });
+}
+
+#[test_case]
+fn test_blocked_receiver_woken_by_send() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        assert_eq!(s.receive_message(pid, 16, false).err(), Some(Errno::ERESTART));
+        assert_eq!(s.processes[&pid].get_state(), ProcessState::Waiting);
+        s.send_message(0, pid, vec![7]).unwrap();
+        assert_eq!(s.processes[&pid].get_state(), ProcessState::Ready);
+        assert_eq!(s.receive_message(pid, 16, false).unwrap().data, vec![7]);
+    });
+}
+
+#[test_case]
+fn test_ports_are_released_on_exit() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let server = s.create_process(LOOP_PROGRAM).unwrap();
+
+        s.register_port("mixer", server).unwrap();
+        assert_eq!(s.register_port("mixer", server), Err(Errno::EEXIST));
+        assert_eq!(s.lookup_port("mixer"), Ok(server));
+
+        s.terminate_process(server);
+        assert_eq!(s.lookup_port("mixer"), Err(Errno::ENOENT));
+    });
+}
+
+#[test_case]
+fn test_large_anonymous_mappings_use_huge_pages() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        // Two huge pages and a small one after them
+        let length = 4 * 1024 * 1024 + 4096;
+        let huge_pages = anon::huge_pages();
+        let start = s.map_anonymous(pid, length, true).unwrap();
+        assert!(start.is_aligned(2 * 1024 * 1024u64));
+        let table = process_table(s, pid);
+        assert!(is_huge(&table, start));
+        assert!(is_huge(&table, start + 2 * 1024 * 1024u64 + 8u64));
+        assert!(!is_huge(&table, start + 4 * 1024 * 1024u64));
+        assert_eq!(anon::huge_pages(), huge_pages + 2);
+
+        let phys_mem_offset = unsafe { memory::PHYS_MEM_OFFSET };
+        let phys = translate(s, pid, start + 12345u64).unwrap();
+        assert_eq!(unsafe { *((phys_mem_offset + phys.as_u64()) as *const u8) }, 0);
+
+        let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+        assert_eq!(s.unmap_anonymous(pid, start, 4096), Err(Errno::EINVAL));
+        s.unmap_anonymous(pid, start, length).unwrap();
+        assert_eq!(translate(s, pid, start), None);
+        assert_eq!(anon::huge_pages(), huge_pages);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated - 2 * memory::HUGE_PAGE_FRAMES - 1);
+    });
+}
+
+#[test_case]
+fn test_small_anonymous_mappings_use_small_pages() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+        let pid = s.create_process(LOOP_PROGRAM).unwrap();
+
+        assert_eq!(s.map_anonymous(pid, usize::MAX - 4096, false), Err(Errno::ENOMEM));
+        let start = s.map_anonymous(pid, 3 * 4096, false).unwrap();
+        let table = process_table(s, pid);
+        assert!(!is_huge(&table, start));
+        assert!(translate(s, pid, start + 2 * 4096u64).is_some());
+        assert_eq!(translate(s, pid, start + 3 * 4096u64), None);
+
+        // Exiting frees them
+        let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+        s.terminate_process(pid);
+        assert_eq!(memory::FRAME_ALLOCATOR.lock().allocated_frames(), allocated - 3);
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
@@ -0,0 +1,119 @@
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(game_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use bootloader::{entry_point, BootInfo};
+use core::panic::PanicInfo;
+use game_os::mem::allocator;
+use game_os::mem::memory::{self, BootInfoFrameAllocator};
+use game_os::mem::vmalloc::{self, VmallocBuffer};
+use game_os::proc::errno::Errno;
+use game_os::proc::scheduler::{ProcessManager, SCHEDULER};
+use x86_64::structures::paging::{OffsetPageTable, PageTable, Translate};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+/// `jmp $`, a user program that spins forever.
+const LOOP_PROGRAM: &[u8] = &[0xEB, 0xFE];
+
+fn main(boot_info: &'static BootInfo) -> ! {
+    game_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
+    unsafe { memory::PHYS_MEM_OFFSET = phys_mem_offset.as_u64() };
+    memory::FRAME_ALLOCATOR.init(frame_allocator);
+    allocator::init_heap(&mut mapper, &mut *memory::FRAME_ALLOCATOR.lock())
+        .expect("heap initialization failed");
+
+    test_main();
+    loop {}
+}
+
+fn with_scheduler<F, R>(f: F) -> R
+where
+    F: FnOnce(&mut ProcessManager) -> R,
+{
+    f(&mut SCHEDULER.lock())
+}
+
+/// Physical address `addr` maps to in `pid`'s address space.
+fn translate(s: &ProcessManager, pid: u32, addr: VirtAddr) -> Option<PhysAddr> {
+    let phys_mem_offset = VirtAddr::new(unsafe { memory::PHYS_MEM_OFFSET });
+    let table = phys_mem_offset + s.processes[&pid].memory.page_table_addr.as_u64();
+    let table = unsafe { OffsetPageTable::new(&mut *table.as_mut_ptr::<PageTable>(), phys_mem_offset) };
+    table.translate_addr(addr)
+}
+
+#[test_case]
+fn test_vmalloc_maps_scattered_frames() {
+    let allocated = memory::FRAME_ALLOCATOR.lock().allocated_frames();
+    let length = 3 * 1024 * 1024;
+    let addr = vmalloc::vmalloc(length).unwrap();
+
+    let buffer = unsafe { core::slice::from_raw_parts_mut(addr.as_mut_ptr::<u8>(), length) };
+    assert!(buffer.iter().all(|&byte| byte == 0));
+    buffer.fill(0xA5);
+    assert!(buffer.iter().all(|&byte| byte == 0xA5));
+    assert_eq!(vmalloc::stats().used, length);
+
+    // Followed by a guard page
+    let kernel = unsafe { memory::kernel_mapper() };
+    assert!(kernel.translate_addr(addr + length as u64 - 1u64).is_some());
+    assert!(kernel.translate_addr(addr + length as u64).is_none());
+
+    vmalloc::vfree(addr).unwrap();
+    assert!(kernel.translate_addr(addr).is_none());
+    assert_eq!(vmalloc::vfree(addr), Err(Errno::EINVAL));
+    assert_eq!(vmalloc::stats().allocations, 0);
+    // Page tables made for the mapping stay
+    assert!(memory::FRAME_ALLOCATOR.lock().allocated_frames() - allocated < 8);
+
+    // The range is handed out again
+    assert_eq!(vmalloc::vmalloc(4096), Ok(addr));
+    vmalloc::vfree(addr).unwrap();
+}
+
+#[test_case]
+fn test_vmalloc_is_shared_by_address_spaces() {
+    with_scheduler(|s| {
+        s.reset();
+        s.init_kernel_process();
+
+        // Address spaces made after the first allocation see it
+        let mut buffer = VmallocBuffer::new(8192).unwrap();
+        buffer[4096] = 7;
+        let before = s.create_process(LOOP_PROGRAM).unwrap();
+        let kernel = unsafe { memory::kernel_mapper() };
+        let phys = kernel.translate_addr(buffer.addr() + 4096u64);
+        assert!(phys.is_some());
+        assert_eq!(translate(s, before, buffer.addr() + 4096u64), phys);
+
+        let addr = buffer.addr();
+        drop(buffer);
+        assert_eq!(translate(s, before, addr), None);
+
+        // And so do those made after the range is freed and handed out again
+        let buffer = VmallocBuffer::new(4096).unwrap();
+        assert_eq!(buffer.addr(), addr);
+        let after = s.create_process(LOOP_PROGRAM).unwrap();
+        let phys = kernel.translate_addr(addr);
+        assert!(phys.is_some());
+        assert_eq!(translate(s, before, addr), phys);
+        assert_eq!(translate(s, after, addr), phys);
+
+        drop(buffer);
+        assert_eq!(translate(s, after, addr), None);
+        s.reset();
+    });
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    game_os::test_panic_handler(info)
+}
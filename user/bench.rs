@@ -0,0 +1,111 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+const ITERATIONS: u64 = 10_000;
+
+const SYS_WRITE: u64 = 1;
+const SYS_GETPID: u64 = 39;
+const SYS_EXIT: u64 = 60;
+
+const STDOUT: u64 = 1;
+
+fn syscall(number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rax") number,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            lateout("rax") result,
+            out("rcx") _,
+            out("r11") _,
+        );
+    }
+    result
+}
+
+fn int80(number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        core::arch::asm!(
+            "int 0x80",
+            in("rax") number,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            lateout("rax") result,
+        );
+    }
+    result
+}
+
+fn rdtsc() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        core::arch::asm!("rdtsc", out("eax") low, out("edx") high);
+    }
+    ((high as u64) << 32) | low as u64
+}
+
+fn write(buf: &[u8]) {
+    syscall(SYS_WRITE, STDOUT, buf.as_ptr() as u64, buf.len() as u64);
+}
+
+fn write_u64(mut value: u64) {
+    let mut digits = [0u8; 20];
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    write(&digits[i..]);
+}
+
+fn exit() -> ! {
+    syscall(SYS_EXIT, 0, 0, 0);
+    loop {}
+}
+
+/// Average cycles per call of `getpid` through the given entry path.
+fn measure(call: fn(u64, u64, u64, u64) -> u64) -> u64 {
+    // Warm up caches and the TLB before timing.
+    for _ in 0..100 {
+        call(SYS_GETPID, 0, 0, 0);
+    }
+
+    let start = rdtsc();
+    for _ in 0..ITERATIONS {
+        call(SYS_GETPID, 0, 0, 0);
+    }
+    (rdtsc() - start) / ITERATIONS
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    let slow = measure(int80);
+    let fast = measure(syscall);
+
+    write(b"getpid via int 0x80: ");
+    write_u64(slow);
+    write(b" cycles\n");
+
+    write(b"getpid via syscall:  ");
+    write_u64(fast);
+    write(b" cycles\n");
+
+    exit();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    exit();
+}
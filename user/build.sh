@@ -1,10 +1,12 @@
-rustc \
-    --edition 2021 \
-    --target x86_64-unknown-none \
-    -C linker=rust-lld \
-    -C link-arg=-T -C link-arg=user/linker.ld \
-    -C link-arg=--oformat=binary \
-    -C relocation-model=static \
-    -C panic=abort \
-    user/shell.rs \
-    -o user/shell.bin
\ No newline at end of file
+for program in shell bench wc; do
+    rustc \
+        --edition 2021 \
+        --target x86_64-unknown-none \
+        -C linker=rust-lld \
+        -C link-arg=-T -C link-arg=user/linker.ld \
+        -C link-arg=--oformat=binary \
+        -C relocation-model=static \
+        -C panic=abort \
+        user/$program.rs \
+        -o user/$program.bin
+done
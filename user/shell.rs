@@ -3,45 +3,229 @@
 
 use core::panic::PanicInfo;
 
-fn syscall(number: u64, arg1: u64, arg2: u64) -> u64 {
+const MAX_PROCESSES: usize = 16;
+const MAX_JOBS: usize = 8;
+const MAX_STAGES: usize = 4;
+const JOB_NAME_LEN: usize = 16;
+
+const STDIN: u64 = 0;
+const STDOUT: u64 = 1;
+const IDLE_PID: u32 = u32::MAX - 1;
+
+const SIGINT: u64 = 2;
+const SIGKILL: u64 = 9;
+const SIGCONT: u64 = 18;
+const SIGTSTP: u64 = 20;
+const SIGTTIN: u64 = 21;
+const SIG_IGN: u64 = 1;
+
+const ENOENT: i64 = 2;
+const EINVAL: i64 = 22;
+
+/// `wait` results.
+const WAIT_DONE: u64 = 0;
+const WAIT_RUNNING: u64 = 1;
+const WAIT_STOPPED: u64 = 2;
+
+/// Mirrors the kernel's `ProcessInfo`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ProcessInfo {
+    pid: u32,
+    parent_pid: u32,
+    state: u32,
+    priority: u32,
+    pgid: u32,
+    sid: u32,
+    policy: u32,
+    rt_priority: u32,
+    user_ticks: u64,
+    system_ticks: u64,
+    voluntary_switches: u64,
+    involuntary_switches: u64,
+    page_faults: u64,
+}
+
+/// A background or stopped command: the processes of a pipeline, all in
+/// the process group led by the first.
+#[derive(Clone, Copy)]
+struct Job {
+    pids: [u32; MAX_STAGES],
+    stages: usize,
+    name: [u8; JOB_NAME_LEN],
+    name_len: usize,
+    stopped: bool,
+}
+
+impl Job {
+    fn new(name: &[u8]) -> Self {
+        let mut job = Job {
+            pids: [0; MAX_STAGES],
+            stages: 0,
+            name: [0; JOB_NAME_LEN],
+            name_len: name.len().min(JOB_NAME_LEN),
+            stopped: false,
+        };
+        job.name[..job.name_len].copy_from_slice(&name[..job.name_len]);
+        job
+    }
+
+    fn name(&self) -> &[u8] {
+        &self.name[..self.name_len]
+    }
+
+    fn pgid(&self) -> u64 {
+        self.pids[0] as u64
+    }
+
+    /// `wait` for the whole pipeline: done once every stage is, stopped as
+    /// soon as one is.
+    fn status(&self) -> u64 {
+        let mut status = WAIT_DONE;
+        for &pid in &self.pids[..self.stages] {
+            match wait(pid as u64) {
+                WAIT_DONE => {}
+                WAIT_STOPPED => return WAIT_STOPPED,
+                _ => status = WAIT_RUNNING,
+            }
+        }
+        status
+    }
+}
+
+const EMPTY_INFO: ProcessInfo = ProcessInfo {
+    pid: 0,
+    parent_pid: 0,
+    state: 0,
+    priority: 0,
+    pgid: 0,
+    sid: 0,
+    policy: 0,
+    rt_priority: 0,
+    user_ticks: 0,
+    system_ticks: 0,
+    voluntary_switches: 0,
+    involuntary_switches: 0,
+    page_faults: 0,
+};
+
+fn syscall(number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
     let result: u64;
     unsafe {
         core::arch::asm!(
-            "int 0x80",
+            "syscall",
             in("rax") number,
             in("rdi") arg1,
             in("rsi") arg2,
+            in("rdx") arg3,
             lateout("rax") result,
+            out("rcx") _,
+            out("r11") _,
         );
     }
     result
 }
 
 fn write(buf: &[u8]) {
-    syscall(1, buf.as_ptr() as u64, buf.len() as u64);
+    write_fd(STDOUT, buf);
+}
+
+fn write_fd(fd: u64, buf: &[u8]) -> u64 {
+    syscall(1, fd, buf.as_ptr() as u64, buf.len() as u64)
 }
 
 fn read(buf: &mut [u8]) -> u64 {
-    syscall(0, buf.as_mut_ptr() as u64, buf.len() as u64)
+    read_fd(STDIN, buf)
+}
+
+fn read_fd(fd: u64, buf: &mut [u8]) -> u64 {
+    syscall(0, fd, buf.as_mut_ptr() as u64, buf.len() as u64)
+}
+
+fn open(path: &[u8]) -> u64 {
+    syscall(2, path.as_ptr() as u64, path.len() as u64, 0)
 }
 
-fn spawn(name: &[u8]) -> u64 {
-    syscall(3, name.as_ptr() as u64, name.len() as u64)
+fn close(fd: u64) {
+    syscall(6, fd, 0, 0);
+}
+
+/// Starts `name` with the given descriptors as its stdin, stdout and
+/// stderr, -1 leaving the console in place.
+fn spawn(name: &[u8], stdio: &[i32; 3]) -> u64 {
+    syscall(3, name.as_ptr() as u64, name.len() as u64, stdio.as_ptr() as u64)
+}
+
+/// `struct pollfd`.
+#[repr(C)]
+struct PollFd {
+    fd: i32,
+    events: i16,
+    revents: i16,
+}
+
+const POLLIN: i16 = 0x01;
+
+/// Sleeps until there is console input instead of spinning on `read`.
+fn wait_for_input() {
+    let mut pollfd = PollFd { fd: STDIN as i32, events: POLLIN, revents: 0 };
+    syscall(7, &mut pollfd as *mut PollFd as u64, 1, -1i64 as u64);
+}
+
+fn pipe(fds: &mut [u32; 2]) -> u64 {
+    syscall(22, fds.as_mut_ptr() as u64, 0, 0)
 }
 
 fn wait(pid: u64) -> u64 {
-    syscall(4, pid, 0)
+    syscall(4, pid, 0, 0)
 }
 
 fn sys_yield() {
-    syscall(5, 0, 0);
+    syscall(5, 0, 0, 0);
+}
+
+fn times() -> u64 {
+    syscall(100, 0, 0, 0)
+}
+
+fn process_info(buf: &mut [ProcessInfo]) -> usize {
+    syscall(500, buf.as_mut_ptr() as u64, buf.len() as u64, 0) as usize
+}
+
+fn kill(pid: i64, signal: u64) -> u64 {
+    syscall(62, pid as u64, signal, 0)
+}
+
+fn setpgid(pid: u64, pgid: u64) -> u64 {
+    syscall(109, pid, pgid, 0)
+}
+
+fn setsid() -> u64 {
+    syscall(112, 0, 0, 0)
+}
+
+fn getpgid() -> u64 {
+    syscall(121, 0, 0, 0)
+}
+
+fn ignore_signal(signal: u64) {
+    syscall(13, signal, SIG_IGN, 0);
+}
+
+fn tcsetpgrp(pgid: u64) -> u64 {
+    syscall(501, pgid, 0, 0)
 }
 
 fn exit() -> ! {
-    syscall(60, 0, 0);
+    syscall(60, 0, 0, 0);
     loop {}
 }
 
+/// Negative return values are errors.
+fn is_error(value: u64) -> bool {
+    (value as i64) < 0
+}
+
 fn str_eq(a: &[u8], b: &[u8]) -> bool {
     if a.len() != b.len() {
         return false;
@@ -56,11 +240,282 @@ fn str_eq(a: &[u8], b: &[u8]) -> bool {
     true
 }
 
+fn write_u64(mut value: u64, width: usize) {
+    let mut digits = [b' '; 20];
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    let start = if digits.len() - i < width { digits.len() - width.min(digits.len()) } else { i };
+    write(&digits[start..]);
+}
+
+fn state_name(state: u32) -> &'static [u8] {
+    match state {
+        0 => b"ready   ",
+        1 => b"running ",
+        2 => b"waiting ",
+        3 => b"zombie  ",
+        4 => b"stopped ",
+        _ => b"?       ",
+    }
+}
+
+fn policy_name(policy: u32) -> &'static [u8] {
+    match policy {
+        0 => b"  TS",
+        1 => b"  FF",
+        2 => b"  RR",
+        _ => b"   ?",
+    }
+}
+
+fn print_process_table(infos: &[ProcessInfo], elapsed: u64) {
+    write(b"  PID  PPID  PGID CLS PRI STATE       USER    SYS  %CPU  VCSW  ICSW  FAULTS\n");
+    for info in infos {
+        if info.pid == IDLE_PID {
+            write(b" idle");
+        } else {
+            write_u64(info.pid as u64, 5);
+        }
+        write_u64(info.parent_pid as u64, 6);
+        write_u64(info.pgid as u64, 6);
+        write(policy_name(info.policy));
+        write_u64(info.rt_priority as u64, 4);
+        write(b" ");
+        write(state_name(info.state));
+        write_u64(info.user_ticks, 7);
+        write_u64(info.system_ticks, 7);
+        write_u64((info.user_ticks + info.system_ticks) * 100 / elapsed.max(1), 6);
+        write_u64(info.voluntary_switches, 6);
+        write_u64(info.involuntary_switches, 6);
+        write_u64(info.page_faults, 8);
+        write(b"\n");
+    }
+}
+
+fn ps() {
+    let mut infos = [EMPTY_INFO; MAX_PROCESSES];
+    let count = process_info(&mut infos);
+    print_process_table(&infos[..count], times());
+}
+
+/// Like `ps`, but busiest processes first with a system-wide usage line.
+fn top() {
+    let mut infos = [EMPTY_INFO; MAX_PROCESSES];
+    let count = process_info(&mut infos);
+    let infos = &mut infos[..count];
+    let elapsed = times();
+
+    let mut idle_ticks = 0;
+    for info in infos.iter() {
+        if info.pid == IDLE_PID {
+            idle_ticks = info.system_ticks;
+        }
+    }
+
+    // Insertion sort by total ticks, descending
+    let mut i = 1;
+    while i < infos.len() {
+        let mut j = i;
+        while j > 0 && total_ticks(&infos[j]) > total_ticks(&infos[j - 1]) {
+            infos.swap(j, j - 1);
+            j -= 1;
+        }
+        i += 1;
+    }
+
+    write(b"CPU usage: ");
+    write_u64(elapsed.saturating_sub(idle_ticks) * 100 / elapsed.max(1), 0);
+    write(b"%  uptime ticks: ");
+    write_u64(elapsed, 0);
+    write(b"\n");
+    print_process_table(infos, elapsed);
+}
+
+fn total_ticks(info: &ProcessInfo) -> u64 {
+    info.user_ticks + info.system_ticks
+}
+
+/// Prints a file, e.g. `cat /proc/meminfo`.
+fn cat(path: &[u8]) {
+    let fd = open(path);
+    if is_error(fd) {
+        write(b"cat: no such file\n");
+        return;
+    }
+
+    let mut buffer = [0u8; 128];
+    loop {
+        let count = read_fd(fd, &mut buffer);
+        if count == 0 || is_error(count) {
+            break;
+        }
+        write(&buffer[..count as usize]);
+    }
+    close(fd);
+}
+
+fn add_job(jobs: &mut [Option<Job>; MAX_JOBS], job: Job) -> Option<usize> {
+    let slot = jobs.iter().position(|job| job.is_none())?;
+    jobs[slot] = Some(job);
+    Some(slot)
+}
+
+fn print_job(number: usize, job: &Job, status: &[u8]) {
+    write(b"[");
+    write_u64(number as u64 + 1, 0);
+    write(b"] ");
+    write(status);
+    write(b" ");
+    write(job.name());
+    write(b"\n");
+}
+
+/// Forgets jobs that have finished, reporting each one.
+fn reap_jobs(jobs: &mut [Option<Job>; MAX_JOBS]) {
+    for (number, slot) in jobs.iter_mut().enumerate() {
+        if let Some(job) = slot {
+            if job.status() == WAIT_DONE {
+                print_job(number, job, b"Done   ");
+                *slot = None;
+            }
+        }
+    }
+}
+
+fn list_jobs(jobs: &[Option<Job>; MAX_JOBS]) {
+    for (number, slot) in jobs.iter().enumerate() {
+        if let Some(job) = slot {
+            print_job(number, job, if job.stopped { b"Stopped" } else { b"Running" });
+        }
+    }
+}
+
+/// Picks the job named by `fg`/`bg`'s argument, or the newest one.
+fn select_job(jobs: &[Option<Job>; MAX_JOBS], arg: &[u8]) -> Option<usize> {
+    if arg.is_empty() {
+        return jobs.iter().rposition(|job| job.is_some());
+    }
+    let mut number = 0usize;
+    for &digit in arg {
+        if !digit.is_ascii_digit() {
+            return None;
+        }
+        number = number * 10 + (digit - b'0') as usize;
+    }
+    if number == 0 || number > MAX_JOBS || jobs[number - 1].is_none() {
+        return None;
+    }
+    Some(number - 1)
+}
+
+/// Hands the console to `job` and waits until it exits or stops. A stopped
+/// job goes (back) into the job table.
+fn run_foreground(jobs: &mut [Option<Job>; MAX_JOBS], job: Job, shell_pgid: u64) {
+    tcsetpgrp(job.pgid());
+    if job.stopped {
+        kill(-(job.pgid() as i64), SIGCONT);
+    }
+
+    loop {
+        match job.status() {
+            WAIT_DONE => break,
+            WAIT_STOPPED => {
+                write(b"\n");
+                let stopped = Job { stopped: true, ..job };
+                match add_job(jobs, stopped) {
+                    Some(number) => print_job(number, &stopped, b"Stopped"),
+                    None => write(b"jobs: table full\n"),
+                }
+                break;
+            }
+            _ => sys_yield(),
+        }
+    }
+
+    tcsetpgrp(shell_pgid);
+}
+
+fn trim_end(mut command: &[u8]) -> &[u8] {
+    while let [rest @ .., b' '] = command {
+        command = rest;
+    }
+    command
+}
+
+/// Starts the `|`-separated stages of `command`, each one's stdout feeding
+/// the next one's stdin. Returns the job, or the error of the stage that
+/// failed to start, after killing the ones already running.
+fn spawn_pipeline(command: &[u8]) -> Result<Job, u64> {
+    let stages = command.split(|&byte| byte == b'|').count();
+    if stages > MAX_STAGES {
+        return Err(-EINVAL as u64);
+    }
+
+    let mut job = Job::new(command);
+    // Read end of the previous stage's pipe
+    let mut input = -1;
+
+    for (index, stage) in command.split(|&byte| byte == b'|').enumerate() {
+        let stage = trim_end(stage.trim_ascii_start());
+        let last = index + 1 == stages;
+
+        let mut fds = [0u32; 2];
+        let mut result = if last { 0 } else { pipe(&mut fds) };
+        let output = if last || is_error(result) { -1 } else { fds[1] as i32 };
+        if !is_error(result) {
+            result = spawn(stage, &[input, output, -1]);
+        }
+
+        // The child has its own copies of these now
+        if input >= 0 {
+            close(input as u64);
+        }
+        if output >= 0 {
+            close(output as u64);
+        }
+
+        if is_error(result) {
+            if output >= 0 {
+                close(fds[0] as u64);
+            }
+            if job.stages > 0 {
+                kill(-(job.pgid() as i64), SIGKILL);
+            }
+            return Err(result);
+        }
+
+        job.pids[index] = result as u32;
+        job.stages += 1;
+        setpgid(result, job.pgid());
+        input = if last { -1 } else { fds[0] as i32 };
+    }
+
+    Ok(job)
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn _start() -> ! {
     write(b"GameOS Shell\n");
 
+    // Lead our own session so we own the console, and leave the job control
+    // signals to the jobs
+    setsid();
+    let shell_pgid = getpgid();
+    ignore_signal(SIGINT);
+    ignore_signal(SIGTSTP);
+    ignore_signal(SIGTTIN);
+
+    let mut jobs: [Option<Job>; MAX_JOBS] = [None; MAX_JOBS];
+
     loop {
+        reap_jobs(&mut jobs);
         write(b"> ");
 
         // Read command
@@ -71,8 +526,9 @@ pub extern "C" fn _start() -> ! {
             let mut character = [0u8; 1];
             let count = read(&mut character);
 
-            // Nothing read, try again
+            // Nothing read, try again once something was typed
             if count == 0 {
+                wait_for_input();
                 continue;
             }
 
@@ -103,8 +559,75 @@ pub extern "C" fn _start() -> ! {
 
         let command = &buffer[..len];
 
-        match command {
-            _ => write(b"No commnands")
+        if str_eq(command, b"ps") {
+            ps();
+            continue;
+        }
+
+        if str_eq(command, b"top") {
+            top();
+            continue;
+        }
+
+        if len > 4 && str_eq(&command[..4], b"cat ") {
+            cat(&command[4..]);
+            continue;
+        }
+
+        if str_eq(command, b"jobs") {
+            list_jobs(&jobs);
+            continue;
+        }
+
+        if command.starts_with(b"fg") || command.starts_with(b"bg") {
+            let arg = trim_end(command[2..].trim_ascii_start());
+            if command.len() == 2 || command[2] == b' ' {
+                let Some(number) = select_job(&jobs, arg) else {
+                    write(b"no such job\n");
+                    continue;
+                };
+                let job = jobs[number].unwrap();
+                if command[0] == b'f' {
+                    jobs[number] = None;
+                    run_foreground(&mut jobs, job, shell_pgid);
+                } else {
+                    kill(-(job.pgid() as i64), SIGCONT);
+                    jobs[number] = Some(Job { stopped: false, ..job });
+                    print_job(number, &job, b"Running");
+                }
+                continue;
+            }
+        }
+
+        // `command &` runs in the background
+        let background = command.last() == Some(&b'&');
+        let command = if background { trim_end(&command[..command.len() - 1]) } else { command };
+
+        let job = match spawn_pipeline(command) {
+            Ok(job) => job,
+            Err(err) => {
+                if err as i64 == -ENOENT {
+                    write(b"Unknown command\n");
+                } else {
+                    write(b"Cannot start process\n");
+                }
+                continue;
+            }
+        };
+
+        if background {
+            match add_job(&mut jobs, job) {
+                Some(number) => {
+                    write(b"[");
+                    write_u64(number as u64 + 1, 0);
+                    write(b"] ");
+                    write_u64(job.pgid(), 0);
+                    write(b"\n");
+                }
+                None => write(b"jobs: table full\n"),
+            }
+        } else {
+            run_foreground(&mut jobs, job, shell_pgid);
         }
 
     }
@@ -0,0 +1,101 @@
+#![no_std]
+#![no_main]
+
+use core::panic::PanicInfo;
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_EXIT: u64 = 60;
+
+const STDIN: u64 = 0;
+const STDOUT: u64 = 1;
+
+fn syscall(number: u64, arg1: u64, arg2: u64, arg3: u64) -> u64 {
+    let result: u64;
+    unsafe {
+        core::arch::asm!(
+            "syscall",
+            in("rax") number,
+            in("rdi") arg1,
+            in("rsi") arg2,
+            in("rdx") arg3,
+            lateout("rax") result,
+            out("rcx") _,
+            out("r11") _,
+        );
+    }
+    result
+}
+
+fn read(buf: &mut [u8]) -> u64 {
+    syscall(SYS_READ, STDIN, buf.as_mut_ptr() as u64, buf.len() as u64)
+}
+
+fn write(buf: &[u8]) {
+    syscall(SYS_WRITE, STDOUT, buf.as_ptr() as u64, buf.len() as u64);
+}
+
+fn write_u64(mut value: u64) {
+    let mut digits = [0u8; 20];
+    let mut i = digits.len();
+    loop {
+        i -= 1;
+        digits[i] = b'0' + (value % 10) as u8;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+    write(&digits[i..]);
+}
+
+fn exit() -> ! {
+    syscall(SYS_EXIT, 0, 0, 0);
+    loop {}
+}
+
+/// Counts the lines, words and bytes of stdin, e.g. the output of the
+/// previous stage of a pipeline, until end of file.
+#[unsafe(no_mangle)]
+pub extern "C" fn _start() -> ! {
+    let mut lines = 0;
+    let mut words = 0;
+    let mut bytes = 0;
+    let mut in_word = false;
+
+    let mut buffer = [0u8; 256];
+    loop {
+        let count = read(&mut buffer);
+        // End of file, or an error reported as a negative count
+        if count == 0 || (count as i64) < 0 {
+            break;
+        }
+
+        for &byte in &buffer[..count as usize] {
+            bytes += 1;
+            if byte == b'\n' {
+                lines += 1;
+            }
+            if byte.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                words += 1;
+            }
+        }
+    }
+
+    write_u64(lines);
+    write(b" ");
+    write_u64(words);
+    write(b" ");
+    write_u64(bytes);
+    write(b"\n");
+
+    exit();
+}
+
+#[panic_handler]
+fn panic(_info: &PanicInfo) -> ! {
+    exit();
+}